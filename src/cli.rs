@@ -1,6 +1,10 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::config::LogFormat;
+use crate::config_store::FileFormat;
+use crate::deps::GraphFormat;
+
 #[derive(Parser)]
 #[command(name = "nssm-rs")]
 #[command(about = "A Rust implementation of NSSM - the Non-Sucking Service Manager")]
@@ -16,6 +20,24 @@ pub struct Cli {
     /// Enable debug output
     #[arg(short, long)]
     pub debug: bool,
+
+    /// Keep service configuration as files in this directory instead of
+    /// the registry
+    #[arg(long, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+
+    /// File format used with --config-dir
+    #[arg(long, value_enum, default_value_t = FileFormat::Json, requires = "config_dir")]
+    pub config_format: FileFormat,
+
+    /// Runtime log line format; overrides a service's AppLogFormat
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Directory for runtime logs of services without an AppLogDirectory;
+    /// services installed with it keep using it
+    #[arg(long, value_name = "DIR", env = "NSSM_RS_LOG_DIR")]
+    pub log_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -38,15 +60,17 @@ pub enum Commands {
         #[arg(short, long, action)]
         confirm: bool,
     },
-    /// Start a service
+    /// Start services, each after the services it depends on
     Start {
-        /// Service name
-        service_name: String,
+        /// Service names
+        #[arg(required = true)]
+        service_names: Vec<String>,
     },
-    /// Stop a service
+    /// Stop services, each before the services it depends on
     Stop {
-        /// Service name
-        service_name: String,
+        /// Service names
+        #[arg(required = true)]
+        service_names: Vec<String>,
     },
     /// Restart a service
     Restart {
@@ -59,9 +83,10 @@ pub enum Commands {
         service_name: String,
         /// Parameter name
         parameter: String,
-        /// Parameter value. AppEnvironmentExtra accepts multiple KEY=VALUE
-        /// entries; AppParameters accepts multiple arguments which are
-        /// quoted and joined.
+        /// Parameter value. AppEnvironment and AppEnvironmentExtra accept
+        /// multiple KEY=VALUE entries; AppParameters accepts multiple
+        /// arguments which are quoted and joined; AppExit takes an exit
+        /// code (or Default) followed by an action.
         #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
         value: Vec<String>,
     },
@@ -86,6 +111,75 @@ pub enum Commands {
     },
     /// List installed services (created by nssm-rs)
     List,
+    /// Print the dependencies between installed services
+    Deps {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Text)]
+        format: GraphFormat,
+    },
+    /// Print a service's full configuration as JSON or TOML
+    Export {
+        /// Service name
+        service_name: String,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = FileFormat::Json)]
+        format: FileFormat,
+        /// Write to this file instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Apply a configuration written by `export`, installing the service if
+    /// it does not exist
+    Import {
+        /// Service name
+        service_name: String,
+        /// Configuration file
+        file: PathBuf,
+        /// File format (guessed from the file extension by default)
+        #[arg(short, long, value_enum)]
+        format: Option<FileFormat>,
+    },
+    /// Show how installed services differ from a manifest
+    Plan {
+        /// Manifest file (JSON or TOML) describing every service
+        manifest: PathBuf,
+        /// Manifest format (guessed from the file extension by default)
+        #[arg(short, long, value_enum)]
+        format: Option<FileFormat>,
+        /// Also plan removal of installed services missing from the manifest
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Install and reconfigure services to match a manifest
+    Apply {
+        /// Manifest file (JSON or TOML) describing every service
+        manifest: PathBuf,
+        /// Manifest format (guessed from the file extension by default)
+        #[arg(short, long, value_enum)]
+        format: Option<FileFormat>,
+        /// Also remove installed services missing from the manifest
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Run a service in this terminal the way the service would run it;
+    /// Ctrl-C stops it
+    Debug {
+        /// Service name
+        service_name: String,
+    },
+    /// Run an application from a configuration file, as written by
+    /// `export`, in this terminal; Ctrl-C stops it
+    RunConfig {
+        /// Configuration file
+        file: PathBuf,
+        /// File format (guessed from the file extension by default)
+        #[arg(short, long, value_enum)]
+        format: Option<FileFormat>,
+        /// Service name for variable expansion and logging (the file name
+        /// without its extension by default)
+        #[arg(short, long)]
+        name: Option<String>,
+    },
     /// Run as a service (internal command)
     #[command(hide = true)]
     Run {
@@ -96,7 +190,7 @@ pub enum Commands {
 
 #[cfg(test)]
 mod tests {
-    use super::{Cli, Commands};
+    use super::{Cli, Commands, FileFormat, GraphFormat, LogFormat};
     use clap::Parser;
     use std::path::PathBuf;
 
@@ -145,4 +239,87 @@ mod tests {
             _ => panic!("expected install command"),
         }
     }
+
+    #[test]
+    fn config_dir_selects_file_store_format() {
+        let cli = Cli::parse_from([
+            "nssm-rs",
+            "--config-dir",
+            r"C:\services",
+            "--config-format",
+            "toml",
+            "run",
+            "Clash",
+        ]);
+
+        assert_eq!(cli.config_dir, Some(PathBuf::from(r"C:\services")));
+        assert_eq!(cli.config_format, FileFormat::Toml);
+        assert!(matches!(cli.command, Commands::Run { .. }));
+    }
+
+    #[test]
+    fn export_defaults_to_json() {
+        let cli = Cli::parse_from(["nssm-rs", "export", "Clash"]);
+        match cli.command {
+            Commands::Export { format, output, .. } => {
+                assert_eq!(format, FileFormat::Json);
+                assert_eq!(output, None);
+            }
+            _ => panic!("expected export command"),
+        }
+    }
+
+    #[test]
+    fn log_format_is_optional() {
+        let cli = Cli::parse_from(["nssm-rs", "list"]);
+        assert_eq!(cli.log_format, None);
+
+        let cli = Cli::parse_from(["nssm-rs", "--log-format", "logfmt", "run", "Clash"]);
+        assert_eq!(cli.log_format, Some(LogFormat::Logfmt));
+    }
+
+    #[test]
+    fn start_and_stop_take_several_services() {
+        let cli = Cli::parse_from(["nssm-rs", "start", "web", "db"]);
+        match cli.command {
+            Commands::Start { service_names } => assert_eq!(service_names, vec!["web", "db"]),
+            _ => panic!("expected start command"),
+        }
+        assert!(Cli::try_parse_from(["nssm-rs", "stop"]).is_err());
+    }
+
+    #[test]
+    fn deps_accepts_dot_format() {
+        let cli = Cli::parse_from(["nssm-rs", "deps", "--format", "dot"]);
+        assert!(matches!(
+            cli.command,
+            Commands::Deps {
+                format: GraphFormat::Dot
+            }
+        ));
+    }
+
+    #[test]
+    fn debug_and_run_config_run_in_the_foreground() {
+        let cli = Cli::parse_from(["nssm-rs", "--config-dir", "services", "debug", "web"]);
+        match cli.command {
+            Commands::Debug { service_name } => assert_eq!(service_name, "web"),
+            _ => panic!("expected debug command"),
+        }
+
+        let cli = Cli::parse_from(["nssm-rs", "run-config", "web.toml", "--name", "api"]);
+        match cli.command {
+            Commands::RunConfig { file, format, name } => {
+                assert_eq!(file, PathBuf::from("web.toml"));
+                assert_eq!(format, None);
+                assert_eq!(name.as_deref(), Some("api"));
+            }
+            _ => panic!("expected run-config command"),
+        }
+    }
+
+    #[test]
+    fn config_format_requires_config_dir() {
+        assert!(Cli::try_parse_from(["nssm-rs", "--config-format", "toml", "list"]).is_err());
+    }
 }
@@ -49,6 +49,7 @@ pub fn parse_command_line(input: &str) -> Vec<String> {
 
 /// Quote a single argument so it survives `parse_command_line` (and
 /// `CommandLineToArgvW`) unchanged.
+#[cfg_attr(not(windows), allow(dead_code))]
 pub fn quote_argument(arg: &str) -> String {
     if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
         arg.to_string()
@@ -58,6 +59,7 @@ pub fn quote_argument(arg: &str) -> String {
 }
 
 /// Join arguments into a single command line, quoting where necessary.
+#[cfg_attr(not(windows), allow(dead_code))]
 pub fn join_arguments<S: AsRef<str>>(arguments: &[S]) -> String {
     arguments
         .iter()
@@ -1,20 +1,34 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+/// Field names serialise under their NSSM registry value names so that
+/// configuration files read like the registry Parameters key. Unknown
+/// names are errors, so a misspelt setting is not silently dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default, deny_unknown_fields)]
 pub struct ServiceConfig {
     pub application: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub app_directory: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub app_parameters: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(rename = "Start")]
     pub start_type: ServiceStartType,
     #[allow(dead_code)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub object_name: Option<String>,
-    #[allow(dead_code)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<String>,
     pub app_priority: ProcessPriority,
-    #[allow(dead_code)]
-    pub app_affinity: Option<String>,
+    /// CPUs the application may run on; `None` means all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_affinity: Option<CpuAffinity>,
     pub app_no_console: bool,
     pub app_stop_method_skip: u32,
     pub app_stop_method_console: u32,
@@ -22,31 +36,103 @@ pub struct ServiceConfig {
     pub app_stop_method_threads: u32,
     pub app_throttle: u32,
     pub app_exit_default: ExitAction,
+    /// AppExit: actions for specific exit codes, overriding the default.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub app_exit: BTreeMap<u32, ExitAction>,
     pub app_restart_delay: u32,
+    pub app_restart_backoff: RestartBackoff,
+    /// First backoff delay in milliseconds.
+    pub app_restart_backoff_base: u32,
+    pub app_restart_backoff_multiplier: u32,
+    /// Longest backoff delay in milliseconds.
+    pub app_restart_backoff_max: u32,
+    /// Random variation of each backoff delay, in percent.
+    pub app_restart_jitter: u32,
+    /// Runtime in milliseconds after which failures are forgotten; 0 means
+    /// AppThrottle.
+    pub app_restart_reset_after: u32,
+    /// Restarts allowed within AppRestartLimitWindow, and consecutive
+    /// failures allowed, before the service gives up; 0 means no limit.
+    pub app_restart_limit: u32,
+    /// Window for AppRestartLimit in seconds.
+    pub app_restart_limit_window: u32,
+    /// Failed launches in a row before the service gives up; 0 means keep
+    /// trying.
+    pub app_launch_attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub app_stdout: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub app_stderr: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub app_stdin: Option<PathBuf>,
-    #[allow(dead_code)]
     pub app_rotate_files: bool,
-    #[allow(dead_code)]
     pub app_rotate_online: bool,
-    #[allow(dead_code)]
     pub app_rotate_seconds: u32,
-    #[allow(dead_code)]
     pub app_rotate_bytes: u64,
-    #[allow(dead_code)]
+    pub app_rotate_keep: u32,
+    pub app_rotate_max_age: u32,
+    pub app_rotate_compress: RotateCompression,
+    pub app_timestamp_output: bool,
+    /// strftime-style format used when AppTimestampOutput is set.
+    pub app_timestamp_format: String,
+    pub app_output_tag: bool,
+    /// Milliseconds without stdout or stderr output after which the
+    /// application is considered hung and restarted; 0 disables this.
+    pub app_output_timeout: u32,
+    pub app_log_format: LogFormat,
+    /// Directory for the runtime log instead of the global one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_log_directory: Option<PathBuf>,
+    pub app_log_max_bytes: u64,
+    pub app_log_keep: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub app_environment: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub app_environment_extra: Vec<String>,
+    /// Liveness probe; `None` disables health checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_health_check: Option<HealthCheck>,
+    /// HTTP statuses an `http://` health check accepts as healthy.
+    pub app_health_check_status: StatusRange,
+    /// Milliseconds between health checks.
+    pub app_health_check_interval: u32,
+    /// Milliseconds a health check may take before it counts as failed.
+    pub app_health_check_timeout: u32,
+    /// Failed health checks in a row before the application is restarted.
+    pub app_health_check_threshold: u32,
+    /// Milliseconds after a launch during which failed health checks are
+    /// not counted.
+    pub app_health_check_grace: u32,
+    /// What must happen before the service reports RUNNING; `None` means
+    /// it is running as soon as the application is launched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_ready_check: Option<ReadyCheck>,
+    /// Milliseconds AppReadyCheck may take before the start fails; 0 means
+    /// wait indefinitely.
+    pub app_ready_timeout: u32,
+    /// Whether the application gets a notify endpoint in NOTIFY_SOCKET.
+    pub app_notify: bool,
+    /// Milliseconds the application may go without a `WATCHDOG=1`
+    /// notification before it is restarted; 0 disables this.
+    pub app_watchdog_timeout: u32,
+    /// What must be available before the application is first launched.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub app_start_condition: Vec<StartCondition>,
+    /// Milliseconds AppStartCondition may take before the start fails; 0
+    /// means wait indefinitely.
+    pub app_start_timeout: u32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
 pub enum ServiceStartType {
     Auto,
     Manual,
     Disabled,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
 pub enum ProcessPriority {
     Realtime,
     High,
@@ -56,11 +142,126 @@ pub enum ProcessPriority {
     Idle,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
 pub enum ExitAction {
     Restart,
     Ignore,
     Exit,
+    /// Exit without reporting SERVICE_STOPPED, so the SCM treats the
+    /// service as crashed and runs its recovery actions.
+    Suicide,
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// Whether chrono understands every specifier in an AppTimestampFormat;
+/// formatting with one it does not fails.
+pub fn is_valid_time_format(format: &str) -> bool {
+    chrono::format::StrftimeItems::new(format)
+        .all(|item| !matches!(item, chrono::format::Item::Error))
+}
+pub const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_KEEP: u32 = 5;
+pub const DEFAULT_RESTART_BACKOFF_BASE: u32 = 2000;
+pub const DEFAULT_RESTART_BACKOFF_MULTIPLIER: u32 = 2;
+pub const DEFAULT_RESTART_BACKOFF_MAX: u32 = 256_000;
+pub const DEFAULT_RESTART_LIMIT_WINDOW: u32 = 3600;
+pub const DEFAULT_LAUNCH_ATTEMPTS: u32 = 10;
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: u32 = 10_000;
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: u32 = 5000;
+pub const DEFAULT_HEALTH_CHECK_THRESHOLD: u32 = 3;
+pub const DEFAULT_READY_TIMEOUT: u32 = 60_000;
+pub const DEFAULT_START_TIMEOUT: u32 = 300_000;
+
+/// AppRestartBackoff: how the delay grows with each consecutive failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum RestartBackoff {
+    /// Always the base delay.
+    Fixed,
+    /// The base delay times the number of failures.
+    Linear,
+    /// The base delay times the multiplier to the power of failures - 1.
+    Exponential,
+}
+
+/// AppRotateCompress: how rotated output files are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum RotateCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// AppLogFormat: line format of the supervisor's runtime log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum LogFormat {
+    Text,
+    Json,
+    Logfmt,
+}
+
+/// AppAffinity: a set of CPUs, written as in NSSM (`0-3,6,8-9`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct CpuAffinity(u64);
+
+/// AppHealthCheck: how the supervisor tells a hung application from a
+/// healthy one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum HealthCheck {
+    /// `http://host[:port][/path]`: a GET answered with a status in
+    /// AppHealthCheckStatus.
+    Http {
+        host: String,
+        port: u16,
+        path: String,
+    },
+    /// `tcp://host:port`: the port accepts a connection.
+    Tcp { host: String, port: u16 },
+    /// `exec:<command line>`: the command exits with 0.
+    Exec(String),
+}
+
+/// AppReadyCheck: when a starting application counts as ready.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum ReadyCheck {
+    /// `tcp://host:port`: the port accepts a connection.
+    Tcp { host: String, port: u16 },
+    /// `file:<path>`: the file exists. A relative path is resolved against
+    /// the application's working directory.
+    File(PathBuf),
+    /// `stdout:<regex>`: a line the application writes to stdout matches.
+    Stdout(String),
+    /// `notify`: the application sends `READY=1` to NOTIFY_SOCKET.
+    Notify,
+}
+
+/// One AppStartCondition entry: something the application needs before it
+/// can start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum StartCondition {
+    /// `tcp://host:port`: the port accepts a connection.
+    Tcp { host: String, port: u16 },
+    /// `path:<path>`: the file or directory exists. A relative path is
+    /// resolved against the application's working directory.
+    Path(PathBuf),
+    /// `service:<name>`: the service is RUNNING.
+    Service(String),
+}
+
+/// AppHealthCheckStatus: an inclusive range of HTTP statuses, `200-399`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct StatusRange {
+    first: u16,
+    last: u16,
 }
 
 impl Default for ServiceConfig {
@@ -83,7 +284,17 @@ impl Default for ServiceConfig {
             app_stop_method_threads: 1500,
             app_throttle: 1500,
             app_exit_default: ExitAction::Restart,
+            app_exit: BTreeMap::new(),
             app_restart_delay: 0,
+            app_restart_backoff: RestartBackoff::Exponential,
+            app_restart_backoff_base: DEFAULT_RESTART_BACKOFF_BASE,
+            app_restart_backoff_multiplier: DEFAULT_RESTART_BACKOFF_MULTIPLIER,
+            app_restart_backoff_max: DEFAULT_RESTART_BACKOFF_MAX,
+            app_restart_jitter: 0,
+            app_restart_reset_after: 0,
+            app_restart_limit: 0,
+            app_restart_limit_window: DEFAULT_RESTART_LIMIT_WINDOW,
+            app_launch_attempts: DEFAULT_LAUNCH_ATTEMPTS,
             app_stdout: None,
             app_stderr: None,
             app_stdin: None,
@@ -91,12 +302,52 @@ impl Default for ServiceConfig {
             app_rotate_online: false,
             app_rotate_seconds: 86400,
             app_rotate_bytes: 1048576,
+            app_rotate_keep: 0,
+            app_rotate_max_age: 0,
+            app_rotate_compress: RotateCompression::None,
+            app_timestamp_output: false,
+            app_timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            app_output_tag: false,
+            app_output_timeout: 0,
+            app_log_format: LogFormat::Text,
+            app_log_directory: None,
+            app_log_max_bytes: DEFAULT_LOG_MAX_BYTES,
+            app_log_keep: DEFAULT_LOG_KEEP,
             app_environment: Vec::new(),
             app_environment_extra: Vec::new(),
+            app_health_check: None,
+            app_health_check_status: StatusRange::SUCCESS,
+            app_health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            app_health_check_timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            app_health_check_threshold: DEFAULT_HEALTH_CHECK_THRESHOLD,
+            app_health_check_grace: 0,
+            app_ready_check: None,
+            app_ready_timeout: DEFAULT_READY_TIMEOUT,
+            app_notify: false,
+            app_watchdog_timeout: 0,
+            app_start_condition: Vec::new(),
+            app_start_timeout: DEFAULT_START_TIMEOUT,
         }
     }
 }
 
+impl ServiceConfig {
+    /// What to do when the application exits with `exit_code`.
+    pub fn exit_action(&self, exit_code: u32) -> ExitAction {
+        self.app_exit
+            .get(&exit_code)
+            .copied()
+            .unwrap_or(self.app_exit_default)
+    }
+
+    /// Whether the application talks back through the notify protocol.
+    pub fn uses_notify(&self) -> bool {
+        self.app_notify
+            || self.app_watchdog_timeout > 0
+            || matches!(self.app_ready_check, Some(ReadyCheck::Notify))
+    }
+}
+
 impl ServiceStartType {
     pub fn to_windows_value(self) -> u32 {
         match self {
@@ -186,6 +437,7 @@ impl ExitAction {
             "RESTART" => Some(Self::Restart),
             "IGNORE" => Some(Self::Ignore),
             "EXIT" => Some(Self::Exit),
+            "SUICIDE" => Some(Self::Suicide),
             _ => None,
         }
     }
@@ -195,6 +447,495 @@ impl ExitAction {
             Self::Restart => "Restart",
             Self::Ignore => "Ignore",
             Self::Exit => "Exit",
+            Self::Suicide => "Suicide",
+        }
+    }
+}
+
+impl RotateCompression {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_uppercase().as_str() {
+            "NONE" | "" => Some(Self::None),
+            "GZIP" | "GZ" => Some(Self::Gzip),
+            "ZSTD" | "ZST" => Some(Self::Zstd),
+            _ => None,
         }
     }
+
+    pub fn as_cli_value(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Gzip => "Gzip",
+            Self::Zstd => "Zstd",
+        }
+    }
+
+    /// Suffix appended to a compressed file's name.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gz"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+}
+
+impl RestartBackoff {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_uppercase().as_str() {
+            "FIXED" => Some(Self::Fixed),
+            "LINEAR" => Some(Self::Linear),
+            "EXPONENTIAL" => Some(Self::Exponential),
+            _ => None,
+        }
+    }
+
+    pub fn as_cli_value(self) -> &'static str {
+        match self {
+            Self::Fixed => "Fixed",
+            Self::Linear => "Linear",
+            Self::Exponential => "Exponential",
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_uppercase().as_str() {
+            "TEXT" | "" => Some(Self::Text),
+            "JSON" => Some(Self::Json),
+            "LOGFMT" => Some(Self::Logfmt),
+            _ => None,
+        }
+    }
+
+    pub fn as_cli_value(self) -> &'static str {
+        match self {
+            Self::Text => "Text",
+            Self::Json => "Json",
+            Self::Logfmt => "Logfmt",
+        }
+    }
+}
+
+impl CpuAffinity {
+    /// One bit per CPU in a Windows affinity mask.
+    pub const MAX_CPUS: u32 = 64;
+
+    /// Parses a comma-separated list of CPU numbers and ranges. `All` and
+    /// an empty value mean no restriction and give `None`; an invalid list
+    /// gives a description of what is wrong with it.
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("all") {
+            return Ok(None);
+        }
+
+        let cpu = |text: &str| -> Result<u32, String> {
+            let text = text.trim();
+            let cpu = text
+                .parse::<u32>()
+                .map_err(|_| format!("'{text}' is not a CPU number"))?;
+            if cpu >= Self::MAX_CPUS {
+                return Err(format!(
+                    "CPU {cpu} is out of range; CPUs are numbered 0 to {}",
+                    Self::MAX_CPUS - 1
+                ));
+            }
+            Ok(cpu)
+        };
+
+        let mut mask = 0u64;
+        for item in value.split(',') {
+            if item.trim().is_empty() {
+                return Err("empty entry in CPU list".to_string());
+            }
+            let (first, last) = match item.split_once('-') {
+                Some((first, last)) => (cpu(first)?, cpu(last)?),
+                None => {
+                    let cpu = cpu(item)?;
+                    (cpu, cpu)
+                }
+            };
+            if first > last {
+                return Err(format!("range {first}-{last} is backwards"));
+            }
+            for cpu in first..=last {
+                mask |= 1 << cpu;
+            }
+        }
+        Ok(Some(Self(mask)))
+    }
+
+    pub fn from_mask(mask: u64) -> Option<Self> {
+        (mask != 0).then_some(Self(mask))
+    }
+
+    pub fn mask(self) -> u64 {
+        self.0
+    }
+
+    /// The CPUs as ascending numbers and ranges, e.g. `0-3,6`.
+    pub fn as_cli_value(self) -> String {
+        let mut items = Vec::new();
+        let mut cpu = 0;
+        while cpu < Self::MAX_CPUS {
+            if self.0 & (1 << cpu) == 0 {
+                cpu += 1;
+                continue;
+            }
+            let first = cpu;
+            while cpu + 1 < Self::MAX_CPUS && self.0 & (1 << (cpu + 1)) != 0 {
+                cpu += 1;
+            }
+            items.push(if first == cpu {
+                first.to_string()
+            } else {
+                format!("{first}-{cpu}")
+            });
+            cpu += 1;
+        }
+        items.join(",")
+    }
+}
+
+impl From<CpuAffinity> for String {
+    fn from(value: CpuAffinity) -> Self {
+        value.as_cli_value()
+    }
 }
+
+impl TryFrom<String> for CpuAffinity {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        CpuAffinity::parse(&value)?.ok_or_else(|| "omit AppAffinity to allow all CPUs".to_string())
+    }
+}
+
+impl HealthCheck {
+    /// Parses `http://`, `tcp://` and `exec:` checks. An empty value means
+    /// no health check and gives `None`; anything else that is not a check
+    /// gives a description of what is wrong with it.
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let (scheme, rest) = match value.split_once(':') {
+            Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+            None => (String::new(), value),
+        };
+        match scheme.as_str() {
+            "http" => {
+                let rest = rest
+                    .strip_prefix("//")
+                    .ok_or_else(|| "expected http://host[:port][/path]".to_string())?;
+                let (authority, path) = match rest.find(['/', '?']) {
+                    Some(index) => rest.split_at(index),
+                    None => (rest, "/"),
+                };
+                let (host, port) = parse_authority(authority, Some(80))?;
+                let path = if path.starts_with('?') {
+                    format!("/{path}")
+                } else {
+                    path.to_string()
+                };
+                Ok(Some(Self::Http { host, port, path }))
+            }
+            "tcp" => {
+                let authority = rest
+                    .strip_prefix("//")
+                    .ok_or_else(|| "expected tcp://host:port".to_string())?;
+                let (host, port) = parse_authority(authority.trim_end_matches('/'), None)?;
+                Ok(Some(Self::Tcp { host, port }))
+            }
+            "exec" => {
+                let command = rest.trim();
+                if command.is_empty() {
+                    return Err("exec: needs a command line".to_string());
+                }
+                Ok(Some(Self::Exec(command.to_string())))
+            }
+            "https" => Err(
+                "HTTPS health checks are not supported; check a plain HTTP port or use exec:"
+                    .to_string(),
+            ),
+            _ => Err(
+                "expected http://host[:port][/path], tcp://host:port or exec:<command line>"
+                    .to_string(),
+            ),
+        }
+    }
+
+    pub fn as_cli_value(&self) -> String {
+        match self {
+            Self::Http { host, port, path } => {
+                format!("http://{}:{port}{path}", format_host(host))
+            }
+            Self::Tcp { host, port } => format!("tcp://{}:{port}", format_host(host)),
+            Self::Exec(command) => format!("exec:{command}"),
+        }
+    }
+}
+
+/// Splits `host:port`, with IPv6 hosts in brackets as in URLs.
+fn parse_authority(authority: &str, default_port: Option<u16>) -> Result<(String, u16), String> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| format!("'{authority}' has an unclosed '['"))?;
+            match rest {
+                "" => (host, None),
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("unexpected '{rest}' after the host")),
+                },
+            }
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return Err("the host is missing".to_string());
+    }
+    let port = match (port, default_port) {
+        (Some(port), _) => port
+            .parse::<u16>()
+            .ok()
+            .filter(|&port| port != 0)
+            .ok_or_else(|| format!("'{port}' is not a port number"))?,
+        (None, Some(port)) => port,
+        (None, None) => return Err("the port is missing".to_string()),
+    };
+    Ok((host.to_string(), port))
+}
+
+fn format_host(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+impl From<HealthCheck> for String {
+    fn from(value: HealthCheck) -> Self {
+        value.as_cli_value()
+    }
+}
+
+impl TryFrom<String> for HealthCheck {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        HealthCheck::parse(&value)?
+            .ok_or_else(|| "omit AppHealthCheck to disable health checks".to_string())
+    }
+}
+
+impl ReadyCheck {
+    /// Parses `tcp://`, `file:`, `stdout:` and `notify` checks. An empty value means
+    /// no readiness check and gives `None`; an invalid check gives a
+    /// description of what is wrong with it.
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        if value.eq_ignore_ascii_case("notify") {
+            return Ok(Some(Self::Notify));
+        }
+
+        let expected =
+            || "expected tcp://host:port, file:<path>, stdout:<regex> or notify".to_string();
+        let (scheme, rest) = value.split_once(':').ok_or_else(expected)?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "tcp" => {
+                let authority = rest
+                    .strip_prefix("//")
+                    .ok_or_else(|| "expected tcp://host:port".to_string())?;
+                let (host, port) = parse_authority(authority.trim_end_matches('/'), None)?;
+                Ok(Some(Self::Tcp { host, port }))
+            }
+            "file" => match rest.trim() {
+                "" => Err("file: needs a path".to_string()),
+                path => Ok(Some(Self::File(PathBuf::from(path)))),
+            },
+            "stdout" => {
+                regex::Regex::new(rest)
+                    .map_err(|error| format!("invalid regular expression: {error}"))?;
+                Ok(Some(Self::Stdout(rest.to_string())))
+            }
+            _ => Err(expected()),
+        }
+    }
+
+    pub fn as_cli_value(&self) -> String {
+        match self {
+            Self::Tcp { host, port } => format!("tcp://{}:{port}", format_host(host)),
+            Self::File(path) => format!("file:{}", path.display()),
+            Self::Stdout(pattern) => format!("stdout:{pattern}"),
+            Self::Notify => "notify".to_string(),
+        }
+    }
+}
+
+impl From<ReadyCheck> for String {
+    fn from(value: ReadyCheck) -> Self {
+        value.as_cli_value()
+    }
+}
+
+impl TryFrom<String> for ReadyCheck {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ReadyCheck::parse(&value)?
+            .ok_or_else(|| "omit AppReadyCheck to report RUNNING at launch".to_string())
+    }
+}
+
+impl StartCondition {
+    /// Parses `tcp://`, `path:` and `service:` conditions. An empty value
+    /// gives `None`; an invalid condition gives a description of what is
+    /// wrong with it.
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let expected = || "expected tcp://host:port, path:<path> or service:<name>".to_string();
+        let (scheme, rest) = value.split_once(':').ok_or_else(expected)?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "tcp" => {
+                let authority = rest
+                    .strip_prefix("//")
+                    .ok_or_else(|| "expected tcp://host:port".to_string())?;
+                let (host, port) = parse_authority(authority.trim_end_matches('/'), None)?;
+                Ok(Some(Self::Tcp { host, port }))
+            }
+            "path" => match rest.trim() {
+                "" => Err("path: needs a path".to_string()),
+                path => Ok(Some(Self::Path(PathBuf::from(path)))),
+            },
+            "service" => match rest.trim() {
+                "" => Err("service: needs a service name".to_string()),
+                name => Ok(Some(Self::Service(name.to_string()))),
+            },
+            _ => Err(expected()),
+        }
+    }
+
+    pub fn as_cli_value(&self) -> String {
+        match self {
+            Self::Tcp { host, port } => format!("tcp://{}:{port}", format_host(host)),
+            Self::Path(path) => format!("path:{}", path.display()),
+            Self::Service(name) => format!("service:{name}"),
+        }
+    }
+}
+
+impl From<StartCondition> for String {
+    fn from(value: StartCondition) -> Self {
+        value.as_cli_value()
+    }
+}
+
+impl TryFrom<String> for StartCondition {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        StartCondition::parse(&value)?.ok_or_else(|| "an empty AppStartCondition entry".to_string())
+    }
+}
+
+impl StatusRange {
+    /// The 2xx and 3xx statuses.
+    pub const SUCCESS: Self = Self {
+        first: 200,
+        last: 399,
+    };
+
+    /// Parses a single status or a range such as `200-299`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let status = |text: &str| -> Result<u16, String> {
+            let text = text.trim();
+            text.parse::<u16>()
+                .ok()
+                .filter(|status| (100..=599).contains(status))
+                .ok_or_else(|| format!("'{text}' is not an HTTP status from 100 to 599"))
+        };
+        let (first, last) = match value.split_once('-') {
+            Some((first, last)) => (status(first)?, status(last)?),
+            None => {
+                let status = status(value)?;
+                (status, status)
+            }
+        };
+        if first > last {
+            return Err(format!("range {first}-{last} is backwards"));
+        }
+        Ok(Self { first, last })
+    }
+
+    pub fn contains(self, status: u16) -> bool {
+        (self.first..=self.last).contains(&status)
+    }
+
+    pub fn as_cli_value(self) -> String {
+        if self.first == self.last {
+            self.first.to_string()
+        } else {
+            format!("{}-{}", self.first, self.last)
+        }
+    }
+}
+
+impl From<StatusRange> for String {
+    fn from(value: StatusRange) -> Self {
+        value.as_cli_value()
+    }
+}
+
+impl TryFrom<String> for StatusRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        StatusRange::parse(&value)
+    }
+}
+
+/// Serde glue: enums are written by their CLI names and read back with the
+/// same lenient parsing `set` accepts.
+macro_rules! serde_via_cli_name {
+    ($type:ty, $to_str:ident) => {
+        impl From<$type> for &'static str {
+            fn from(value: $type) -> Self {
+                value.$to_str()
+            }
+        }
+
+        impl TryFrom<String> for $type {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                <$type>::from_str(&value)
+                    .ok_or_else(|| format!("invalid {} value '{value}'", stringify!($type)))
+            }
+        }
+    };
+}
+
+serde_via_cli_name!(ServiceStartType, as_cli_value);
+serde_via_cli_name!(ProcessPriority, as_cli_value);
+serde_via_cli_name!(ExitAction, as_registry_value);
+serde_via_cli_name!(RotateCompression, as_cli_value);
+serde_via_cli_name!(LogFormat, as_cli_value);
+serde_via_cli_name!(RestartBackoff, as_cli_value);
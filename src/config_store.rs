@@ -0,0 +1,773 @@
+//! Where service configuration is kept.
+//!
+//! The registry Parameters key is the default store; a directory of JSON or
+//! TOML files and an in-memory map implement the same trait so the whole
+//! set/get/reset pipeline runs without a live registry.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::config::ServiceConfig;
+use crate::error::{AppError, AppResult};
+use crate::parameters::ServiceParameter;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub trait ConfigStore: Send + Sync {
+    fn load(&self, service_name: &str) -> AppResult<ServiceConfig>;
+
+    fn save(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()>;
+
+    /// Deletes the service's configuration. Missing configuration is not an
+    /// error.
+    fn remove(&self, service_name: &str) -> AppResult<()>;
+
+    /// Names of every service with a configuration in this store, sorted.
+    fn list(&self) -> AppResult<Vec<String>>;
+
+    /// Whether DisplayName, Description and Start are persisted by the store
+    /// itself. The registry store leaves them to the SCM.
+    fn keeps_scm_fields(&self) -> bool {
+        true
+    }
+
+    /// Global arguments placed before `run` in an installed service's
+    /// command line so the service reads its configuration from this store.
+    /// `None` if the store cannot be reached from another process.
+    fn service_arguments(&self) -> Option<Vec<String>>;
+}
+
+/// Applies `values` to one parameter of a stored configuration.
+///
+/// Dependencies, AppEnvironment and AppEnvironmentExtra take one entry per
+/// value (a single empty value clears the list); AppParameters quotes and
+/// joins multiple values; AppStartCondition takes one condition per value;
+/// AppExit takes either `<code|Default> <action>` or a whole table of
+/// `<code> <action>` lines.
+/// Every other parameter expects exactly one value.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn set_parameter(
+    store: &dyn ConfigStore,
+    service_name: &str,
+    parameter: ServiceParameter,
+    values: &[String],
+) -> AppResult<()> {
+    let mut config = store.load(service_name)?;
+    match parameter {
+        _ if let Some(list) = parameter.list_mut(&mut config) => {
+            let entries = list_values(values);
+            if parameter.is_environment() {
+                validate_environment(parameter, &entries)?;
+            }
+            *list = entries;
+        }
+        ServiceParameter::AppExit if values.len() == 2 => {
+            crate::parameters::apply_exit_entry(&mut config, &values[0], &values[1])?;
+        }
+        ServiceParameter::AppParameters if values.len() > 1 => {
+            parameter.apply(&mut config, &crate::cmdline::join_arguments(values))?;
+        }
+        ServiceParameter::AppStartCondition if values.len() > 1 => {
+            parameter.apply(&mut config, &values.join("\n"))?;
+        }
+        _ => parameter.apply(&mut config, single_value(parameter, values)?)?,
+    }
+    store.save(service_name, &config)
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn get_parameter(
+    store: &dyn ConfigStore,
+    service_name: &str,
+    parameter: ServiceParameter,
+) -> AppResult<String> {
+    Ok(parameter.read(&store.load(service_name)?))
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn reset_parameter(
+    store: &dyn ConfigStore,
+    service_name: &str,
+    parameter: ServiceParameter,
+) -> AppResult<()> {
+    let default_value = parameter.reset_value()?;
+    set_parameter(store, service_name, parameter, &[default_value])
+}
+
+/// Checks a whole configuration read from a file, which bypasses the
+/// per-parameter validation done by `set`: every parameter's value is put
+/// through the same `apply` that `set` uses, on a scratch copy.
+pub fn validate_config(config: &ServiceConfig) -> AppResult<()> {
+    let mut scratch = config.clone();
+    for &parameter in ServiceParameter::ALL {
+        parameter.apply(&mut scratch, &parameter.read(config))?;
+    }
+    validate_environment(ServiceParameter::AppEnvironment, &config.app_environment)?;
+    validate_environment(
+        ServiceParameter::AppEnvironmentExtra,
+        &config.app_environment_extra,
+    )
+}
+
+fn validate_environment(parameter: ServiceParameter, entries: &[String]) -> AppResult<()> {
+    match entries.iter().find(|entry| !entry.contains('=')) {
+        Some(entry) => Err(AppError::InvalidParameterValue {
+            parameter: parameter.as_str().to_string(),
+            value: entry.clone(),
+            reason: None,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The entries of a list parameter; a single empty value means none.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn list_values(values: &[String]) -> Vec<String> {
+    match values {
+        [value] if value.is_empty() => Vec::new(),
+        _ => values
+            .iter()
+            .filter(|value| !value.is_empty())
+            .cloned()
+            .collect(),
+    }
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn single_value(parameter: ServiceParameter, values: &[String]) -> AppResult<&str> {
+    match values {
+        [value] => Ok(value),
+        _ => Err(AppError::Message(format!(
+            "Parameter '{}' expects exactly one value, got {}",
+            parameter.as_str(),
+            values.len()
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileFormat {
+    Json,
+    Toml,
+}
+
+impl FileFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+        }
+    }
+
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        if extension.eq_ignore_ascii_case("json") {
+            Some(Self::Json)
+        } else if extension.eq_ignore_ascii_case("toml") {
+            Some(Self::Toml)
+        } else {
+            None
+        }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn serialize<T: Serialize>(self, value: &T) -> AppResult<String> {
+        match self {
+            Self::Json => serde_json::to_string_pretty(value)
+                .map(|text| text + "\n")
+                .map_err(|error| AppError::Message(format!("Failed to encode JSON: {error}"))),
+            Self::Toml => toml::to_string_pretty(value)
+                .map_err(|error| AppError::Message(format!("Failed to encode TOML: {error}"))),
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, text: &str) -> AppResult<T> {
+        match self {
+            Self::Json => serde_json::from_str(text)
+                .map_err(|error| AppError::Message(format!("Invalid JSON configuration: {error}"))),
+            Self::Toml => toml::from_str(text)
+                .map_err(|error| AppError::Message(format!("Invalid TOML configuration: {error}"))),
+        }
+    }
+}
+
+/// One `<service>.json` or `<service>.toml` file per service.
+pub struct FileStore {
+    directory: PathBuf,
+    format: FileFormat,
+}
+
+impl FileStore {
+    pub fn new(directory: &Path, format: FileFormat) -> AppResult<Self> {
+        // Installed services run with a different working directory, so the
+        // path embedded in their command line must be absolute.
+        Ok(Self {
+            directory: std::path::absolute(directory)?,
+            format,
+        })
+    }
+
+    fn config_path(&self, service_name: &str) -> AppResult<PathBuf> {
+        if service_name.is_empty()
+            || service_name == "."
+            || service_name == ".."
+            || service_name.contains(['/', '\\'])
+        {
+            return Err(AppError::Message(format!(
+                "Service name '{service_name}' cannot be used as a configuration file name"
+            )));
+        }
+        Ok(self
+            .directory
+            .join(format!("{service_name}.{}", self.format.extension())))
+    }
+}
+
+impl ConfigStore for FileStore {
+    fn load(&self, service_name: &str) -> AppResult<ServiceConfig> {
+        let path = self.config_path(service_name)?;
+        let text = std::fs::read_to_string(&path).map_err(|error| {
+            AppError::Message(format!(
+                "Failed to read configuration for service '{service_name}' from '{}': {error}",
+                path.display()
+            ))
+        })?;
+        let config = self.format.deserialize(&text)?;
+        validate_config(&config).map_err(|error| {
+            AppError::Message(format!(
+                "Invalid configuration for service '{service_name}' in '{}': {error}",
+                path.display()
+            ))
+        })?;
+        Ok(config)
+    }
+
+    fn save(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        let path = self.config_path(service_name)?;
+        let text = self.format.serialize(config)?;
+        std::fs::create_dir_all(&self.directory)?;
+
+        // Write then rename so a concurrently starting service never reads
+        // a half-written file.
+        let temp_path = path.with_extension(format!("{}.tmp", self.format.extension()));
+        std::fs::write(&temp_path, text)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    fn remove(&self, service_name: &str) -> AppResult<()> {
+        match std::fs::remove_file(self.config_path(service_name)?) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn list(&self) -> AppResult<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(self.format.extension())
+                && let Some(stem) = path.file_stem().and_then(|stem| stem.to_str())
+            {
+                names.push(stem.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn service_arguments(&self) -> Option<Vec<String>> {
+        Some(vec![
+            "--config-dir".to_string(),
+            self.directory.to_string_lossy().into_owned(),
+            "--config-format".to_string(),
+            self.format.extension().to_string(),
+        ])
+    }
+}
+
+/// Configuration held in process memory; lets tests drive the parameter
+/// pipeline without touching the registry or the file system.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    configs: std::sync::Mutex<std::collections::BTreeMap<String, ServiceConfig>>,
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+impl ConfigStore for MemoryStore {
+    fn load(&self, service_name: &str) -> AppResult<ServiceConfig> {
+        self.configs
+            .lock()
+            .unwrap()
+            .get(service_name)
+            .cloned()
+            .ok_or_else(|| {
+                AppError::Message(format!("No configuration for service '{service_name}'"))
+            })
+    }
+
+    fn save(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        self.configs
+            .lock()
+            .unwrap()
+            .insert(service_name.to_string(), config.clone());
+        Ok(())
+    }
+
+    fn remove(&self, service_name: &str) -> AppResult<()> {
+        self.configs.lock().unwrap().remove(service_name);
+        Ok(())
+    }
+
+    fn list(&self) -> AppResult<Vec<String>> {
+        Ok(self.configs.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn service_arguments(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{ExitAction, LogFormat, RotateCompression, ServiceStartType};
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    pub(crate) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(label: &str) -> Self {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "nssm-rs-{label}-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn installed(store: &dyn ConfigStore) {
+        let config = ServiceConfig {
+            application: PathBuf::from(r"C:\app.exe"),
+            ..Default::default()
+        };
+        store.save("svc", &config).unwrap();
+    }
+
+    fn set(store: &dyn ConfigStore, parameter: &str, values: &[&str]) -> AppResult<()> {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        set_parameter(store, "svc", ServiceParameter::parse(parameter)?, &values)
+    }
+
+    fn get(store: &dyn ConfigStore, parameter: &str) -> String {
+        get_parameter(store, "svc", ServiceParameter::parse(parameter).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn app_exit_sets_one_code_or_the_whole_table() {
+        let store = MemoryStore::new();
+        installed(&store);
+
+        set(&store, "AppExit", &["3", "Ignore"]).unwrap();
+        set(&store, "AppExit", &["Default", "Exit"]).unwrap();
+        assert_eq!(get(&store, "AppExit"), "3 Ignore");
+        assert_eq!(get(&store, "AppExitAction"), "Exit");
+        assert!(set(&store, "AppExit", &["3", "Reboot"]).is_err());
+
+        set(&store, "AppExit", &["0 Exit\n1 Exit"]).unwrap();
+        assert_eq!(get(&store, "AppExit"), "0 Exit\n1 Exit");
+
+        reset_parameter(&store, "svc", ServiceParameter::AppExit).unwrap();
+        assert!(store.load("svc").unwrap().app_exit.is_empty());
+    }
+
+    #[test]
+    fn set_get_reset_flow() {
+        let store = MemoryStore::new();
+        installed(&store);
+
+        set(&store, "AppThrottle", &["3000"]).unwrap();
+        set(&store, "AppExitAction", &["exit"]).unwrap();
+        set(&store, "DisplayName", &["My Service"]).unwrap();
+        assert_eq!(get(&store, "AppThrottle"), "3000");
+        assert_eq!(get(&store, "AppExitAction"), "Exit");
+        assert_eq!(get(&store, "DisplayName"), "My Service");
+
+        reset_parameter(&store, "svc", ServiceParameter::AppThrottle).unwrap();
+        reset_parameter(&store, "svc", ServiceParameter::AppExitAction).unwrap();
+        assert_eq!(get(&store, "AppThrottle"), "1500");
+        assert_eq!(
+            store.load("svc").unwrap().app_exit_default,
+            ExitAction::Restart
+        );
+    }
+
+    #[test]
+    fn reset_refuses_application() {
+        let store = MemoryStore::new();
+        installed(&store);
+        assert!(reset_parameter(&store, "svc", ServiceParameter::Application).is_err());
+        assert_eq!(get(&store, "Application"), r"C:\app.exe");
+    }
+
+    #[test]
+    fn environment_takes_multiple_entries_and_validates() {
+        let store = MemoryStore::new();
+        installed(&store);
+
+        set(&store, "AppEnvironment", &["PATH=C:\\bin", "TEMP=C:\\tmp"]).unwrap();
+        let config = store.load("svc").unwrap();
+        assert_eq!(config.app_environment, vec!["PATH=C:\\bin", "TEMP=C:\\tmp"]);
+        assert!(config.app_environment_extra.is_empty());
+
+        assert!(matches!(
+            set(&store, "AppEnvironment", &["NOEQUALS"]),
+            Err(AppError::InvalidParameterValue { .. })
+        ));
+        set(&store, "AppEnvironment", &[""]).unwrap();
+        assert!(store.load("svc").unwrap().app_environment.is_empty());
+    }
+
+    #[test]
+    fn environment_extra_takes_multiple_entries_and_clears() {
+        let store = MemoryStore::new();
+        installed(&store);
+
+        set(&store, "AppEnvironmentExtra", &["A=1", "B=two words"]).unwrap();
+        assert_eq!(
+            store.load("svc").unwrap().app_environment_extra,
+            vec!["A=1", "B=two words"]
+        );
+
+        assert!(matches!(
+            set(&store, "AppEnvironmentExtra", &["A=1", "oops"]),
+            Err(AppError::InvalidParameterValue { .. })
+        ));
+
+        set(&store, "AppEnvironmentExtra", &[""]).unwrap();
+        assert!(store.load("svc").unwrap().app_environment_extra.is_empty());
+    }
+
+    #[test]
+    fn multiple_app_parameters_are_quoted_and_joined() {
+        let store = MemoryStore::new();
+        installed(&store);
+        set(&store, "AppParameters", &["--name", "hello world"]).unwrap();
+        assert_eq!(get(&store, "AppParameters"), "--name \"hello world\"");
+    }
+
+    #[test]
+    fn single_value_parameters_reject_extra_values() {
+        let store = MemoryStore::new();
+        installed(&store);
+        assert!(set(&store, "AppThrottle", &["1", "2"]).is_err());
+    }
+
+    #[test]
+    fn set_on_missing_service_fails() {
+        let store = MemoryStore::new();
+        assert!(set(&store, "AppThrottle", &["1"]).is_err());
+    }
+
+    fn fully_populated_config() -> ServiceConfig {
+        ServiceConfig {
+            application: PathBuf::from(r"C:\Program Files\app\app.exe"),
+            app_directory: Some(PathBuf::from(r"C:\data")),
+            app_parameters: Some("--port 80 \"two words\"".to_string()),
+            display_name: Some("My App".to_string()),
+            description: Some("Does things".to_string()),
+            start_type: ServiceStartType::Disabled,
+            object_name: Some(r".\svc".to_string()),
+            dependencies: vec!["Tcpip".to_string()],
+            app_priority: crate::config::ProcessPriority::BelowNormal,
+            app_affinity: crate::config::CpuAffinity::from_mask(0b11),
+            app_no_console: true,
+            app_stop_method_skip: 6,
+            app_stop_method_console: 1,
+            app_stop_method_window: 2,
+            app_stop_method_threads: 3,
+            app_throttle: 4,
+            app_exit_default: ExitAction::Ignore,
+            app_exit: [(0, ExitAction::Exit), (3, ExitAction::Suicide)].into(),
+            app_restart_delay: 5,
+            app_restart_backoff: crate::config::RestartBackoff::Fixed,
+            app_restart_backoff_base: 6,
+            app_restart_backoff_multiplier: 7,
+            app_restart_backoff_max: 8,
+            app_restart_jitter: 9,
+            app_restart_reset_after: 10,
+            app_restart_limit: 11,
+            app_restart_limit_window: 12,
+            app_launch_attempts: 13,
+            app_stdout: Some(PathBuf::from(r"C:\logs\out.log")),
+            app_stderr: Some(PathBuf::from(r"C:\logs\err.log")),
+            app_stdin: Some(PathBuf::from(r"C:\in.txt")),
+            app_rotate_files: true,
+            app_rotate_online: true,
+            app_rotate_seconds: 60,
+            app_rotate_bytes: 5_000_000_000,
+            app_rotate_keep: 7,
+            app_rotate_max_age: 604800,
+            app_rotate_compress: RotateCompression::Gzip,
+            app_timestamp_output: true,
+            app_timestamp_format: "%d/%m/%Y %T".to_string(),
+            app_output_tag: true,
+            app_output_timeout: 60000,
+            app_log_format: LogFormat::Json,
+            app_log_directory: Some(PathBuf::from(r"D:\logs")),
+            app_log_max_bytes: 1 << 33,
+            app_log_keep: 3,
+            app_environment: vec!["PATH=C:\\bin".to_string()],
+            app_environment_extra: vec!["A=1".to_string(), "B=".to_string()],
+            app_health_check: Some(crate::config::HealthCheck::Http {
+                host: "localhost".to_string(),
+                port: 8080,
+                path: "/healthz".to_string(),
+            }),
+            app_health_check_status: crate::config::StatusRange::parse("200-204").unwrap(),
+            app_health_check_interval: 14,
+            app_health_check_timeout: 15,
+            app_health_check_threshold: 16,
+            app_health_check_grace: 17,
+            app_ready_check: Some(crate::config::ReadyCheck::Stdout(
+                r"^Listening on port \d+$".to_string(),
+            )),
+            app_ready_timeout: 18,
+            app_notify: true,
+            app_watchdog_timeout: 19,
+            app_start_condition: vec![
+                crate::config::StartCondition::Tcp {
+                    host: "db.internal".to_string(),
+                    port: 5432,
+                },
+                crate::config::StartCondition::Service("Postgres".to_string()),
+            ],
+            app_start_timeout: 20,
+        }
+    }
+
+    #[test]
+    fn every_field_round_trips_through_both_formats() {
+        let config = fully_populated_config();
+        for format in [FileFormat::Json, FileFormat::Toml] {
+            let text = format.serialize(&config).unwrap();
+            assert_eq!(
+                format.deserialize::<ServiceConfig>(&text).unwrap(),
+                config,
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn enums_are_written_by_cli_name() {
+        let text = FileFormat::Json
+            .serialize(&fully_populated_config())
+            .unwrap();
+        assert!(text.contains(r#""Start": "SERVICE_DISABLED""#), "{text}");
+        assert!(
+            text.contains(r#""AppPriority": "BELOW_NORMAL_PRIORITY_CLASS""#),
+            "{text}"
+        );
+        assert!(text.contains(r#""AppExitDefault": "Ignore""#), "{text}");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        // APPEXITACTION is stored as AppExitDefault; the parameter name is
+        // not a file key.
+        let error = FileFormat::Json
+            .deserialize::<ServiceConfig>(r#"{"Application": "a.exe", "AppExitAction": "Exit"}"#)
+            .unwrap_err();
+        assert!(error.to_string().contains("AppExitAction"), "{error}");
+    }
+
+    #[test]
+    fn unknown_enum_names_are_rejected() {
+        let error = FileFormat::Toml
+            .deserialize::<ServiceConfig>("Application = 'a.exe'\nAppPriority = 'TURBO'\n")
+            .unwrap_err();
+        assert!(error.to_string().contains("TURBO"), "{error}");
+    }
+
+    #[test]
+    fn validate_config_catches_what_set_would_reject() {
+        assert!(validate_config(&fully_populated_config()).is_ok());
+        assert!(validate_config(&ServiceConfig::default()).is_err());
+
+        let mut config = fully_populated_config();
+        config.app_environment_extra.push("NOEQUALS".to_string());
+        assert!(matches!(
+            validate_config(&config),
+            Err(AppError::InvalidParameterValue { .. })
+        ));
+
+        // The scalar rules `set` enforces apply to files too.
+        let rejects = |expected: ServiceParameter, break_config: fn(&mut ServiceConfig)| {
+            let mut config = fully_populated_config();
+            break_config(&mut config);
+            assert!(
+                matches!(
+                    validate_config(&config),
+                    Err(AppError::InvalidParameterValue { ref parameter, .. })
+                        if parameter == expected.as_str()
+                ),
+                "{expected:?}"
+            );
+        };
+        rejects(ServiceParameter::AppRestartJitter, |config| {
+            config.app_restart_jitter = 101
+        });
+        rejects(ServiceParameter::AppHealthCheckInterval, |config| {
+            config.app_health_check_interval = 0
+        });
+        rejects(ServiceParameter::AppHealthCheckTimeout, |config| {
+            config.app_health_check_timeout = 0
+        });
+        rejects(ServiceParameter::AppHealthCheckThreshold, |config| {
+            config.app_health_check_threshold = 0
+        });
+    }
+
+    #[test]
+    fn file_store_rejects_invalid_configurations() {
+        let dir = TempDir::new("store");
+        let store = FileStore::new(&dir.0, FileFormat::Toml).unwrap();
+        std::fs::write(
+            dir.0.join("svc.toml"),
+            "Application = 'a.exe'\nAppRestartJitter = 150\n",
+        )
+        .unwrap();
+        let error = store.load("svc").unwrap_err();
+        assert!(error.to_string().contains("APPRESTARTJITTER"), "{error}");
+    }
+
+    #[test]
+    fn invalid_timestamp_formats_in_files_are_rejected() {
+        let config = FileFormat::Json
+            .deserialize::<ServiceConfig>(
+                r#"{"Application": "a.exe", "AppTimestampOutput": true, "AppTimestampFormat": "%Q"}"#,
+            )
+            .unwrap();
+        assert!(matches!(
+            validate_config(&config),
+            Err(AppError::InvalidParameterValue { parameter, .. })
+                if parameter == ServiceParameter::AppTimestampFormat.as_str()
+        ));
+    }
+
+    #[test]
+    fn format_is_guessed_from_extension() {
+        assert_eq!(
+            FileFormat::from_path(Path::new("svc.TOML")),
+            Some(FileFormat::Toml)
+        );
+        assert_eq!(
+            FileFormat::from_path(Path::new(r"C:\x\svc.json")),
+            Some(FileFormat::Json)
+        );
+        assert_eq!(FileFormat::from_path(Path::new("svc.yaml")), None);
+    }
+
+    #[test]
+    fn file_store_round_trips_in_both_formats() {
+        for format in [FileFormat::Json, FileFormat::Toml] {
+            let dir = TempDir::new("store");
+            let store = FileStore::new(&dir.0, format).unwrap();
+            installed(&store);
+
+            set(&store, "Start", &["SERVICE_DEMAND_START"]).unwrap();
+            set(&store, "AppStdout", &[r"C:\logs\out.log"]).unwrap();
+            set(&store, "AppEnvironmentExtra", &["A=1", "B=2"]).unwrap();
+
+            let config = store.load("svc").unwrap();
+            assert_eq!(config.start_type, ServiceStartType::Manual);
+            assert_eq!(config.app_stdout, Some(PathBuf::from(r"C:\logs\out.log")));
+            assert_eq!(config.app_environment_extra, vec!["A=1", "B=2"]);
+            assert!(dir.0.join(format!("svc.{}", format.extension())).is_file());
+            assert_eq!(store.list().unwrap(), vec!["svc"]);
+
+            store.remove("svc").unwrap();
+            assert!(store.list().unwrap().is_empty());
+            store.remove("svc").unwrap();
+        }
+    }
+
+    #[test]
+    fn file_store_writes_cli_names() {
+        let dir = TempDir::new("store");
+        let store = FileStore::new(&dir.0, FileFormat::Toml).unwrap();
+        installed(&store);
+
+        let text = std::fs::read_to_string(dir.0.join("svc.toml")).unwrap();
+        assert!(text.contains("Start = \"SERVICE_AUTO_START\""), "{text}");
+        assert!(
+            text.contains("AppPriority = \"NORMAL_PRIORITY_CLASS\""),
+            "{text}"
+        );
+        assert!(text.contains("AppExitDefault = \"Restart\""), "{text}");
+    }
+
+    #[test]
+    fn file_store_fills_missing_fields_with_defaults() {
+        let dir = TempDir::new("store");
+        std::fs::write(
+            dir.0.join("svc.json"),
+            r#"{ "Application": "C:\\app.exe", "AppPriority": "high" }"#,
+        )
+        .unwrap();
+        let store = FileStore::new(&dir.0, FileFormat::Json).unwrap();
+
+        let config = store.load("svc").unwrap();
+        assert_eq!(config.app_throttle, ServiceConfig::default().app_throttle);
+        assert_eq!(config.app_priority, crate::config::ProcessPriority::High);
+    }
+
+    #[test]
+    fn file_store_rejects_path_like_service_names() {
+        let dir = TempDir::new("store");
+        let store = FileStore::new(&dir.0, FileFormat::Json).unwrap();
+        assert!(store.load("../escape").is_err());
+        assert!(store.save("a\\b", &ServiceConfig::default()).is_err());
+    }
+
+    #[test]
+    fn file_store_service_arguments_select_the_same_store() {
+        let dir = TempDir::new("store");
+        let store = FileStore::new(&dir.0, FileFormat::Toml).unwrap();
+        let arguments = store.service_arguments().unwrap();
+        assert_eq!(arguments[0], "--config-dir");
+        assert!(Path::new(&arguments[1]).is_absolute());
+        assert_eq!(arguments[3], "toml");
+        assert!(MemoryStore::new().service_arguments().is_none());
+    }
+}
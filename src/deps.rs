@@ -0,0 +1,252 @@
+//! Dependency ordering between services.
+//!
+//! Each service lists the services it depends on (DependOnService). Starting
+//! a group runs dependencies before their dependents; stopping runs the
+//! reverse. Service names are compared case-insensitively, as in the SCM,
+//! and load-order groups (`+Group` entries) are ignored.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::error::{AppError, AppResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// One line per service listing its dependencies
+    Text,
+    /// Graphviz DOT, with edges from dependents to their dependencies
+    Dot,
+}
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    /// Keyed by lowercased name; the value keeps the name as written.
+    services: BTreeMap<String, (String, Vec<String>)>,
+}
+
+fn key(service_name: &str) -> String {
+    service_name.to_lowercase()
+}
+
+impl DependencyGraph {
+    pub fn new<I>(services: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Vec<String>)>,
+    {
+        let services = services
+            .into_iter()
+            .map(|(name, dependencies)| {
+                let dependencies = dependencies
+                    .into_iter()
+                    .filter(|dependency| !dependency.starts_with('+'))
+                    .collect();
+                (key(&name), (name, dependencies))
+            })
+            .collect();
+        Self { services }
+    }
+
+    fn dependencies(&self, service_name: &str) -> &[String] {
+        self.services
+            .get(&key(service_name))
+            .map(|(_, dependencies)| dependencies.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// `selected` ordered so every service comes after the services it
+    /// depends on, directly or through services outside `selected`.
+    /// Otherwise unrelated services keep their given order.
+    pub fn start_order(&self, selected: &[String]) -> AppResult<Vec<String>> {
+        let wanted: HashSet<String> = selected.iter().map(|name| key(name)).collect();
+        let mut state = HashMap::new();
+        let mut path = Vec::new();
+        let mut order = Vec::new();
+        for service_name in selected {
+            self.visit(service_name, &wanted, &mut state, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// `selected` ordered so dependents are stopped before what they
+    /// depend on.
+    pub fn stop_order(&self, selected: &[String]) -> AppResult<Vec<String>> {
+        let mut order = self.start_order(selected)?;
+        order.reverse();
+        Ok(order)
+    }
+
+    /// Depth-first post-order walk; `state` is false while a service is on
+    /// the current path and true once it is done.
+    fn visit(
+        &self,
+        service_name: &str,
+        wanted: &HashSet<String>,
+        state: &mut HashMap<String, bool>,
+        path: &mut Vec<String>,
+        order: &mut Vec<String>,
+    ) -> AppResult<()> {
+        let service_key = key(service_name);
+        match state.get(&service_key) {
+            Some(true) => return Ok(()),
+            Some(false) => {
+                let start = path
+                    .iter()
+                    .position(|name| key(name) == service_key)
+                    .unwrap_or_default();
+                let mut cycle = path[start..].to_vec();
+                cycle.push(service_name.to_string());
+                return Err(AppError::Message(format!(
+                    "Dependency cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            None => {}
+        }
+
+        state.insert(service_key.clone(), false);
+        path.push(service_name.to_string());
+        for dependency in self.dependencies(service_name) {
+            self.visit(dependency, wanted, state, path, order)?;
+        }
+        path.pop();
+        state.insert(service_key.clone(), true);
+
+        if wanted.contains(&service_key) {
+            order.push(service_name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Fails with the first dependency cycle found, if any.
+    pub fn check_cycles(&self) -> AppResult<()> {
+        let names: Vec<String> = self
+            .services
+            .values()
+            .map(|(name, _)| name.clone())
+            .collect();
+        self.start_order(&names).map(|_| ())
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Text => self.to_text(),
+            GraphFormat::Dot => self.to_dot(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (name, dependencies) in self.services.values() {
+            if dependencies.is_empty() {
+                let _ = writeln!(text, "{name}");
+            } else {
+                let _ = writeln!(text, "{name}: {}", dependencies.join(", "));
+            }
+        }
+        text
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n");
+        for (name, dependencies) in self.services.values() {
+            let _ = writeln!(dot, "    {};", dot_id(name));
+            for dependency in dependencies {
+                let _ = writeln!(dot, "    {} -> {};", dot_id(name), dot_id(dependency));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(services: &[(&str, &[&str])]) -> DependencyGraph {
+        DependencyGraph::new(services.iter().map(|(name, dependencies)| {
+            (
+                name.to_string(),
+                dependencies.iter().map(|name| name.to_string()).collect(),
+            )
+        }))
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn starts_dependencies_first_and_stops_them_last() {
+        let graph = graph(&[("web", &["api"]), ("api", &["db"]), ("db", &[])]);
+        let selected = names(&["web", "db", "api"]);
+        assert_eq!(
+            graph.start_order(&selected).unwrap(),
+            names(&["db", "api", "web"])
+        );
+        assert_eq!(
+            graph.stop_order(&selected).unwrap(),
+            names(&["web", "api", "db"])
+        );
+    }
+
+    #[test]
+    fn orders_through_unselected_services() {
+        let graph = graph(&[("web", &["api"]), ("api", &["db"]), ("db", &[])]);
+        assert_eq!(
+            graph.start_order(&names(&["web", "db"])).unwrap(),
+            names(&["db", "web"])
+        );
+    }
+
+    #[test]
+    fn keeps_order_of_unrelated_services() {
+        let graph = graph(&[
+            ("b", &[]),
+            ("a", &[]),
+            ("c", &["Tcpip", "+NetworkProvider"]),
+        ]);
+        assert_eq!(
+            graph.start_order(&names(&["c", "b", "a"])).unwrap(),
+            names(&["c", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let graph = graph(&[("Web", &["DB"]), ("db", &[])]);
+        assert_eq!(
+            graph.start_order(&names(&["web", "db"])).unwrap(),
+            names(&["DB", "web"])
+        );
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let graph = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        let error = graph.start_order(&names(&["a"])).unwrap_err();
+        assert_eq!(error.to_string(), "Dependency cycle: a -> b -> c -> a");
+        assert!(graph.check_cycles().is_err());
+        assert!(graph.start_order(&names(&["d"])).is_ok());
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let graph = graph(&[("a", &["a"])]);
+        assert!(graph.check_cycles().is_err());
+    }
+
+    #[test]
+    fn renders_dot() {
+        let graph = graph(&[("web", &["api", "Tcpip"]), ("api", &[])]);
+        assert_eq!(
+            graph.render(GraphFormat::Dot),
+            "digraph dependencies {\n    \"api\";\n    \"web\";\n    \"web\" -> \"api\";\n    \"web\" -> \"Tcpip\";\n}\n"
+        );
+        assert_eq!(graph.render(GraphFormat::Text), "api\nweb: api, Tcpip\n");
+    }
+}
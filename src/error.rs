@@ -3,8 +3,11 @@ use std::fmt::{self, Display, Formatter};
 #[derive(Debug)]
 pub enum AppError {
     Io(std::io::Error),
+    #[cfg(windows)]
     Windows(windows::core::Error),
+    #[cfg(windows)]
     WindowsService(windows_service::Error),
+    #[cfg(windows)]
     Registry {
         operation: &'static str,
         path: String,
@@ -13,8 +16,12 @@ pub enum AppError {
     InvalidParameterValue {
         parameter: String,
         value: String,
+        /// Why the value was rejected, when there is more to say.
+        reason: Option<String>,
     },
+    #[cfg_attr(not(windows), allow(dead_code))]
     UnknownParameter(String),
+    #[cfg(windows)]
     InvalidServiceState(u32),
     Message(String),
 }
@@ -25,17 +32,29 @@ impl Display for AppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::Io(error) => write!(f, "{error}"),
+            #[cfg(windows)]
             Self::Windows(error) => write!(f, "{error}"),
+            #[cfg(windows)]
             Self::WindowsService(error) => write!(f, "{error}"),
+            #[cfg(windows)]
             Self::Registry {
                 operation,
                 path,
                 code,
             } => write!(f, "Registry {operation} failed for '{path}' (code {code})"),
-            Self::InvalidParameterValue { parameter, value } => {
-                write!(f, "Invalid value '{value}' for parameter '{parameter}'")
+            Self::InvalidParameterValue {
+                parameter,
+                value,
+                reason,
+            } => {
+                write!(f, "Invalid value '{value}' for parameter '{parameter}'")?;
+                match reason {
+                    Some(reason) => write!(f, ": {reason}"),
+                    None => Ok(()),
+                }
             }
             Self::UnknownParameter(parameter) => write!(f, "Unknown parameter: {parameter}"),
+            #[cfg(windows)]
             Self::InvalidServiceState(state) => write!(f, "Invalid Windows service state: {state}"),
             Self::Message(message) => write!(f, "{message}"),
         }
@@ -50,12 +69,14 @@ impl From<std::io::Error> for AppError {
     }
 }
 
+#[cfg(windows)]
 impl From<windows::core::Error> for AppError {
     fn from(value: windows::core::Error) -> Self {
         Self::Windows(value)
     }
 }
 
+#[cfg(windows)]
 impl From<windows_service::Error> for AppError {
     fn from(value: windows_service::Error) -> Self {
         Self::WindowsService(value)
@@ -0,0 +1,259 @@
+//! Variable expansion in configuration values, applied at launch time.
+//!
+//! `%VAR%` and `${VAR}` are replaced with environment variables, and
+//! `{service}`, `{date}`, `{time}` and `{restart_count}` with built-in
+//! values. References that cannot be resolved are left as written.
+//! `%%`, `$${` and `{{` produce a literal `%`, `${` and `{`.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+
+use crate::config::ServiceConfig;
+
+/// Built-in values for one launch of the application.
+#[derive(Debug, Clone, Copy)]
+pub struct Variables<'a> {
+    pub service_name: &'a str,
+    pub restart_count: u32,
+    pub now: DateTime<Local>,
+}
+
+impl<'a> Variables<'a> {
+    pub fn new(service_name: &'a str, restart_count: u32) -> Self {
+        Self {
+            service_name,
+            restart_count,
+            now: Local::now(),
+        }
+    }
+
+    fn builtin(&self, name: &str) -> Option<String> {
+        match name {
+            "service" => Some(self.service_name.to_string()),
+            "date" => Some(self.now.format("%Y-%m-%d").to_string()),
+            "time" => Some(self.now.format("%H%M%S").to_string()),
+            "restart_count" => Some(self.restart_count.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Looks a variable up in this process's environment.
+pub fn process_environment(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+pub fn expand(
+    input: &str,
+    variables: &Variables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(index) = rest.find(['%', '$', '{']) {
+        output.push_str(&rest[..index]);
+        let consumed = expand_reference(&rest[index..], variables, lookup, &mut output);
+        rest = &rest[index + consumed..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Expands the reference at the start of `text` into `output` and returns
+/// how many bytes of `text` it used. An unresolved reference only
+/// consumes its first character, so scanning resumes right after it.
+fn expand_reference(
+    text: &str,
+    variables: &Variables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    output: &mut String,
+) -> usize {
+    for escape in ["%%", "$${", "{{"] {
+        if text.starts_with(escape) {
+            output.push_str(&escape[1..]);
+            return escape.len();
+        }
+    }
+
+    let resolved = if let Some(body) = text.strip_prefix('%') {
+        body.find('%')
+            .map(|end| &body[..end])
+            .and_then(|name| lookup(name).map(|value| (value, name.len() + 2)))
+    } else if let Some(body) = text.strip_prefix("${") {
+        body.find('}')
+            .map(|end| &body[..end])
+            .filter(|name| !name.is_empty())
+            .and_then(|name| lookup(name).map(|value| (value, name.len() + 3)))
+    } else if let Some(body) = text.strip_prefix('{') {
+        body.find('}')
+            .map(|end| &body[..end])
+            .and_then(|name| variables.builtin(name).map(|value| (value, name.len() + 2)))
+    } else {
+        None
+    };
+
+    match resolved {
+        Some((value, consumed)) => {
+            output.push_str(&value);
+            consumed
+        }
+        None => {
+            // All delimiters are ASCII, so this is a whole character.
+            output.push_str(&text[..1]);
+            1
+        }
+    }
+}
+
+fn expand_path(
+    path: &Path,
+    variables: &Variables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> PathBuf {
+    match path.to_str() {
+        Some(text) => PathBuf::from(expand(text, variables, lookup)),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The configuration the application is launched with: the application
+/// path, AppDirectory, AppParameters, redirection paths and environment
+/// values expanded. Environment variable names are left alone.
+pub fn expand_config(
+    config: &ServiceConfig,
+    variables: &Variables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> ServiceConfig {
+    let path = |path: &Path| expand_path(path, variables, lookup);
+    let optional_path = |value: &Option<PathBuf>| value.as_deref().map(path);
+    let environment = |entries: &[String]| -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((key, value)) => format!("{key}={}", expand(value, variables, lookup)),
+                None => entry.clone(),
+            })
+            .collect()
+    };
+
+    ServiceConfig {
+        application: path(&config.application),
+        app_directory: optional_path(&config.app_directory),
+        app_parameters: config
+            .app_parameters
+            .as_deref()
+            .map(|parameters| expand(parameters, variables, lookup)),
+        app_stdout: optional_path(&config.app_stdout),
+        app_stderr: optional_path(&config.app_stderr),
+        app_stdin: optional_path(&config.app_stdin),
+        app_environment: environment(&config.app_environment),
+        app_environment_extra: environment(&config.app_environment_extra),
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn variables() -> Variables<'static> {
+        Variables {
+            service_name: "web",
+            restart_count: 3,
+            now: Local.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap(),
+        }
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "ProgramData" => Some(r"C:\ProgramData".to_string()),
+            "PATH" => Some(r"C:\Windows".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn expand_test(input: &str) -> String {
+        expand(input, &variables(), &lookup)
+    }
+
+    #[test]
+    fn expands_environment_references() {
+        assert_eq!(
+            expand_test(r"%ProgramData%\app\out.log"),
+            r"C:\ProgramData\app\out.log"
+        );
+        assert_eq!(expand_test(r"%PATH%;C:\tools"), r"C:\Windows;C:\tools");
+        assert_eq!(expand_test(r"${PATH};C:\tools"), r"C:\Windows;C:\tools");
+        assert_eq!(expand_test("%PATH%%PATH%"), r"C:\WindowsC:\Windows");
+        assert_eq!(expand_test("[%EMPTY%]"), "[]");
+    }
+
+    #[test]
+    fn expands_builtins() {
+        assert_eq!(
+            expand_test(r"logs\{service}-{date}-{time}.log"),
+            r"logs\web-2025-01-02-030405.log"
+        );
+        assert_eq!(expand_test("run {restart_count}"), "run 3");
+    }
+
+    #[test]
+    fn leaves_unresolved_references_alone() {
+        assert_eq!(expand_test("%MISSING%"), "%MISSING%");
+        assert_eq!(expand_test("${MISSING}"), "${MISSING}");
+        assert_eq!(expand_test("${}"), "${}");
+        assert_eq!(expand_test("50% of 100%"), "50% of 100%");
+        assert_eq!(expand_test("50% and %PATH%"), r"50% and C:\Windows");
+        assert_eq!(expand_test(r#"{"port": 80}"#), r#"{"port": 80}"#);
+        assert_eq!(expand_test("cost $5 {unknown}"), "cost $5 {unknown}");
+        assert_eq!(expand_test("trailing %"), "trailing %");
+    }
+
+    #[test]
+    fn escapes_produce_literals() {
+        assert_eq!(expand_test("%%PATH%%"), "%PATH%");
+        assert_eq!(expand_test("$${PATH}"), "${PATH}");
+        assert_eq!(expand_test("{{service}"), "{service}");
+        assert_eq!(expand_test("100%%"), "100%");
+    }
+
+    #[test]
+    fn keeps_non_ascii_text() {
+        assert_eq!(expand_test("日志-{service}-€%"), "日志-web-€%");
+    }
+
+    #[test]
+    fn expand_config_covers_launch_values() {
+        let config = ServiceConfig {
+            application: PathBuf::from(r"%ProgramData%\app\app.exe"),
+            app_directory: Some(PathBuf::from(r"%ProgramData%\app")),
+            app_parameters: Some("--name {service}".to_string()),
+            app_stdout: Some(PathBuf::from(r"C:\logs\{service}-{date}.log")),
+            app_environment: vec![r"PATH=%PATH%;C:\tools".to_string()],
+            app_environment_extra: vec!["%KEY%=${PATH}".to_string()],
+            display_name: Some("{service}".to_string()),
+            ..Default::default()
+        };
+
+        let expanded = expand_config(&config, &variables(), &lookup);
+        assert_eq!(
+            expanded.application,
+            PathBuf::from(r"C:\ProgramData\app\app.exe")
+        );
+        assert_eq!(
+            expanded.app_directory,
+            Some(PathBuf::from(r"C:\ProgramData\app"))
+        );
+        assert_eq!(expanded.app_parameters.as_deref(), Some("--name web"));
+        assert_eq!(
+            expanded.app_stdout,
+            Some(PathBuf::from(r"C:\logs\web-2025-01-02.log"))
+        );
+        assert_eq!(expanded.app_environment, vec![r"PATH=C:\Windows;C:\tools"]);
+        assert_eq!(expanded.app_environment_extra, vec![r"%KEY%=C:\Windows"]);
+        assert_eq!(expanded.display_name.as_deref(), Some("{service}"));
+    }
+}
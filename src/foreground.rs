@@ -0,0 +1,66 @@
+//! Running a service in the terminal instead of under the SCM.
+//!
+//! `debug` and `run-config` drive the same supervisor loop as an installed
+//! service. Ctrl-C is the stop control, the application writes to the
+//! terminal unless its output is redirected, and the state changes the SCM
+//! would see are logged instead.
+
+use std::time::Duration;
+
+use log::{debug, info};
+
+use crate::config::ServiceConfig;
+use crate::error::{AppError, AppResult};
+use crate::process;
+use crate::runtime_log;
+use crate::supervisor::{self, Event, Events, StatusReporter};
+
+struct ConsoleReporter;
+
+impl StatusReporter for ConsoleReporter {
+    fn start_pending(
+        &self,
+        checkpoint: u32,
+        wait_hint: Duration,
+        _accept_stop: bool,
+    ) -> AppResult<()> {
+        debug!("START_PENDING (checkpoint {checkpoint}, wait hint {wait_hint:?})");
+        Ok(())
+    }
+
+    fn running(&self) -> AppResult<()> {
+        info!("Service is running; press Ctrl-C to stop it");
+        Ok(())
+    }
+
+    fn stop_pending(&self, checkpoint: u32, wait_hint: Duration) -> AppResult<()> {
+        debug!("STOP_PENDING (checkpoint {checkpoint}, wait hint {wait_hint:?})");
+        Ok(())
+    }
+}
+
+/// Supervises the application until Ctrl-C or until the service would
+/// have stopped; a non-zero service exit code is returned as an error.
+pub fn run(service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+    runtime_log::use_service_format(config.app_log_format);
+
+    let events = Events::new();
+    let stop = events.sender();
+    ctrlc::set_handler(move || {
+        // The stop sequence's own Ctrl-C reaches us too when the
+        // application shares our console.
+        if process::take_raised_ctrl_c() {
+            return;
+        }
+        info!("Ctrl-C received; stopping the service");
+        let _ = stop.send(Event::Stop);
+    })?;
+
+    info!("Running service '{service_name}' in the foreground");
+    match supervisor::run(service_name, config, &ConsoleReporter, &events)? {
+        0 => Ok(()),
+        exit_code => Err(AppError::Message(format!(
+            "Service '{service_name}' stopped with exit code {exit_code}"
+        ))),
+    }
+}
@@ -0,0 +1,525 @@
+//! Health checks: telling a hung application from a healthy one.
+//!
+//! An application that stops responding without exiting looks healthy to
+//! the exit waiter. When AppHealthCheck is set, a monitor thread probes the
+//! application every AppHealthCheckInterval and, after
+//! AppHealthCheckThreshold failures in a row, reports `Event::Unhealthy`.
+//! The supervisor then runs the stop sequence and restarts it.
+//!
+//! AppReadyCheck is the counterpart at start-up: the supervisor polls it
+//! until the application is ready to be reported RUNNING. AppStartCondition
+//! comes before that, holding back the first launch until what the
+//! application depends on is available.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::config::{HealthCheck, ReadyCheck, ServiceConfig, StartCondition, StatusRange};
+use crate::process::{self, StopMethod};
+use crate::supervisor::Event;
+
+/// How often a running exec check is polled for its exit.
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The longest HTTP status line accepted.
+const MAX_STATUS_LINE: usize = 8192;
+
+/// One configured health check.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    check: HealthCheck,
+    status: StatusRange,
+    timeout: Duration,
+    /// Where exec checks run: the application's working directory.
+    working_dir: PathBuf,
+}
+
+impl Probe {
+    /// The service's health check, or `None` if AppHealthCheck is not set.
+    pub fn from_config(config: &ServiceConfig, working_dir: &Path) -> Option<Self> {
+        Some(Self {
+            check: config.app_health_check.clone()?,
+            status: config.app_health_check_status,
+            timeout: Duration::from_millis(u64::from(config.app_health_check_timeout.max(1))),
+            working_dir: working_dir.to_path_buf(),
+        })
+    }
+
+    /// Runs the check once; the error says why the application is
+    /// considered unhealthy.
+    pub fn run(&self) -> Result<(), String> {
+        let deadline = Instant::now() + self.timeout;
+        match &self.check {
+            HealthCheck::Http { host, port, path } => {
+                check_http(host, *port, path, self.status, deadline)
+            }
+            HealthCheck::Tcp { host, port } => connect(host, *port, deadline).map(drop),
+            HealthCheck::Exec(command) => check_exec(command, &self.working_dir, deadline),
+        }
+    }
+}
+
+/// Probes one launch of the application until it is dropped.
+pub struct HealthMonitor {
+    /// Dropping the sender disconnects the channel, which ends the thread.
+    _cancel: mpsc::Sender<()>,
+}
+
+impl HealthMonitor {
+    /// Starts probing the application from launch `generation`, or returns
+    /// `None` if no health check is configured.
+    pub fn start(
+        config: &ServiceConfig,
+        working_dir: &Path,
+        events: mpsc::Sender<Event>,
+        generation: u32,
+    ) -> Option<Self> {
+        let probe = Probe::from_config(config, working_dir)?;
+        // Zero is rejected by validation; never probe in a tight loop anyway.
+        let interval = Duration::from_millis(u64::from(config.app_health_check_interval.max(1)));
+        let grace = Duration::from_millis(u64::from(config.app_health_check_grace));
+        let threshold = config.app_health_check_threshold.max(1);
+        info!(
+            "Health check {} every {interval:?}, restarting after {threshold} failures",
+            probe.check.as_cli_value()
+        );
+
+        let (cancel, cancelled) = mpsc::channel();
+        thread::spawn(move || {
+            let started = Instant::now();
+            let mut failures = 0u32;
+            loop {
+                match cancelled.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+                match probe.run() {
+                    Ok(()) => {
+                        if failures > 0 {
+                            info!("Health check passed after {failures} failures");
+                        }
+                        failures = 0;
+                    }
+                    // Failures while the application is still starting up
+                    // do not count.
+                    Err(reason) if started.elapsed() < grace => {
+                        debug!("Health check failed during the grace period: {reason}");
+                    }
+                    Err(reason) => {
+                        failures += 1;
+                        warn!("Health check failed ({failures} of {threshold}): {reason}");
+                        if failures >= threshold {
+                            let _ = events.send(Event::Unhealthy { generation, reason });
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Some(Self { _cancel: cancel })
+    }
+}
+
+/// Whether a `tcp://` or `file:` AppReadyCheck is met, trying for no
+/// longer than `timeout`. `stdout:` and `notify` checks are met by an
+/// event instead, so never here.
+pub fn is_ready(check: &ReadyCheck, working_dir: &Path, timeout: Duration) -> bool {
+    match check {
+        ReadyCheck::Tcp { host, port } => connect(host, *port, Instant::now() + timeout).is_ok(),
+        ReadyCheck::File(path) => working_dir.join(path).exists(),
+        ReadyCheck::Stdout(_) | ReadyCheck::Notify => false,
+    }
+}
+
+/// Checks an AppStartCondition once, trying for no longer than `timeout`;
+/// the error says what is still missing.
+pub fn check_start_condition(
+    condition: &StartCondition,
+    working_dir: &Path,
+    timeout: Duration,
+) -> Result<(), String> {
+    match condition {
+        StartCondition::Tcp { host, port } => {
+            connect(host, *port, Instant::now() + timeout).map(drop)
+        }
+        StartCondition::Path(path) => match working_dir.join(path).try_exists() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("{} does not exist", path.display())),
+            Err(error) => Err(format!("cannot check {}: {error}", path.display())),
+        },
+        StartCondition::Service(name) => service_running(name),
+    }
+}
+
+#[cfg(windows)]
+fn service_running(name: &str) -> Result<(), String> {
+    use windows_service::service::{ServiceAccess, ServiceState};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|error| format!("cannot connect to the service control manager: {error}"))?;
+    let state = manager
+        .open_service(name, ServiceAccess::QUERY_STATUS)
+        .and_then(|service| service.query_status())
+        .map_err(|error| format!("cannot query service '{name}': {error}"))?
+        .current_state;
+    match state {
+        ServiceState::Running => Ok(()),
+        state => Err(format!("service '{name}' is {state:?}")),
+    }
+}
+
+/// Without a service control manager there is no service to wait for.
+#[cfg(not(windows))]
+fn service_running(name: &str) -> Result<(), String> {
+    Err(format!(
+        "service '{name}' cannot be checked without the Windows service control manager"
+    ))
+}
+
+fn remaining(deadline: Instant) -> Result<Duration, String> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        Err("timed out".to_string())
+    } else {
+        Ok(remaining)
+    }
+}
+
+/// Connects to the first address of `host` that accepts before the
+/// deadline.
+fn connect(host: &str, port: u16, deadline: Instant) -> Result<TcpStream, String> {
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .map_err(|error| format!("cannot resolve {host}: {error}"))?;
+    let mut last_error = format!("{host} has no addresses");
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, remaining(deadline)?) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = format!("cannot connect to {address}: {error}"),
+        }
+    }
+    Err(last_error)
+}
+
+/// Sends a GET and checks the status line of the response; the body is
+/// not read.
+fn check_http(
+    host: &str,
+    port: u16,
+    path: &str,
+    expected: StatusRange,
+    deadline: Instant,
+) -> Result<(), String> {
+    let mut stream = connect(host, port, deadline)?;
+    let host_header = match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    };
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host_header}\r\nUser-Agent: nssm-rs\r\n\
+         Connection: close\r\n\r\n"
+    );
+    stream
+        .set_write_timeout(Some(remaining(deadline)?))
+        .and_then(|()| stream.write_all(request.as_bytes()))
+        .map_err(|error| format!("cannot send request: {error}"))?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !response.windows(2).any(|pair| pair == b"\r\n") {
+        if response.len() > MAX_STATUS_LINE {
+            return Err("response status line is too long".to_string());
+        }
+        stream
+            .set_read_timeout(Some(remaining(deadline)?))
+            .map_err(|error| format!("cannot read response: {error}"))?;
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            Err(error) => return Err(format!("cannot read response: {error}")),
+        }
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = match status_line.split_whitespace().collect::<Vec<_>>()[..] {
+        [version, status, ..] if version.starts_with("HTTP/") => status.parse::<u16>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("'{status_line}' is not an HTTP status line"))?;
+
+    if expected.contains(status) {
+        Ok(())
+    } else {
+        Err(format!(
+            "HTTP status {status} is outside {}",
+            expected.as_cli_value()
+        ))
+    }
+}
+
+/// Runs the command and expects it to exit with 0 before the deadline; a
+/// command still running then is killed along with anything it started.
+fn check_exec(command_line: &str, working_dir: &Path, deadline: Instant) -> Result<(), String> {
+    let arguments = crate::cmdline::parse_command_line(command_line);
+    let (program, arguments) = arguments
+        .split_first()
+        .ok_or_else(|| "the command line is empty".to_string())?;
+    let mut command = Command::new(program);
+    command
+        .args(arguments)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let control = process::platform();
+    control.configure(&mut command);
+
+    let mut child = command
+        .spawn()
+        .map_err(|error| format!("cannot run {program}: {error}"))?;
+    let _guard = control.attach(&child);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => {
+                return Err(format!("{program} {}", control.exit_of(status)));
+            }
+            Ok(None) if Instant::now() < deadline => thread::sleep(EXEC_POLL_INTERVAL),
+            Ok(None) => {
+                control.send_stop(&mut child, StopMethod::Terminate);
+                let _ = child.wait();
+                return Err(format!("{program} timed out"));
+            }
+            Err(error) => return Err(format!("cannot wait for {program}: {error}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_store::tests::TempDir;
+    use std::net::TcpListener;
+
+    fn probe(check: &str, timeout: Duration) -> Probe {
+        Probe {
+            check: HealthCheck::parse(check).unwrap().unwrap(),
+            status: StatusRange::SUCCESS,
+            timeout,
+            working_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Answers each connection on a loopback port with `response`.
+    fn serve(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    fn unused_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn http_checks_compare_the_status_with_the_range() {
+        let ok = serve("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n");
+        let failing = serve("HTTP/1.1 503 Service Unavailable\r\n\r\n");
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(
+            probe(&format!("http://127.0.0.1:{ok}/health"), timeout).run(),
+            Ok(())
+        );
+        assert_eq!(
+            probe(&format!("http://127.0.0.1:{failing}/health"), timeout).run(),
+            Err("HTTP status 503 is outside 200-399".to_string())
+        );
+    }
+
+    #[test]
+    fn http_checks_reject_non_http_responses() {
+        let port = serve("SSH-2.0-OpenSSH\r\n");
+        let error = probe(&format!("http://127.0.0.1:{port}/"), Duration::from_secs(5))
+            .run()
+            .unwrap_err();
+        assert_eq!(error, "'SSH-2.0-OpenSSH' is not an HTTP status line");
+    }
+
+    #[test]
+    fn http_checks_time_out_on_silent_servers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let started = Instant::now();
+        let result = probe(
+            &format!("http://127.0.0.1:{port}/"),
+            Duration::from_millis(200),
+        )
+        .run();
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(listener);
+    }
+
+    #[test]
+    fn tcp_checks_need_a_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(
+            probe(&format!("tcp://127.0.0.1:{port}"), timeout).run(),
+            Ok(())
+        );
+        let closed = unused_port();
+        assert!(
+            probe(&format!("tcp://127.0.0.1:{closed}"), timeout)
+                .run()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn ready_checks_wait_for_ports_and_files() {
+        let dir = TempDir::new("ready");
+        let dir = dir.0.as_path();
+        let timeout = Duration::from_millis(500);
+        let file = ReadyCheck::File(PathBuf::from("ready.flag"));
+        assert!(!is_ready(&file, dir, timeout));
+        std::fs::write(dir.join("ready.flag"), b"").unwrap();
+        assert!(is_ready(&file, dir, timeout));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tcp = |port| ReadyCheck::Tcp {
+            host: "127.0.0.1".to_string(),
+            port,
+        };
+        assert!(is_ready(&tcp(port), dir, timeout));
+        assert!(!is_ready(&tcp(unused_port()), dir, timeout));
+    }
+
+    #[test]
+    fn start_conditions_say_what_is_missing() {
+        let dir = TempDir::new("start");
+        let dir = dir.0.as_path();
+        let timeout = Duration::from_millis(500);
+        let share = StartCondition::Path(PathBuf::from("share"));
+        assert_eq!(
+            check_start_condition(&share, dir, timeout),
+            Err("share does not exist".to_string())
+        );
+        std::fs::create_dir(dir.join("share")).unwrap();
+        assert_eq!(check_start_condition(&share, dir, timeout), Ok(()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let database = StartCondition::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+        };
+        assert_eq!(check_start_condition(&database, dir, timeout), Ok(()));
+        drop(listener);
+        assert!(check_start_condition(&database, dir, timeout).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_checks_expect_exit_code_zero() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(probe("exec:/bin/sh -c \"exit 0\"", timeout).run(), Ok(()));
+        assert_eq!(
+            probe("exec:/bin/sh -c \"exit 2\"", timeout).run(),
+            Err("/bin/sh exited with code 2".to_string())
+        );
+
+        let started = Instant::now();
+        assert_eq!(
+            probe("exec:/bin/sleep 30", Duration::from_millis(200)).run(),
+            Err("/bin/sleep timed out".to_string())
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn monitor_reports_unhealthy_after_the_threshold() {
+        let port = serve("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        let config = ServiceConfig {
+            app_health_check: HealthCheck::parse(&format!("http://127.0.0.1:{port}/")).unwrap(),
+            app_health_check_interval: 20,
+            app_health_check_threshold: 3,
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let _monitor = HealthMonitor::start(&config, Path::new("."), sender, 7).unwrap();
+
+        match receiver.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Event::Unhealthy { generation, reason } => {
+                assert_eq!(generation, 7);
+                assert_eq!(reason, "HTTP status 500 is outside 200-399");
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[test]
+    fn failures_during_the_grace_period_do_not_count() {
+        let config = ServiceConfig {
+            app_health_check: HealthCheck::parse(&format!("tcp://127.0.0.1:{}", unused_port()))
+                .unwrap(),
+            app_health_check_interval: 20,
+            app_health_check_threshold: 1,
+            app_health_check_grace: 300,
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let started = Instant::now();
+        let _monitor = HealthMonitor::start(&config, Path::new("."), sender, 1).unwrap();
+
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::Unhealthy { .. })
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn dropping_the_monitor_stops_it() {
+        let config = ServiceConfig {
+            app_health_check: HealthCheck::parse(&format!("tcp://127.0.0.1:{}", unused_port()))
+                .unwrap(),
+            app_health_check_interval: 50,
+            app_health_check_threshold: 1,
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        drop(HealthMonitor::start(&config, Path::new("."), sender, 1).unwrap());
+
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(300)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+}
@@ -1,21 +1,51 @@
 mod cli;
 mod cmdline;
 mod config;
+mod config_store;
+// The service-management commands that use these modules are Windows-only;
+// elsewhere they are built for their unit tests.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod deps;
 mod error;
+mod expand;
+mod foreground;
+mod health;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod manifest;
+mod notify;
+mod output;
+#[cfg_attr(not(windows), allow(dead_code))]
 mod parameters;
+mod process;
+#[cfg(windows)]
 mod registry;
+#[cfg(windows)]
+mod registry_store;
+mod restart;
+mod runtime_log;
+#[cfg(windows)]
 mod service_manager;
+#[cfg(windows)]
 mod service_runner;
+mod supervisor;
+
+use std::path::Path;
 
 use clap::Parser;
 use cli::{Cli, Commands};
-use error::AppResult;
+use config::{DEFAULT_LOG_KEEP, DEFAULT_LOG_MAX_BYTES, ServiceConfig};
+use config_store::{ConfigStore, FileFormat, FileStore};
+use error::{AppError, AppResult};
 use log::{debug, error, info};
-use parameters::ServiceParameter;
+#[cfg(windows)]
+use manifest::Manifest;
+#[cfg(windows)]
+use registry_store::RegistryStore;
+#[cfg(windows)]
 use service_manager::ServiceManager;
+#[cfg(windows)]
 use service_runner::run_service;
 
-#[cfg(windows)]
 fn main() {
     let cli = Cli::parse();
     init_logging(&cli);
@@ -41,23 +71,84 @@ fn main() {
     info!("NSSM-RS shutting down normally");
 }
 
-#[cfg(not(windows))]
-fn main() {
-    eprintln!("This application is only supported on Windows");
-    std::process::exit(1);
-}
-
-#[cfg(windows)]
 fn run(cli: Cli) -> AppResult<()> {
+    let foreground_name = foreground_service_name(&cli.command);
+    let open_store = || open_config_store(cli.config_dir.as_deref(), cli.config_format);
     match cli.command {
-        Commands::Run { name } => run_service(name),
+        Commands::Debug { service_name } => {
+            let config = open_store()?.load(&service_name)?;
+            foreground::run(&service_name, &config)
+        }
+        Commands::RunConfig { file, format, .. } => {
+            let config = read_config_file(&file, format)?;
+            config_store::validate_config(&config)?;
+            foreground::run(foreground_name.as_deref().unwrap_or_default(), &config)
+        }
+        #[cfg(windows)]
+        Commands::Run { name } => run_service(name, open_store()?),
+        #[cfg(windows)]
         command => {
+            let store = open_store()?;
             let service_manager = match command {
-                Commands::Install { .. } => ServiceManager::new_for_install()?,
-                _ => ServiceManager::new()?,
+                Commands::Install { .. } | Commands::Import { .. } | Commands::Apply { .. } => {
+                    ServiceManager::new_for_install(store)?
+                }
+                _ => ServiceManager::new(store)?,
             };
             execute_command(&service_manager, command)
         }
+        #[cfg(not(windows))]
+        _ => Err(AppError::Message(
+            "This command is only supported on Windows; debug and run-config work everywhere"
+                .to_string(),
+        )),
+    }
+}
+
+fn open_config_store(
+    config_dir: Option<&Path>,
+    config_format: FileFormat,
+) -> AppResult<Box<dyn ConfigStore>> {
+    match config_dir {
+        Some(directory) => {
+            debug!("Using configuration files in {}", directory.display());
+            Ok(Box::new(FileStore::new(directory, config_format)?))
+        }
+        #[cfg(windows)]
+        None => Ok(Box::new(RegistryStore)),
+        #[cfg(not(windows))]
+        None => Err(AppError::Message(
+            "Without --config-dir service configuration is read from the Windows registry"
+                .to_string(),
+        )),
+    }
+}
+
+/// Reads a configuration written by `export`.
+fn read_config_file(file: &Path, format: Option<FileFormat>) -> AppResult<ServiceConfig> {
+    let format = format
+        .or_else(|| FileFormat::from_path(file))
+        .ok_or_else(|| {
+            AppError::Message(format!(
+                "Cannot tell the format of '{}'; pass --format json or --format toml",
+                file.display()
+            ))
+        })?;
+    format.deserialize(&std::fs::read_to_string(file)?)
+}
+
+/// The service run by `debug` or `run-config`; `run-config` defaults to the
+/// file name without its extension.
+fn foreground_service_name(command: &Commands) -> Option<String> {
+    match command {
+        Commands::Debug { service_name } => Some(service_name.clone()),
+        Commands::RunConfig { file, name, .. } => Some(name.clone().unwrap_or_else(|| {
+            file.file_stem().map_or_else(
+                || "nssm-rs".to_string(),
+                |stem| stem.to_string_lossy().into_owned(),
+            )
+        })),
+        _ => None,
     }
 }
 
@@ -82,13 +173,19 @@ fn execute_command(service_manager: &ServiceManager, command: Commands) -> AppRe
             info!("Removing service '{service_name}'");
             service_manager.remove_service(&service_name, confirm)
         }
-        Commands::Start { service_name } => {
-            info!("Starting service '{service_name}'");
-            service_manager.start_service(&service_name)
+        Commands::Start { service_names } => {
+            info!("Starting services {service_names:?}");
+            match service_names.as_slice() {
+                [service_name] => service_manager.start_service(service_name),
+                _ => service_manager.start_services(&service_names),
+            }
         }
-        Commands::Stop { service_name } => {
-            info!("Stopping service '{service_name}'");
-            service_manager.stop_service(&service_name)
+        Commands::Stop { service_names } => {
+            info!("Stopping services {service_names:?}");
+            match service_names.as_slice() {
+                [service_name] => service_manager.stop_service(service_name),
+                _ => service_manager.stop_services(&service_names),
+            }
         }
         Commands::Restart { service_name } => {
             info!("Restarting service '{service_name}'");
@@ -117,27 +214,7 @@ fn execute_command(service_manager: &ServiceManager, command: Commands) -> AppRe
         Commands::Reset {
             service_name,
             parameter,
-        } => {
-            let parameter = ServiceParameter::parse(&parameter)?;
-            if parameter == ServiceParameter::Application {
-                return Err(error::AppError::Message(
-                    "APPLICATION cannot be reset to an empty value; set a new path instead"
-                        .to_string(),
-                ));
-            }
-            let default_value = parameter.default_value();
-            info!(
-                "Resetting parameter '{}' for service '{}' to '{}'",
-                parameter.as_str(),
-                service_name,
-                default_value
-            );
-            service_manager.set_service_parameter(
-                &service_name,
-                parameter.as_str(),
-                &[default_value],
-            )
-        }
+        } => service_manager.reset_service_parameter(&service_name, &parameter),
         Commands::Status { service_name } => {
             info!("Querying status for service '{service_name}'");
             service_manager.query_service_status(&service_name)
@@ -146,11 +223,69 @@ fn execute_command(service_manager: &ServiceManager, command: Commands) -> AppRe
             info!("Listing all NSSM-RS managed services");
             service_manager.list_nssm_services()
         }
-        Commands::Run { .. } => unreachable!(),
+        Commands::Deps { format } => {
+            info!("Printing service dependencies");
+            let graph = service_manager.dependency_graph()?;
+            print!("{}", graph.render(format));
+            graph.check_cycles()
+        }
+        Commands::Export {
+            service_name,
+            format,
+            output,
+        } => {
+            info!("Exporting configuration for service '{service_name}'");
+            let config = service_manager.export_service(&service_name)?;
+            let text = format.serialize(&config)?;
+            match output {
+                Some(path) => std::fs::write(path, text)?,
+                None => print!("{text}"),
+            }
+            Ok(())
+        }
+        Commands::Import {
+            service_name,
+            file,
+            format,
+        } => {
+            info!(
+                "Importing configuration for service '{service_name}' from {}",
+                file.display()
+            );
+            let config = read_config_file(&file, format)?;
+            service_manager.import_service(&service_name, &config)
+        }
+        Commands::Plan {
+            manifest,
+            format,
+            prune,
+        } => {
+            info!("Planning changes for manifest {}", manifest.display());
+            let manifest = Manifest::load(&manifest, format)?;
+            let plan = manifest::plan(&manifest, &service_manager.installed_configs()?, prune);
+            println!("{plan}");
+            Ok(())
+        }
+        Commands::Apply {
+            manifest,
+            format,
+            prune,
+        } => {
+            info!("Applying manifest {}", manifest.display());
+            let manifest = Manifest::load(&manifest, format)?;
+            let plan = manifest::plan(&manifest, &service_manager.installed_configs()?, prune);
+            println!("{plan}");
+            if !plan.has_changes() {
+                return Ok(());
+            }
+            manifest::apply(&plan, service_manager)
+        }
+        Commands::Run { .. } | Commands::Debug { .. } | Commands::RunConfig { .. } => {
+            unreachable!()
+        }
     }
 }
 
-#[cfg(windows)]
 fn init_logging(cli: &Cli) {
     let log_level = if cli.debug {
         log::LevelFilter::Debug
@@ -160,23 +295,12 @@ fn init_logging(cli: &Cli) {
         log::LevelFilter::Warn
     };
 
+    runtime_log::init(cli.log_format, cli.log_dir.clone());
     let mut builder = env_logger::Builder::from_default_env();
-    builder.format(|buf, record| {
-        use std::io::Write;
-
-        let local_time = chrono::Local::now();
-        writeln!(
-            buf,
-            "[{} {} {}:{}] {}",
-            local_time.format("%Y-%m-%d %H:%M:%S"),
-            record.level(),
-            record.file().unwrap_or("unknown"),
-            record.line().unwrap_or(0),
-            record.args()
-        )
-    });
+    builder.format(runtime_log::write_record);
 
     if let Commands::Run { name } = &cli.command {
+        runtime_log::set_service_name(name);
         // When running as a service, stdout only reaches a hidden console;
         // log to a per-service file so launches, exits and stop sequences
         // can actually be diagnosed.
@@ -185,14 +309,28 @@ fn init_logging(cli: &Cli) {
         } else {
             log::LevelFilter::Info
         });
-        match open_service_log_file(name) {
-            Some(file) => {
-                builder.target(env_logger::Target::Pipe(Box::new(file)));
+        // Defaults until the service's own AppLog* settings are loaded.
+        let path = runtime_log::service_log_path(name, None);
+        match runtime_log::open_service_log(&path, DEFAULT_LOG_MAX_BYTES, DEFAULT_LOG_KEEP) {
+            Ok(()) => {
+                builder.target(env_logger::Target::Pipe(Box::new(
+                    runtime_log::ServiceLogWriter,
+                )));
             }
-            None => {
+            Err(_) => {
                 builder.target(env_logger::Target::Stdout);
             }
         }
+    } else if let Some(service_name) = foreground_service_name(&cli.command) {
+        runtime_log::set_service_name(&service_name);
+        // Following the supervisor is the point of running in the
+        // foreground, so its progress is shown without --verbose.
+        builder.filter_level(if cli.debug {
+            log::LevelFilter::Debug
+        } else {
+            log::LevelFilter::Info
+        });
+        builder.target(env_logger::Target::Stdout);
     } else {
         builder.filter_level(log_level);
         builder.target(env_logger::Target::Stdout);
@@ -207,32 +345,3 @@ fn init_logging(cli: &Cli) {
         info!("Verbose mode enabled");
     }
 }
-
-/// Opens %ProgramData%\nssm-rs\logs\<service>.log for appending.
-#[cfg(windows)]
-fn open_service_log_file(service_name: &str) -> Option<std::fs::File> {
-    use std::path::PathBuf;
-
-    let base = std::env::var_os("ProgramData")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"));
-    let dir = base.join("nssm-rs").join("logs");
-    std::fs::create_dir_all(&dir).ok()?;
-
-    let sanitized: String = service_name
-        .chars()
-        .map(|ch| {
-            if matches!(ch, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
-                '_'
-            } else {
-                ch
-            }
-        })
-        .collect();
-
-    std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(dir.join(format!("{sanitized}.log")))
-        .ok()
-}
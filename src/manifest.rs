@@ -0,0 +1,635 @@
+//! Declarative multi-service manifests.
+//!
+//! A manifest maps service names to full configurations. `plan` compares it
+//! parameter by parameter (through `ServiceParameter::read`) with what is
+//! installed, and `apply` carries the plan out through a `Reconcile` target,
+//! which is the `ServiceManager` in production.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ServiceConfig;
+use crate::config_store::{FileFormat, validate_config};
+use crate::error::{AppError, AppResult};
+use crate::parameters::ServiceParameter;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub services: BTreeMap<String, ServiceConfig>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path, format: Option<FileFormat>) -> AppResult<Self> {
+        let format = format
+            .or_else(|| FileFormat::from_path(path))
+            .ok_or_else(|| {
+                AppError::Message(format!(
+                    "Cannot tell the format of '{}'; pass --format json or --format toml",
+                    path.display()
+                ))
+            })?;
+        let manifest: Self = format.deserialize(&std::fs::read_to_string(path)?)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn validate(&self) -> AppResult<()> {
+        let mut seen = BTreeMap::new();
+        for service_name in self.services.keys() {
+            if let Some(other) = seen.insert(key(service_name), service_name) {
+                return Err(AppError::Message(format!(
+                    "Services '{other}' and '{service_name}' in manifest are the same \
+                     service; service names are not case-sensitive"
+                )));
+            }
+        }
+        for (service_name, config) in &self.services {
+            validate_config(config).map_err(|error| {
+                AppError::Message(format!("Service '{service_name}' in manifest: {error}"))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// One parameter whose installed value differs from the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterDiff {
+    pub parameter: ServiceParameter,
+    pub current: String,
+    pub desired: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Add,
+    Change,
+    Remove,
+}
+
+impl ParameterDiff {
+    pub fn kind(&self) -> DiffKind {
+        match (self.current.is_empty(), self.desired.is_empty()) {
+            (true, _) => DiffKind::Add,
+            (false, true) => DiffKind::Remove,
+            (false, false) => DiffKind::Change,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceAction {
+    /// Not installed; the diffs are against a default configuration.
+    Install {
+        config: ServiceConfig,
+        diffs: Vec<ParameterDiff>,
+    },
+    Update {
+        config: ServiceConfig,
+        diffs: Vec<ParameterDiff>,
+    },
+    Unchanged,
+    /// Installed but not in the manifest, and pruning was requested.
+    Remove,
+    /// Installed but not in the manifest; left alone without pruning.
+    Unlisted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedService {
+    pub service_name: String,
+    pub action: ServiceAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub services: Vec<PlannedService>,
+}
+
+/// Where `apply` makes its changes.
+pub trait Reconcile {
+    fn install(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()>;
+
+    fn set(
+        &self,
+        service_name: &str,
+        parameter: ServiceParameter,
+        values: &[String],
+    ) -> AppResult<()>;
+
+    fn remove(&self, service_name: &str) -> AppResult<()>;
+}
+
+pub fn diff_parameters(current: &ServiceConfig, desired: &ServiceConfig) -> Vec<ParameterDiff> {
+    ServiceParameter::ALL
+        .iter()
+        .filter_map(|&parameter| {
+            let current = parameter.read(current);
+            let desired = parameter.read(desired);
+            (current != desired).then_some(ParameterDiff {
+                parameter,
+                current,
+                desired,
+            })
+        })
+        .collect()
+}
+
+/// Service names are not case-sensitive, so they are matched by this key.
+fn key(service_name: &str) -> String {
+    service_name.to_lowercase()
+}
+
+/// Compares a manifest with the installed configurations. Names are
+/// matched case-insensitively, as the SCM does, and an installed service
+/// keeps its installed name. Services are listed in name order.
+pub fn plan(manifest: &Manifest, installed: &BTreeMap<String, ServiceConfig>, prune: bool) -> Plan {
+    let mut services = Vec::new();
+    let installed_by_key: BTreeMap<String, (&String, &ServiceConfig)> = installed
+        .iter()
+        .map(|(service_name, config)| (key(service_name), (service_name, config)))
+        .collect();
+    let listed: BTreeSet<String> = manifest.services.keys().map(|name| key(name)).collect();
+
+    for (service_name, desired) in &manifest.services {
+        let (service_name, action) = match installed_by_key.get(&key(service_name)) {
+            None => (
+                service_name,
+                ServiceAction::Install {
+                    config: desired.clone(),
+                    diffs: diff_parameters(&ServiceConfig::default(), desired),
+                },
+            ),
+            Some(&(installed_name, current)) => {
+                let diffs = diff_parameters(current, desired);
+                let action = if diffs.is_empty() {
+                    ServiceAction::Unchanged
+                } else {
+                    ServiceAction::Update {
+                        config: desired.clone(),
+                        diffs,
+                    }
+                };
+                (installed_name, action)
+            }
+        };
+        services.push(PlannedService {
+            service_name: service_name.clone(),
+            action,
+        });
+    }
+
+    for service_name in installed.keys() {
+        if !listed.contains(&key(service_name)) {
+            services.push(PlannedService {
+                service_name: service_name.clone(),
+                action: if prune {
+                    ServiceAction::Remove
+                } else {
+                    ServiceAction::Unlisted
+                },
+            });
+        }
+    }
+
+    services.sort_by(|a, b| a.service_name.cmp(&b.service_name));
+    Plan { services }
+}
+
+impl Plan {
+    pub fn has_changes(&self) -> bool {
+        self.services.iter().any(|service| {
+            matches!(
+                service.action,
+                ServiceAction::Install { .. }
+                    | ServiceAction::Update { .. }
+                    | ServiceAction::Remove
+            )
+        })
+    }
+
+    fn count(&self, predicate: impl Fn(&ServiceAction) -> bool) -> usize {
+        self.services
+            .iter()
+            .filter(|service| predicate(&service.action))
+            .count()
+    }
+}
+
+/// Carries out every change in the plan. A failing service does not stop
+/// the others; the error lists how many failed.
+pub fn apply(plan: &Plan, target: &dyn Reconcile) -> AppResult<()> {
+    let mut failed = Vec::new();
+
+    for service in &plan.services {
+        let service_name = service.service_name.as_str();
+        let result = match &service.action {
+            ServiceAction::Install { config, .. } => {
+                info!("Installing service '{service_name}'");
+                target.install(service_name, config)
+            }
+            ServiceAction::Update { config, diffs } => {
+                info!(
+                    "Updating {} parameter(s) of service '{service_name}'",
+                    diffs.len()
+                );
+                diffs.iter().try_for_each(|diff| {
+                    target.set(
+                        service_name,
+                        diff.parameter,
+                        &parameter_values(diff.parameter, config),
+                    )
+                })
+            }
+            ServiceAction::Remove => {
+                info!("Removing service '{service_name}'");
+                target.remove(service_name)
+            }
+            ServiceAction::Unchanged | ServiceAction::Unlisted => Ok(()),
+        };
+
+        if let Err(error) = result {
+            error!("Failed to apply changes to service '{service_name}': {error}");
+            failed.push(service_name);
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Message(format!(
+            "Failed to apply changes to {} service(s): {}",
+            failed.len(),
+            failed.join(", ")
+        )))
+    }
+}
+
+/// The `set` arguments that reproduce a parameter's value in `config`.
+fn parameter_values(parameter: ServiceParameter, config: &ServiceConfig) -> Vec<String> {
+    match parameter {
+        _ if let Some(list) = parameter.list(config).filter(|list| !list.is_empty()) => {
+            list.clone()
+        }
+        _ => vec![parameter.read(config)],
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for service in &self.services {
+            let name = &service.service_name;
+            match &service.action {
+                ServiceAction::Install { diffs, .. } => {
+                    writeln!(f, "+ {name}: install")?;
+                    write_diffs(f, diffs)?;
+                }
+                ServiceAction::Update { diffs, .. } => {
+                    writeln!(f, "~ {name}: update")?;
+                    write_diffs(f, diffs)?;
+                }
+                ServiceAction::Unchanged => writeln!(f, "  {name}: unchanged")?,
+                ServiceAction::Remove => writeln!(f, "- {name}: remove")?,
+                ServiceAction::Unlisted => writeln!(
+                    f,
+                    "? {name}: installed but not in the manifest (use --prune to remove)"
+                )?,
+            }
+        }
+
+        write!(
+            f,
+            "Plan: {} to install, {} to update, {} to remove.",
+            self.count(|action| matches!(action, ServiceAction::Install { .. })),
+            self.count(|action| matches!(action, ServiceAction::Update { .. })),
+            self.count(|action| matches!(action, ServiceAction::Remove)),
+        )
+    }
+}
+
+fn write_diffs(f: &mut Formatter<'_>, diffs: &[ParameterDiff]) -> fmt::Result {
+    for diff in diffs {
+        let name = diff.parameter.as_str();
+        let current = display_value(&diff.current);
+        let desired = display_value(&diff.desired);
+        match diff.kind() {
+            DiffKind::Add => writeln!(f, "    + {name} = {desired}")?,
+            DiffKind::Change => writeln!(f, "    ~ {name}: {current} -> {desired}")?,
+            DiffKind::Remove => writeln!(f, "    - {name} (was {current})")?,
+        }
+    }
+    Ok(())
+}
+
+/// Multi-valued parameters read back newline-separated; keep each diff on
+/// one line.
+fn display_value(value: &str) -> String {
+    value.replace('\n', ", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_store::{self, ConfigStore, MemoryStore};
+    use std::path::PathBuf;
+
+    struct StoreTarget<'a>(&'a dyn ConfigStore);
+
+    impl Reconcile for StoreTarget<'_> {
+        fn install(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+            self.0.save(service_name, config)
+        }
+
+        fn set(
+            &self,
+            service_name: &str,
+            parameter: ServiceParameter,
+            values: &[String],
+        ) -> AppResult<()> {
+            config_store::set_parameter(self.0, service_name, parameter, values)
+        }
+
+        fn remove(&self, service_name: &str) -> AppResult<()> {
+            self.0.remove(service_name)
+        }
+    }
+
+    fn installed(store: &dyn ConfigStore) -> BTreeMap<String, ServiceConfig> {
+        store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|name| {
+                let config = store.load(&name).unwrap();
+                (name, config)
+            })
+            .collect()
+    }
+
+    fn app(path: &str) -> ServiceConfig {
+        ServiceConfig {
+            application: PathBuf::from(path),
+            ..Default::default()
+        }
+    }
+
+    fn manifest(toml: &str) -> Manifest {
+        let manifest: Manifest = FileFormat::Toml.deserialize(toml).unwrap();
+        manifest.validate().unwrap();
+        manifest
+    }
+
+    #[test]
+    fn plan_reports_install_update_unchanged_and_unlisted() {
+        let store = MemoryStore::new();
+        store.save("Api", &app(r"C:\api.exe")).unwrap();
+        store.save("Db", &app(r"C:\db.exe")).unwrap();
+        store.save("Old", &app(r"C:\old.exe")).unwrap();
+
+        let manifest = manifest(
+            r#"
+            [services.Api]
+            Application = 'C:\api.exe'
+            AppThrottle = 3000
+
+            [services.Db]
+            Application = 'C:\db.exe'
+
+            [services.Web]
+            Application = 'C:\web.exe'
+            AppExitDefault = "Exit"
+            "#,
+        );
+
+        let plan = plan(&manifest, &installed(&store), false);
+        let actions: Vec<_> = plan
+            .services
+            .iter()
+            .map(|service| (service.service_name.as_str(), &service.action))
+            .collect();
+
+        assert!(matches!(
+            actions[0],
+            ("Api", ServiceAction::Update { diffs, .. })
+                if diffs == &[ParameterDiff {
+                    parameter: ServiceParameter::AppThrottle,
+                    current: "1500".to_string(),
+                    desired: "3000".to_string(),
+                }]
+        ));
+        assert!(matches!(actions[1], ("Db", ServiceAction::Unchanged)));
+        assert!(matches!(actions[2], ("Old", ServiceAction::Unlisted)));
+        assert!(matches!(
+            actions[3],
+            ("Web", ServiceAction::Install { diffs, .. })
+                if diffs.iter().map(|diff| diff.parameter).collect::<Vec<_>>()
+                    == [ServiceParameter::Application, ServiceParameter::AppExitAction]
+        ));
+        assert!(plan.has_changes());
+    }
+
+    #[test]
+    fn prune_turns_unlisted_services_into_removals() {
+        let store = MemoryStore::new();
+        store.save("Old", &app(r"C:\old.exe")).unwrap();
+
+        let plan = plan(&Manifest::default(), &installed(&store), true);
+        assert_eq!(plan.services[0].action, ServiceAction::Remove);
+    }
+
+    #[test]
+    fn service_names_match_case_insensitively() {
+        let store = MemoryStore::new();
+        store.save("Api", &app(r"C:\api.exe")).unwrap();
+
+        let manifest = manifest(
+            r#"
+            [services.api]
+            Application = 'C:\api.exe'
+            AppThrottle = 3000
+            "#,
+        );
+
+        // The listed service is updated under its installed name, and
+        // pruning leaves it alone.
+        let first = plan(&manifest, &installed(&store), true);
+        assert_eq!(first.services.len(), 1);
+        assert!(matches!(
+            &first.services[0],
+            PlannedService {
+                service_name,
+                action: ServiceAction::Update { .. },
+            } if service_name == "Api"
+        ));
+        apply(&first, &StoreTarget(&store)).unwrap();
+
+        assert_eq!(store.list().unwrap(), vec!["Api"]);
+        assert_eq!(store.load("Api").unwrap().app_throttle, 3000);
+        assert!(!plan(&manifest, &installed(&store), true).has_changes());
+    }
+
+    #[test]
+    fn manifest_rejects_names_differing_only_in_case() {
+        let manifest: Manifest = FileFormat::Toml
+            .deserialize(
+                "[services.Api]\nApplication = 'a.exe'\n[services.API]\nApplication = 'b.exe'\n",
+            )
+            .unwrap();
+        let error = manifest.validate().unwrap_err();
+        assert!(error.to_string().contains("not case-sensitive"), "{error}");
+    }
+
+    #[test]
+    fn diff_kinds() {
+        let mut current = app(r"C:\a.exe");
+        current.app_stdout = Some(PathBuf::from(r"C:\out.log"));
+        let mut desired = app(r"C:\a.exe");
+        desired.app_stderr = Some(PathBuf::from(r"C:\err.log"));
+        desired.app_priority = crate::config::ProcessPriority::High;
+
+        let kinds: Vec<_> = diff_parameters(&current, &desired)
+            .iter()
+            .map(|diff| (diff.parameter, diff.kind()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (ServiceParameter::AppPriority, DiffKind::Change),
+                (ServiceParameter::AppStdout, DiffKind::Remove),
+                (ServiceParameter::AppStderr, DiffKind::Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_converges_the_store_on_the_manifest() {
+        let store = MemoryStore::new();
+        let mut api = app(r"C:\api.exe");
+        api.app_stdout = Some(PathBuf::from(r"C:\api.log"));
+        api.app_environment_extra = vec!["OLD=1".to_string()];
+        store.save("Api", &api).unwrap();
+        store.save("Old", &app(r"C:\old.exe")).unwrap();
+
+        let manifest = manifest(
+            r#"
+            [services.Api]
+            Application = 'C:\api.exe'
+            AppParameters = '--port 80 "two words"'
+            AppEnvironmentExtra = ["A=1", "B=2"]
+
+            [services.Web]
+            Application = 'C:\web.exe'
+            Start = "manual"
+            "#,
+        );
+
+        let first = plan(&manifest, &installed(&store), true);
+        apply(&first, &StoreTarget(&store)).unwrap();
+
+        assert_eq!(store.list().unwrap(), vec!["Api", "Web"]);
+        let api = store.load("Api").unwrap();
+        assert_eq!(api.app_stdout, None);
+        assert_eq!(api.app_environment_extra, vec!["A=1", "B=2"]);
+        assert_eq!(
+            api.app_parameters.as_deref(),
+            Some("--port 80 \"two words\"")
+        );
+
+        let second = plan(&manifest, &installed(&store), true);
+        assert!(!second.has_changes(), "{second}");
+    }
+
+    #[test]
+    fn apply_continues_past_failing_services() {
+        struct Failing;
+
+        impl Reconcile for Failing {
+            fn install(&self, service_name: &str, _: &ServiceConfig) -> AppResult<()> {
+                Err(AppError::Message(format!("cannot install {service_name}")))
+            }
+
+            fn set(&self, _: &str, _: ServiceParameter, _: &[String]) -> AppResult<()> {
+                Ok(())
+            }
+
+            fn remove(&self, _: &str) -> AppResult<()> {
+                Ok(())
+            }
+        }
+
+        let manifest = manifest(
+            r#"
+            [services.A]
+            Application = 'a.exe'
+            [services.B]
+            Application = 'b.exe'
+            "#,
+        );
+        let error = apply(&plan(&manifest, &BTreeMap::new(), false), &Failing).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to apply changes to 2 service(s): A, B"
+        );
+    }
+
+    #[test]
+    fn plan_display_shows_each_change() {
+        let store = MemoryStore::new();
+        let mut api = app(r"C:\api.exe");
+        api.app_stdout = Some(PathBuf::from(r"C:\api.log"));
+        store.save("Api", &api).unwrap();
+        store.save("Old", &app(r"C:\old.exe")).unwrap();
+
+        let manifest = manifest(
+            r#"
+            [services.Api]
+            Application = 'C:\api.exe'
+            AppThrottle = 3000
+            AppEnvironmentExtra = ["A=1", "B=2"]
+            "#,
+        );
+
+        let text = plan(&manifest, &installed(&store), true).to_string();
+        assert_eq!(
+            text,
+            "~ Api: update\n\
+             \x20   ~ APPTHROTTLE: 1500 -> 3000\n\
+             \x20   - APPSTDOUT (was C:\\api.log)\n\
+             \x20   + APPENVIRONMENTEXTRA = A=1, B=2\n\
+             - Old: remove\n\
+             Plan: 0 to install, 1 to update, 1 to remove."
+        );
+    }
+
+    #[test]
+    fn manifest_rejects_invalid_services() {
+        let manifest: Manifest = FileFormat::Toml
+            .deserialize("[services.Broken]\nAppThrottle = 1\n")
+            .unwrap();
+        let error = manifest.validate().unwrap_err();
+        assert!(error.to_string().contains("Broken"), "{error}");
+    }
+
+    #[test]
+    fn manifest_rejects_misspelt_service_settings() {
+        let error = FileFormat::Toml
+            .deserialize::<Manifest>("[services.Web]\nApplication = 'a.exe'\nAppThrotle = 3000\n")
+            .unwrap_err();
+        assert!(error.to_string().contains("AppThrotle"), "{error}");
+    }
+
+    #[test]
+    fn manifest_rejects_unknown_top_level_keys() {
+        assert!(
+            FileFormat::Toml
+                .deserialize::<Manifest>("[service.Typo]\nApplication = 'a.exe'\n")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,642 @@
+//! The notify protocol: how a wrapped application talks back to the
+//! supervisor, in the style of systemd's sd_notify.
+//!
+//! When AppNotify is set, AppReadyCheck is `notify` or AppWatchdogTimeout
+//! is set, every launch gets its own endpoint, named in the application's
+//! NOTIFY_SOCKET environment variable: a named pipe on Windows, a Unix
+//! datagram socket elsewhere. The application writes newline-separated
+//! `KEY=VALUE` assignments to it:
+//!
+//! - `READY=1`: start-up is complete; meets a `notify` AppReadyCheck.
+//! - `STATUS=<text>`: a one-line status, shown by `nssm-rs status`.
+//! - `WATCHDOG=1`: the application is alive; with AppWatchdogTimeout set
+//!   a missing ping gets it restarted.
+//! - `STOPPING=1`: the application is shutting down on its own; the
+//!   watchdog is no longer enforced.
+//!
+//! Anything else is ignored, so sd_notify clients work unchanged.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::config::ServiceConfig;
+use crate::runtime_log;
+use crate::supervisor::Event;
+
+/// The environment variable naming the endpoint.
+pub const NOTIFY_SOCKET_VAR: &str = "NOTIFY_SOCKET";
+
+/// The environment variable holding AppWatchdogTimeout in microseconds,
+/// as sd_watchdog_enabled() expects.
+pub const WATCHDOG_USEC_VAR: &str = "WATCHDOG_USEC";
+
+/// The longest message read in one go; sd_notify messages are far
+/// shorter.
+const MAX_MESSAGE: usize = 4096;
+
+/// Numbers the endpoints this process opens; launch generations repeat
+/// when several supervisors share a process, as in tests.
+static ENDPOINTS: AtomicU32 = AtomicU32::new(0);
+
+/// One assignment from the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    Ready,
+    Status(String),
+    Watchdog,
+    Stopping,
+}
+
+/// Parses a message into the assignments the supervisor understands.
+pub fn parse_message(message: &[u8]) -> Vec<Notification> {
+    String::from_utf8_lossy(message)
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            match (key.trim(), value.trim_end_matches('\r')) {
+                ("READY", "1") => Some(Notification::Ready),
+                ("STATUS", text) => Some(Notification::Status(text.to_string())),
+                ("WATCHDOG", "1") => Some(Notification::Watchdog),
+                ("STOPPING", "1") => Some(Notification::Stopping),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// What a notification changed, for the supervisor to act on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Update {
+    /// The first `READY=1` of this launch.
+    Ready,
+    /// A status different from the last one.
+    Status(String),
+    /// The first `STOPPING=1` of this launch.
+    Stopping,
+    Unchanged,
+}
+
+/// What the application from one launch has told the supervisor.
+#[derive(Debug)]
+pub struct NotifyState {
+    ready: bool,
+    stopping: bool,
+    status: Option<String>,
+    /// AppWatchdogTimeout, if enabled.
+    watchdog: Option<Duration>,
+    /// The launch, until the first `WATCHDOG=1`.
+    last_ping: Instant,
+}
+
+impl NotifyState {
+    pub fn new(watchdog: Option<Duration>, launched: Instant) -> Self {
+        Self {
+            ready: false,
+            stopping: false,
+            status: None,
+            watchdog,
+            last_ping: launched,
+        }
+    }
+
+    pub fn apply(&mut self, notification: Notification, now: Instant) -> Update {
+        match notification {
+            Notification::Ready if !self.ready => {
+                self.ready = true;
+                Update::Ready
+            }
+            Notification::Status(text) if self.status.as_ref() != Some(&text) => {
+                self.status = Some(text.clone());
+                Update::Status(text)
+            }
+            Notification::Watchdog => {
+                self.last_ping = self.last_ping.max(now);
+                Update::Unchanged
+            }
+            Notification::Stopping if !self.stopping => {
+                self.stopping = true;
+                Update::Stopping
+            }
+            _ => Update::Unchanged,
+        }
+    }
+
+    /// When the application is overdue for a `WATCHDOG=1`, or `None` if
+    /// the watchdog is disabled or the application is stopping.
+    pub fn watchdog_deadline(&self) -> Option<Instant> {
+        self.watchdog
+            .filter(|_| !self.stopping)
+            .map(|timeout| self.last_ping + timeout)
+    }
+}
+
+/// Where the application's latest STATUS is kept for `nssm-rs status`:
+/// beside the runtime log, as `<service>.status`.
+pub fn status_path(service_name: &str, config: &ServiceConfig) -> PathBuf {
+    status_path_beside(&runtime_log::service_log_path(
+        service_name,
+        config.app_log_directory.as_deref(),
+    ))
+}
+
+/// The status file kept beside the runtime log at `log_path`.
+pub fn status_path_beside(log_path: &Path) -> PathBuf {
+    log_path.with_extension("status")
+}
+
+/// The status the running application last reported, if any.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn read_status(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|status| status.trim_end().to_string())
+        .filter(|status| !status.is_empty())
+}
+
+/// The endpoint of one launch, with what the application has said
+/// through it. Dropping it closes the endpoint and removes the status
+/// file.
+pub struct NotifyChannel {
+    listener: Listener,
+    pub state: NotifyState,
+    status_path: PathBuf,
+}
+
+impl NotifyChannel {
+    /// Opens the endpoint for launch `generation`; notifications arrive
+    /// as `Event::Notify`.
+    pub fn open(
+        config: &ServiceConfig,
+        status_path: PathBuf,
+        events: mpsc::Sender<Event>,
+        generation: u32,
+    ) -> io::Result<Self> {
+        // A status from the previous launch no longer applies.
+        let _ = fs::remove_file(&status_path);
+        let watchdog = (config.app_watchdog_timeout > 0)
+            .then(|| Duration::from_millis(u64::from(config.app_watchdog_timeout)));
+        Ok(Self {
+            listener: Listener::start(events, generation)?,
+            state: NotifyState::new(watchdog, Instant::now()),
+            status_path,
+        })
+    }
+
+    /// The value of NOTIFY_SOCKET for the application.
+    pub fn address(&self) -> &str {
+        &self.listener.address
+    }
+
+    /// Records a notification, publishing a new status for `nssm-rs
+    /// status`.
+    pub fn apply(&mut self, notification: Notification) -> Update {
+        let update = self.state.apply(notification, Instant::now());
+        if let Update::Status(text) = &update
+            && let Err(error) = fs::write(&self.status_path, text)
+        {
+            warn!(
+                "Failed to write the application status to {}: {error}",
+                self.status_path.display()
+            );
+        }
+        update
+    }
+}
+
+impl Drop for NotifyChannel {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.status_path);
+    }
+}
+
+/// Reads messages from the endpoint on a thread of its own until dropped.
+struct Listener {
+    address: String,
+    stop: Arc<AtomicBool>,
+}
+
+impl Listener {
+    fn start(events: mpsc::Sender<Event>, generation: u32) -> io::Result<Self> {
+        let name = format!(
+            "nssm-rs-notify-{}-{}",
+            std::process::id(),
+            ENDPOINTS.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut endpoint = platform::Endpoint::bind(&name)?;
+        let address = endpoint.address();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            loop {
+                let message = match endpoint.receive() {
+                    Ok(message) => message,
+                    Err(error) => {
+                        warn!("Notify endpoint failed: {error}");
+                        return;
+                    }
+                };
+                if stopped.load(Ordering::Acquire) {
+                    return;
+                }
+                for notification in parse_message(&message) {
+                    if events
+                        .send(Event::Notify {
+                            generation,
+                            notification,
+                        })
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self { address, stop })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        platform::wake(&self.address);
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::fs;
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+
+    use super::MAX_MESSAGE;
+
+    /// A datagram socket in the temporary directory; each datagram is one
+    /// message.
+    pub struct Endpoint {
+        socket: UnixDatagram,
+        path: PathBuf,
+    }
+
+    impl Endpoint {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            let path = std::env::temp_dir().join(format!("{name}.sock"));
+            let _ = fs::remove_file(&path);
+            let socket = UnixDatagram::bind(&path)?;
+            Ok(Self { socket, path })
+        }
+
+        pub fn address(&self) -> String {
+            self.path.display().to_string()
+        }
+
+        pub fn receive(&mut self) -> io::Result<Vec<u8>> {
+            let mut buffer = vec![0; MAX_MESSAGE];
+            let read = self.socket.recv(&mut buffer)?;
+            buffer.truncate(read);
+            Ok(buffer)
+        }
+    }
+
+    /// Unblocks `receive` with an empty message and removes the socket
+    /// file; the queued message is still delivered.
+    pub fn wake(address: &str) {
+        if let Ok(socket) = UnixDatagram::unbound() {
+            let _ = socket.send_to(b"", address);
+        }
+        let _ = fs::remove_file(address);
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use windows::Win32::Foundation::{
+        CloseHandle, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED,
+        GENERIC_WRITE, HANDLE, WAIT_TIMEOUT,
+    };
+    use windows::Win32::Storage::FileSystem::{
+        CreateFileW, FILE_FLAG_OVERLAPPED, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_NONE,
+        OPEN_EXISTING, PIPE_ACCESS_INBOUND, ReadFile,
+    };
+    use windows::Win32::System::IO::{
+        CancelIoEx, GetOverlappedResult, GetOverlappedResultEx, OVERLAPPED,
+    };
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+        PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+    use windows::Win32::System::Threading::{CreateEventW, INFINITE};
+    use windows::core::{HRESULT, PCWSTR};
+
+    use super::MAX_MESSAGE;
+
+    /// How long a connected client has to send its message and close the
+    /// pipe; whatever it sent by then is taken as the message.
+    const MESSAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// A named pipe; each client connection is one message. The next
+    /// instance is created as soon as a client connects, so one is always
+    /// waiting. I/O is overlapped so a client that connects and then goes
+    /// quiet cannot hold up the listener.
+    pub struct Endpoint {
+        name: Vec<u16>,
+        address: String,
+        waiting: HANDLE,
+        /// Signals completion of the one overlapped operation in flight.
+        event: HANDLE,
+    }
+
+    // The handle is only used by the listener thread that owns it.
+    unsafe impl Send for Endpoint {}
+
+    impl Endpoint {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            let address = format!(r"\\.\pipe\{name}");
+            let name: Vec<u16> = address.encode_utf16().chain(Some(0)).collect();
+            let event = unsafe { CreateEventW(None, true, false, PCWSTR::null()) }?;
+            let waiting = match create_instance(&name) {
+                Ok(waiting) => waiting,
+                Err(error) => {
+                    unsafe {
+                        let _ = CloseHandle(event);
+                    }
+                    return Err(error);
+                }
+            };
+            Ok(Self {
+                name,
+                address,
+                waiting,
+                event,
+            })
+        }
+
+        pub fn address(&self) -> String {
+            self.address.clone()
+        }
+
+        pub fn receive(&mut self) -> io::Result<Vec<u8>> {
+            // Waiting for a client has no deadline; `wake` connects one.
+            let waiting = self.waiting;
+            match complete(waiting, self.event, INFINITE, |overlapped| unsafe {
+                ConnectNamedPipe(waiting, Some(overlapped))
+            }) {
+                Ok(_) => {}
+                Err(error) if error.code() == ERROR_PIPE_CONNECTED.to_hresult() => {}
+                Err(error) => return Err(error.into()),
+            }
+            let connected = std::mem::replace(&mut self.waiting, create_instance(&self.name)?);
+
+            let deadline = Instant::now() + MESSAGE_TIMEOUT;
+            let mut message = Vec::new();
+            let mut buffer = [0u8; 512];
+            while message.len() < MAX_MESSAGE {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                // A zero wait would not cancel a pending read, so wait at
+                // least 1ms.
+                let timeout = u32::try_from(remaining.as_millis())
+                    .unwrap_or(INFINITE - 1)
+                    .max(1);
+                match complete(connected, self.event, timeout, |overlapped| unsafe {
+                    ReadFile(connected, Some(&mut buffer), None, Some(overlapped))
+                }) {
+                    Ok(read) if read > 0 => message.extend_from_slice(&buffer[..read as usize]),
+                    Ok(_) => break,
+                    Err(error)
+                        if error.code() == ERROR_BROKEN_PIPE.to_hresult()
+                            || error.code() == HRESULT::from_win32(WAIT_TIMEOUT.0) =>
+                    {
+                        break;
+                    }
+                    Err(error) => {
+                        close(connected);
+                        return Err(error.into());
+                    }
+                }
+            }
+            close(connected);
+            Ok(message)
+        }
+    }
+
+    impl Drop for Endpoint {
+        fn drop(&mut self) {
+            close(self.waiting);
+            unsafe {
+                let _ = CloseHandle(self.event);
+            }
+        }
+    }
+
+    /// Starts an overlapped operation on `pipe` and waits up to
+    /// `timeout_ms` for it, returning the bytes transferred. One still
+    /// pending then is cancelled, failing with WAIT_TIMEOUT.
+    fn complete(
+        pipe: HANDLE,
+        event: HANDLE,
+        timeout_ms: u32,
+        start: impl FnOnce(*mut OVERLAPPED) -> windows::core::Result<()>,
+    ) -> windows::core::Result<u32> {
+        let mut overlapped = OVERLAPPED {
+            hEvent: event,
+            ..Default::default()
+        };
+        if let Err(error) = start(&mut overlapped)
+            && error.code() != ERROR_IO_PENDING.to_hresult()
+        {
+            return Err(error);
+        }
+        let mut transferred = 0u32;
+        let result = unsafe {
+            GetOverlappedResultEx(pipe, &overlapped, &mut transferred, timeout_ms, false)
+        };
+        if let Err(error) = &result
+            && error.code() == HRESULT::from_win32(WAIT_TIMEOUT.0)
+        {
+            // The operation must be over before `overlapped` goes away.
+            unsafe {
+                let _ = CancelIoEx(pipe, Some(&overlapped));
+                let _ = GetOverlappedResult(pipe, &overlapped, &mut transferred, true);
+            }
+        }
+        result.map(|()| transferred)
+    }
+
+    fn create_instance(name: &[u16]) -> io::Result<HANDLE> {
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                PCWSTR(name.as_ptr()),
+                PIPE_ACCESS_INBOUND | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                0,
+                MAX_MESSAGE as u32,
+                0,
+                None,
+            )
+        };
+        if pipe.is_invalid() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(pipe)
+    }
+
+    fn close(pipe: HANDLE) {
+        unsafe {
+            let _ = DisconnectNamedPipe(pipe);
+            let _ = CloseHandle(pipe);
+        }
+    }
+
+    /// Unblocks `receive` by connecting without writing anything.
+    pub fn wake(address: &str) {
+        let name: Vec<u16> = address.encode_utf16().chain(Some(0)).collect();
+        // Between a client connecting and the next instance being created
+        // the pipe is briefly busy.
+        for _ in 0..5 {
+            match unsafe {
+                CreateFileW(
+                    PCWSTR(name.as_ptr()),
+                    GENERIC_WRITE.0,
+                    FILE_SHARE_NONE,
+                    None,
+                    OPEN_EXISTING,
+                    FILE_FLAGS_AND_ATTRIBUTES(0),
+                    None,
+                )
+            } {
+                Ok(pipe) => {
+                    unsafe {
+                        let _ = CloseHandle(pipe);
+                    }
+                    return;
+                }
+                Err(error) if error.code() == ERROR_PIPE_BUSY.to_hresult() => {
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_parse_into_known_assignments() {
+        assert_eq!(
+            parse_message(b"READY=1\nSTATUS=Loading cache (3/5)\nMAINPID=42\nWATCHDOG=1\n"),
+            vec![
+                Notification::Ready,
+                Notification::Status("Loading cache (3/5)".to_string()),
+                Notification::Watchdog,
+            ]
+        );
+        assert_eq!(parse_message(b"STOPPING=1"), vec![Notification::Stopping]);
+        // Only `=1` counts for the flags; unknown keys and junk are ignored.
+        assert!(parse_message(b"READY=0\nWATCHDOG=trigger\nnonsense\n").is_empty());
+        assert_eq!(
+            parse_message(b"STATUS=a=b\r\n"),
+            vec![Notification::Status("a=b".to_string())]
+        );
+    }
+
+    #[test]
+    fn state_reports_each_change_once() {
+        let launched = Instant::now();
+        let mut state = NotifyState::new(None, launched);
+        assert_eq!(state.apply(Notification::Ready, launched), Update::Ready);
+        assert_eq!(
+            state.apply(Notification::Ready, launched),
+            Update::Unchanged
+        );
+        let status = || Notification::Status("Serving".to_string());
+        assert_eq!(
+            state.apply(status(), launched),
+            Update::Status("Serving".to_string())
+        );
+        assert_eq!(state.apply(status(), launched), Update::Unchanged);
+        assert_eq!(
+            state.apply(Notification::Stopping, launched),
+            Update::Stopping
+        );
+        assert_eq!(
+            state.apply(Notification::Stopping, launched),
+            Update::Unchanged
+        );
+        assert_eq!(state.watchdog_deadline(), None);
+    }
+
+    #[test]
+    fn watchdog_pings_push_the_deadline_back() {
+        let launched = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut state = NotifyState::new(Some(timeout), launched);
+        assert_eq!(state.watchdog_deadline(), Some(launched + timeout));
+
+        let pinged = launched + Duration::from_secs(3);
+        state.apply(Notification::Watchdog, pinged);
+        assert_eq!(state.watchdog_deadline(), Some(pinged + timeout));
+        // A stopping application is not expected to keep pinging.
+        state.apply(Notification::Stopping, pinged);
+        assert_eq!(state.watchdog_deadline(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn datagrams_arrive_as_events_until_the_channel_is_dropped() {
+        use std::os::unix::net::UnixDatagram;
+
+        let directory = crate::config_store::tests::TempDir::new("notify");
+        let status_path = directory.0.join("test.status");
+        let config = ServiceConfig::default();
+        let (sender, receiver) = mpsc::channel();
+        let mut channel = NotifyChannel::open(&config, status_path.clone(), sender, 7).unwrap();
+
+        let client = UnixDatagram::unbound().unwrap();
+        client
+            .send_to(b"STATUS=Warming up\nREADY=1", channel.address())
+            .unwrap();
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match receiver.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::Notify {
+                    generation: 7,
+                    notification,
+                } => received.push(notification),
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                Notification::Status("Warming up".to_string()),
+                Notification::Ready
+            ]
+        );
+
+        for notification in received {
+            channel.apply(notification);
+        }
+        assert_eq!(read_status(&status_path).as_deref(), Some("Warming up"));
+
+        let address = channel.address().to_string();
+        drop(channel);
+        assert!(read_status(&status_path).is_none());
+        assert!(!Path::new(&address).exists());
+    }
+}
@@ -0,0 +1,1374 @@
+//! Child stdout/stderr redirection files and their NSSM-style rotation.
+//!
+//! Rotated files are renamed next to the original with a timestamp before
+//! the extension, e.g. `out.log` becomes `out-20250102T030405.678.log`.
+//! With AppRotateCompress they are then compressed on a background thread
+//! to `out-20250102T030405.678.log.gz` (or `.zst`).
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use log::{error, info, warn};
+
+use crate::config::{DEFAULT_TIMESTAMP_FORMAT, RotateCompression, ServiceConfig};
+
+/// AppRotateFiles together with its thresholds and retention limits. A
+/// zero threshold or limit is disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// AppRotateOnline: also rotate while the application is running.
+    pub online: bool,
+    pub seconds: u32,
+    pub bytes: u64,
+    /// AppRotateKeep: number of rotated files to keep.
+    pub keep: u32,
+    /// AppRotateMaxAge: seconds after rotation before a file is deleted.
+    pub max_age: u32,
+    pub compress: RotateCompression,
+}
+
+impl RotationPolicy {
+    pub fn from_config(config: &ServiceConfig) -> Option<Self> {
+        config.app_rotate_files.then_some(Self {
+            online: config.app_rotate_online,
+            seconds: config.app_rotate_seconds,
+            bytes: config.app_rotate_bytes,
+            keep: config.app_rotate_keep,
+            max_age: config.app_rotate_max_age,
+            compress: config.app_rotate_compress,
+        })
+    }
+
+    fn online_rotation_due(&self, size: u64, age: Duration) -> bool {
+        self.online
+            && ((self.bytes > 0 && size >= self.bytes)
+                || (self.seconds > 0 && age >= Duration::from_secs(u64::from(self.seconds))))
+    }
+}
+
+/// Rotates an existing, non-empty file before the application is launched.
+///
+/// As in NSSM, the file is only rotated if it was last written more than
+/// `seconds` ago and is at least `bytes` long.
+pub fn rotate_at_launch(path: &Path, policy: &RotationPolicy) -> io::Result<Option<PathBuf>> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    if metadata.len() == 0 {
+        return Ok(None);
+    }
+    if policy.seconds > 0 {
+        let age = metadata.modified()?.elapsed().unwrap_or_default();
+        if age < Duration::from_secs(u64::from(policy.seconds)) {
+            return Ok(None);
+        }
+    }
+    if policy.bytes > 0 && metadata.len() < policy.bytes {
+        return Ok(None);
+    }
+
+    rotate(path).map(Some)
+}
+
+/// Renames `path` to its timestamped name and returns that name.
+pub fn rotate(path: &Path) -> io::Result<PathBuf> {
+    let target = rotated_path(path, Local::now());
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Deletes rotated siblings of `path` beyond the newest `keep` and those
+/// rotated more than `max_age` seconds before `now`, along with any
+/// `.partial` archive left by a compression that was cut short (e.g. the
+/// supervisor being killed). Returns what was deleted.
+///
+/// Retention runs on the compression worker after its job, or where there
+/// is no worker, so no `.partial` file is being written at this point.
+pub fn prune_rotated(
+    path: &Path,
+    keep: u32,
+    max_age: u32,
+    now: DateTime<Local>,
+) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for partial in partial_siblings(path)? {
+        fs::remove_file(&partial)?;
+        removed.push(partial);
+    }
+    if keep == 0 && max_age == 0 {
+        return Ok(removed);
+    }
+
+    let mut siblings = rotated_siblings(path)?;
+    // Newest first, so everything past `keep` is surplus.
+    siblings.reverse();
+    let oldest_kept = now.naive_local() - chrono::TimeDelta::seconds(i64::from(max_age));
+
+    for (index, (rotated_at, sibling)) in siblings.into_iter().enumerate() {
+        let surplus = keep > 0 && index >= keep as usize;
+        let expired = max_age > 0 && rotated_at < oldest_kept;
+        if surplus || expired {
+            fs::remove_file(&sibling)?;
+            removed.push(sibling);
+        }
+    }
+    Ok(removed)
+}
+
+/// Files named by `rotated_path` for `path`, oldest first, with the time
+/// taken from their names.
+pub fn rotated_siblings(path: &Path) -> io::Result<Vec<(NaiveDateTime, PathBuf)>> {
+    let (directory, stem, extension) = sibling_pattern(path);
+    let mut siblings = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Some(key) = parse_rotated_name(&name.to_string_lossy(), &stem, &extension) {
+            siblings.push((key, entry.path()));
+        }
+    }
+    siblings.sort();
+    Ok(siblings
+        .into_iter()
+        .map(|((rotated_at, _), path)| (rotated_at, path))
+        .collect())
+}
+
+/// Unfinished `<rotated>.gz.partial` or `<rotated>.zst.partial` archives
+/// next to `path`.
+fn partial_siblings(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let (directory, stem, extension) = sibling_pattern(path);
+    let mut partials = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let is_partial = name.strip_suffix(".partial").is_some_and(|archive| {
+            COMPRESSED_SUFFIXES.iter().any(|suffix| {
+                archive
+                    .strip_suffix(suffix)
+                    .and_then(|rotated| parse_uncompressed_rotated_name(rotated, &stem, &extension))
+                    .is_some()
+            })
+        });
+        if is_partial {
+            partials.push(entry.path());
+        }
+    }
+    partials.sort();
+    Ok(partials)
+}
+
+/// The directory, stem and `.extension` (possibly empty) that rotated
+/// names for `path` are built from.
+fn sibling_pattern(path: &Path) -> (&Path, String, String) {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (directory, stem, extension)
+}
+
+/// Parses `stem-YYYYMMDDTHHMMSS.mmm[-N]extension`, optionally followed by
+/// a compression suffix, into the rotation time and the collision counter.
+fn parse_rotated_name(name: &str, stem: &str, extension: &str) -> Option<(NaiveDateTime, u32)> {
+    parse_uncompressed_rotated_name(name, stem, extension).or_else(|| {
+        COMPRESSED_SUFFIXES.iter().find_map(|suffix| {
+            parse_uncompressed_rotated_name(name.strip_suffix(suffix)?, stem, extension)
+        })
+    })
+}
+
+fn parse_uncompressed_rotated_name(
+    name: &str,
+    stem: &str,
+    extension: &str,
+) -> Option<(NaiveDateTime, u32)> {
+    let middle = name
+        .strip_prefix(stem)?
+        .strip_prefix('-')?
+        .strip_suffix(extension)?;
+    let (stamp, counter) = match middle.split_once('-') {
+        Some((stamp, counter)) => (stamp, counter.parse().ok()?),
+        None => (middle, 0),
+    };
+    if stamp.len() != ROTATION_STAMP_LEN {
+        return None;
+    }
+    let rotated_at = NaiveDateTime::parse_from_str(stamp, ROTATION_STAMP_FORMAT).ok()?;
+    Some((rotated_at, counter))
+}
+
+const ROTATION_STAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+const ROTATION_STAMP_LEN: usize = "YYYYMMDDTHHMMSS.mmm".len();
+const COMPRESSED_SUFFIXES: [&str; 2] = [".gz", ".zst"];
+
+/// Compresses `path` to `path.gz` or `path.zst` and deletes `path`,
+/// returning the compressed file. The output is written under a temporary
+/// name first so a half-written archive is never mistaken for a rotated
+/// file.
+pub fn compress_file(path: &Path, compression: RotateCompression) -> io::Result<PathBuf> {
+    let Some(suffix) = compression.extension() else {
+        return Ok(path.to_path_buf());
+    };
+    let target = append_extension(path, suffix);
+    let partial = append_extension(&target, "partial");
+
+    let written =
+        write_compressed(path, &partial, compression).and_then(|()| fs::rename(&partial, &target));
+    if let Err(error) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(error);
+    }
+    fs::remove_file(path)?;
+    Ok(target)
+}
+
+fn write_compressed(
+    source: &Path,
+    target: &Path,
+    compression: RotateCompression,
+) -> io::Result<()> {
+    let mut output = File::create(target)?;
+    match compression {
+        RotateCompression::None => return Ok(()),
+        RotateCompression::Gzip => {
+            let mut input = File::open(source)?;
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+        }
+        RotateCompression::Zstd => {
+            // ruzstd's encoder streams block by block but panics on I/O
+            // errors, so they are held back and reported afterwards.
+            let mut input = LatchedIo::new(File::open(source)?);
+            let mut encoded = LatchedIo::new(&mut output);
+            ruzstd::encoding::compress(
+                &mut input,
+                &mut encoded,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            );
+            if let Some(error) = input.error.or(encoded.error) {
+                return Err(error);
+            }
+        }
+    }
+    output.sync_all()
+}
+
+/// Wraps a reader or writer so that its first error is kept instead of
+/// returned: reads then see end of input and writes are dropped.
+struct LatchedIo<T> {
+    inner: T,
+    error: Option<io::Error>,
+}
+
+impl<T> LatchedIo<T> {
+    fn new(inner: T) -> Self {
+        Self { inner, error: None }
+    }
+}
+
+impl<T: Read> Read for LatchedIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.error.is_none() {
+            match self.inner.read(buf) {
+                Ok(read) => return Ok(read),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => self.error = Some(error),
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl<T: Write> Write for LatchedIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.error.is_none()
+            && let Err(error) = self.inner.write_all(buf)
+        {
+            self.error = Some(error);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.error.is_none()
+            && let Err(error) = self.inner.flush()
+        {
+            self.error = Some(error);
+        }
+        Ok(())
+    }
+}
+
+fn append_extension(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// `dir/name.ext` becomes `dir/name-YYYYMMDDTHHMMSS.mmm.ext`, with a
+/// counter appended if that name is already taken.
+pub fn rotated_path(path: &Path, time: DateTime<Local>) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let stamp = time.format(ROTATION_STAMP_FORMAT);
+
+    let mut candidate = path.with_file_name(format!("{stem}-{stamp}{extension}"));
+    let mut counter = 1;
+    while candidate.exists() {
+        candidate = path.with_file_name(format!("{stem}-{stamp}-{counter}{extension}"));
+        counter += 1;
+    }
+    candidate
+}
+
+/// Whether two redirection paths name the same file, so stdout and stderr
+/// must share one writer.
+pub fn same_file_path(a: &Path, b: &Path) -> bool {
+    let (Ok(a), Ok(b)) = (std::path::absolute(a), std::path::absolute(b)) else {
+        return a == b;
+    };
+    if cfg!(windows) {
+        a.to_string_lossy()
+            .eq_ignore_ascii_case(&b.to_string_lossy())
+    } else {
+        a == b
+    }
+}
+
+/// An append-mode redirection file that rotates itself when online
+/// rotation is enabled.
+pub struct OutputFile {
+    path: PathBuf,
+    /// `None` once a failed rotation left nowhere safe to write.
+    file: Option<File>,
+    size: u64,
+    opened_at: Instant,
+    policy: Option<RotationPolicy>,
+}
+
+pub type SharedOutputFile = Arc<Mutex<OutputFile>>;
+
+impl OutputFile {
+    /// Opens `path` for appending, first rotating it if it is due.
+    pub fn open(path: &Path, policy: Option<RotationPolicy>) -> io::Result<Self> {
+        if let Some(policy) = &policy {
+            match rotate_at_launch(path, policy) {
+                Ok(Some(rotated)) => {
+                    info!("Rotated {} to {}", path.display(), rotated.display());
+                    finish_rotation(path, rotated, policy);
+                }
+                Ok(None) => {}
+                Err(error) => warn!("Failed to rotate {}: {error}", path.display()),
+            }
+        }
+
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Some(file),
+            size,
+            opened_at: Instant::now(),
+            policy,
+        })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.write_all(chunk)?;
+        self.size += chunk.len() as u64;
+
+        if self
+            .policy
+            .is_some_and(|policy| policy.online_rotation_due(self.size, self.opened_at.elapsed()))
+        {
+            self.rotate_now();
+        }
+        Ok(())
+    }
+
+    /// Rotation failures (e.g. another process holding the file without
+    /// delete sharing) are logged and retried after another full period;
+    /// output keeps going to the current file.
+    fn rotate_now(&mut self) {
+        match rotate(&self.path) {
+            Ok(rotated) => match open_append(&self.path) {
+                Ok(file) => {
+                    self.file = Some(file);
+                    info!("Rotated {} to {}", self.path.display(), rotated.display());
+                    if let Some(policy) = &self.policy {
+                        finish_rotation(&self.path, rotated, policy);
+                    }
+                }
+                Err(error) => self.undo_rotation(&rotated, &error),
+            },
+            Err(error) => warn!("Failed to rotate {}: {error}", self.path.display()),
+        }
+        self.size = 0;
+        self.opened_at = Instant::now();
+    }
+
+    /// The file was renamed but could not be reopened. The old handle now
+    /// writes to the rotated file, which retention or compression may take
+    /// away, so the rename is undone; failing that, output is dropped.
+    fn undo_rotation(&mut self, rotated: &Path, error: &io::Error) {
+        match fs::rename(rotated, &self.path) {
+            Ok(()) => warn!(
+                "Failed to reopen {} after rotating it ({error}); rotation undone",
+                self.path.display()
+            ),
+            Err(rename_error) => {
+                error!(
+                    "Failed to reopen {} after rotating it to {} ({error}) or to rename it \
+                     back ({rename_error}); discarding output until the application restarts",
+                    self.path.display(),
+                    rotated.display()
+                );
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// Compresses and prunes after a rotation. Compression is handed to the
+/// background worker, which applies retention once the archive exists;
+/// the pump thread calling this must not block on it.
+fn finish_rotation(path: &Path, rotated: PathBuf, policy: &RotationPolicy) {
+    if policy.compress == RotateCompression::None {
+        apply_retention(path, policy);
+        return;
+    }
+
+    let job = CompressJob {
+        path: path.to_path_buf(),
+        rotated,
+        policy: *policy,
+    };
+    if let Err(mpsc::SendError(job)) = compressor().send(job) {
+        warn!(
+            "Compression worker unavailable; leaving {} uncompressed",
+            job.rotated.display()
+        );
+        apply_retention(&job.path, &job.policy);
+    }
+}
+
+struct CompressJob {
+    path: PathBuf,
+    rotated: PathBuf,
+    policy: RotationPolicy,
+}
+
+impl CompressJob {
+    fn run(self) {
+        match compress_file(&self.rotated, self.policy.compress) {
+            Ok(compressed) => info!(
+                "Compressed {} to {}",
+                self.rotated.display(),
+                compressed.display()
+            ),
+            Err(error) => warn!("Failed to compress {}: {error}", self.rotated.display()),
+        }
+        apply_retention(&self.path, &self.policy);
+    }
+}
+
+/// The process-wide compression worker, started on first use. Jobs run
+/// one at a time in rotation order.
+fn compressor() -> &'static Sender<CompressJob> {
+    static COMPRESSOR: OnceLock<Sender<CompressJob>> = OnceLock::new();
+    COMPRESSOR.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<CompressJob>();
+        let spawned = thread::Builder::new()
+            .name("output-compressor".to_string())
+            .spawn(move || {
+                for job in receiver {
+                    job.run();
+                }
+            });
+        if let Err(error) = spawned {
+            error!("Failed to start compression worker: {error}");
+        }
+        sender
+    })
+}
+
+fn apply_retention(path: &Path, policy: &RotationPolicy) {
+    match prune_rotated(path, policy.keep, policy.max_age, Local::now()) {
+        Ok(removed) => {
+            for removed in removed {
+                info!("Deleted old rotated file {}", removed.display());
+            }
+        }
+        Err(error) => warn!(
+            "Failed to delete old rotated files of {}: {error}",
+            path.display()
+        ),
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Where a pumped stream ends up.
+pub enum OutputTarget {
+    /// No redirection configured: lines go to the runtime log.
+    Log,
+    File(SharedOutputFile),
+    /// Redirection configured but unusable; output is dropped.
+    Discard,
+}
+
+/// AppTimestampOutput/AppOutputTag: how each line written to a
+/// redirection file is prefixed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinePrefix {
+    /// AppTimestampFormat, when AppTimestampOutput is set.
+    pub timestamp_format: Option<String>,
+    pub tag: bool,
+}
+
+impl LinePrefix {
+    pub fn from_config(config: &ServiceConfig) -> Option<Self> {
+        (config.app_timestamp_output || config.app_output_tag).then(|| Self {
+            timestamp_format: config
+                .app_timestamp_output
+                .then(|| config.app_timestamp_format.clone()),
+            tag: config.app_output_tag,
+        })
+    }
+}
+
+/// When the application last wrote to stdout or stderr. The pump threads
+/// record every read; the AppOutputTimeout watchdog compares it with the
+/// clock.
+#[derive(Debug, Clone)]
+pub struct OutputActivity {
+    origin: Instant,
+    /// Milliseconds from `origin` to the latest output.
+    latest: Arc<AtomicU64>,
+}
+
+impl OutputActivity {
+    /// Starts counting silence from now.
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            latest: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn record(&self) {
+        let elapsed = self.origin.elapsed().as_millis() as u64;
+        self.latest.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// The time of the latest output, or of `new` if there was none.
+    pub fn latest(&self) -> Instant {
+        self.origin + Duration::from_millis(self.latest.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for OutputActivity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Watches a stream's lines for the first one matching a pattern, as the
+/// `stdout:` AppReadyCheck does, and then calls `on_match` once.
+pub struct LineMatcher {
+    pattern: regex::Regex,
+    line: Vec<u8>,
+    on_match: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl LineMatcher {
+    pub fn new(pattern: regex::Regex, on_match: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            pattern,
+            line: Vec::new(),
+            on_match: Some(Box::new(on_match)),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        for &byte in chunk {
+            if self.on_match.is_none() {
+                return;
+            }
+            if byte == b'\n' {
+                self.end_line();
+            } else if self.line.len() < MAX_LINE_LENGTH {
+                self.line.push(byte);
+            }
+        }
+    }
+
+    /// Matches a last line that has no newline.
+    pub fn finish(&mut self) {
+        if !self.line.is_empty() {
+            self.end_line();
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line);
+        if self.pattern.is_match(line.trim_end_matches('\r'))
+            && let Some(on_match) = self.on_match.take()
+        {
+            on_match();
+        }
+        self.line.clear();
+    }
+}
+
+/// Lines longer than this are written out in pieces rather than buffered
+/// without bound.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Splits one stream's output into lines and prefixes each of them.
+///
+/// Works on bytes, so non-UTF-8 output passes through unchanged; a line
+/// split across reads is held back until its newline arrives and is
+/// stamped with the time its first byte was read.
+pub struct LineFramer {
+    prefix: LinePrefix,
+    stream_name: &'static str,
+    pending: Vec<u8>,
+    pending_since: Option<DateTime<Local>>,
+}
+
+impl LineFramer {
+    pub fn new(prefix: LinePrefix, is_stderr: bool) -> Self {
+        Self {
+            prefix,
+            stream_name: if is_stderr { "stderr" } else { "stdout" },
+            pending: Vec::new(),
+            pending_since: None,
+        }
+    }
+
+    /// Returns the complete lines in `chunk`, prefixed, holding back any
+    /// trailing partial line.
+    pub fn push(&mut self, chunk: &[u8], now: DateTime<Local>) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(chunk.len() + 64);
+        for mut piece in chunk.split_inclusive(|&byte| byte == b'\n') {
+            while !piece.is_empty() {
+                let since = *self.pending_since.get_or_insert(now);
+                let (head, tail) =
+                    piece.split_at(piece.len().min(MAX_LINE_LENGTH - self.pending.len()));
+                self.pending.extend_from_slice(head);
+                piece = tail;
+                if head.ends_with(b"\n") || self.pending.len() >= MAX_LINE_LENGTH {
+                    self.write_line(&mut framed, since);
+                }
+            }
+        }
+        framed
+    }
+
+    /// Returns the held-back partial line, if any, terminated with a
+    /// newline so it does not run into the other stream's output.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut framed = Vec::new();
+        if let Some(since) = self.pending_since {
+            self.pending.push(b'\n');
+            self.write_line(&mut framed, since);
+        }
+        framed
+    }
+
+    fn write_line(&mut self, framed: &mut Vec<u8>, since: DateTime<Local>) {
+        if let Some(format) = &self.prefix.timestamp_format {
+            // `to_string` would panic on a format chrono cannot render;
+            // configurations are validated, but the pump must not die.
+            let mut stamp = String::new();
+            if write!(stamp, "{}", since.format(format)).is_err() {
+                stamp.clear();
+                let _ = write!(stamp, "{}", since.format(DEFAULT_TIMESTAMP_FORMAT));
+            }
+            framed.extend_from_slice(stamp.as_bytes());
+            framed.push(b' ');
+        }
+        if self.prefix.tag {
+            framed.extend_from_slice(format!("[{}] ", self.stream_name).as_bytes());
+        }
+        framed.append(&mut self.pending);
+        self.pending_since = None;
+    }
+}
+
+/// Drain a child output pipe for the lifetime of the process.
+///
+/// Copies raw bytes: output must not be assumed to be UTF-8, and the pipe
+/// must be drained even when the redirection file cannot be written,
+/// otherwise the pipe fills up and blocks the child. With a `line_prefix`
+/// whole lines are written at a time, so a file shared by stdout and
+/// stderr never interleaves within a line. Every read is recorded in
+/// `activity` and shown to `matcher`, whatever happens to the bytes.
+pub fn pump_output<T: Read>(
+    mut stream: T,
+    mut target: OutputTarget,
+    is_stderr: bool,
+    line_prefix: Option<LinePrefix>,
+    activity: OutputActivity,
+    mut matcher: Option<LineMatcher>,
+) {
+    let stream_name = if is_stderr { "stderr" } else { "stdout" };
+    let mut framer = line_prefix.map(|prefix| LineFramer::new(prefix, is_stderr));
+
+    let mut buffer = [0u8; 8192];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => {
+                activity.record();
+                let chunk = &buffer[..count];
+                if let Some(matcher) = matcher.as_mut() {
+                    matcher.push(chunk);
+                }
+                match &target {
+                    OutputTarget::File(file) => {
+                        let framed = framer
+                            .as_mut()
+                            .map(|framer| framer.push(chunk, Local::now()));
+                        let chunk = framed.as_deref().unwrap_or(chunk);
+                        if !chunk.is_empty() && write_to(file, chunk, stream_name).is_err() {
+                            target = OutputTarget::Discard;
+                        }
+                    }
+                    OutputTarget::Log => {
+                        let text = String::from_utf8_lossy(chunk);
+                        let text = text.trim_end_matches(['\r', '\n']);
+                        if !text.is_empty() {
+                            if is_stderr {
+                                warn!("stderr: {text}");
+                            } else {
+                                info!("stdout: {text}");
+                            }
+                        }
+                    }
+                    OutputTarget::Discard => {}
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+
+    if let Some(matcher) = matcher.as_mut() {
+        matcher.finish();
+    }
+    if let (OutputTarget::File(file), Some(framer)) = (&target, framer.as_mut()) {
+        let rest = framer.finish();
+        if !rest.is_empty() {
+            let _ = write_to(file, &rest, stream_name);
+        }
+    }
+}
+
+fn write_to(file: &SharedOutputFile, chunk: &[u8], stream_name: &str) -> io::Result<()> {
+    let result = file
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .write_chunk(chunk);
+    if let Err(error) = &result {
+        error!(
+            "Failed to write {stream_name} redirection output: {error}; output will be discarded"
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_store::tests::TempDir;
+    use chrono::TimeZone;
+
+    fn policy(online: bool, seconds: u32, bytes: u64) -> RotationPolicy {
+        RotationPolicy {
+            online,
+            seconds,
+            bytes,
+            keep: 0,
+            max_age: 0,
+            compress: RotateCompression::None,
+        }
+    }
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn local(stamp: &str) -> DateTime<Local> {
+        NaiveDateTime::parse_from_str(stamp, ROTATION_STAMP_FORMAT)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+    }
+
+    fn files_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotated_path_inserts_timestamp_before_extension() {
+        let time = Local
+            .with_ymd_and_hms(2025, 1, 2, 3, 4, 5)
+            .unwrap()
+            .checked_add_signed(chrono::TimeDelta::milliseconds(678))
+            .unwrap();
+        assert_eq!(
+            rotated_path(Path::new("logs/out.log"), time),
+            Path::new("logs/out-20250102T030405.678.log")
+        );
+        assert_eq!(
+            rotated_path(Path::new("logs/out"), time),
+            Path::new("logs/out-20250102T030405.678")
+        );
+    }
+
+    #[test]
+    fn rotated_path_avoids_existing_files() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+        let time = Local::now();
+        let first = rotated_path(&path, time);
+        fs::write(&first, "x").unwrap();
+        let second = rotated_path(&path, time);
+        assert_ne!(first, second);
+        assert!(second.to_string_lossy().ends_with("-1.log"));
+    }
+
+    #[test]
+    fn launch_rotation_respects_thresholds() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+
+        assert_eq!(rotate_at_launch(&path, &policy(false, 0, 0)).unwrap(), None);
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(rotate_at_launch(&path, &policy(false, 0, 0)).unwrap(), None);
+
+        fs::write(&path, "12345").unwrap();
+        assert_eq!(
+            rotate_at_launch(&path, &policy(false, 0, 10)).unwrap(),
+            None
+        );
+        assert_eq!(
+            rotate_at_launch(&path, &policy(false, 3600, 0)).unwrap(),
+            None
+        );
+
+        let rotated = rotate_at_launch(&path, &policy(false, 0, 5))
+            .unwrap()
+            .unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(rotated).unwrap(), "12345");
+    }
+
+    #[test]
+    fn open_rotates_existing_file_and_starts_fresh() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+        fs::write(&path, "old").unwrap();
+
+        let mut file = OutputFile::open(&path, Some(policy(false, 0, 0))).unwrap();
+        file.write_chunk(b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(files_in(&dir.0).len(), 2);
+    }
+
+    #[test]
+    fn open_without_policy_appends() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+        fs::write(&path, "old").unwrap();
+
+        let mut file = OutputFile::open(&path, None).unwrap();
+        file.write_chunk(b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "oldnew");
+    }
+
+    #[test]
+    fn online_rotation_by_size() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+
+        let mut file = OutputFile::open(&path, Some(policy(true, 0, 10))).unwrap();
+        file.write_chunk(b"12345").unwrap();
+        assert_eq!(files_in(&dir.0), vec!["out.log"]);
+        file.write_chunk(b"67890").unwrap();
+        file.write_chunk(b"abc").unwrap();
+
+        let names = files_in(&dir.0);
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc");
+        let rotated = names.iter().find(|name| *name != "out.log").unwrap();
+        assert!(rotated.starts_with("out-") && rotated.ends_with(".log"));
+        assert_eq!(
+            fs::read_to_string(dir.0.join(rotated)).unwrap(),
+            "1234567890"
+        );
+    }
+
+    #[test]
+    fn failed_reopen_undoes_the_rotation_or_discards_output() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+        let mut file = OutputFile::open(&path, None).unwrap();
+        file.write_chunk(b"before ").unwrap();
+
+        // The rename went through but the new file could not be opened:
+        // the handle keeps writing to the original name.
+        let rotated = rotate(&path).unwrap();
+        file.undo_rotation(&rotated, &io::Error::other("sharing violation"));
+        file.write_chunk(b"after").unwrap();
+        assert_eq!(files_in(&dir.0), vec!["out.log"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "before after");
+
+        // Nor could it be renamed back (here because it is gone): nothing
+        // is written to the rotated file any more.
+        let rotated = rotate(&path).unwrap();
+        let error = io::Error::other("denied");
+        file.undo_rotation(&dir.0.join("missing.log"), &error);
+        file.write_chunk(b"lost").unwrap();
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "before after");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn online_rotation_is_off_unless_requested() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("out.log");
+
+        let mut file = OutputFile::open(&path, Some(policy(false, 0, 4))).unwrap();
+        file.write_chunk(b"12345678").unwrap();
+        assert_eq!(files_in(&dir.0), vec!["out.log"]);
+    }
+
+    #[test]
+    fn online_rotation_by_age() {
+        assert!(!policy(true, 60, 0).online_rotation_due(1 << 30, Duration::from_secs(59)));
+        assert!(policy(true, 60, 0).online_rotation_due(0, Duration::from_secs(60)));
+        assert!(!policy(true, 0, 0).online_rotation_due(1 << 30, Duration::from_secs(1 << 20)));
+    }
+
+    #[test]
+    fn pump_writes_raw_bytes_to_shared_file() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("both.log");
+        let shared = Arc::new(Mutex::new(OutputFile::open(&path, None).unwrap()));
+
+        pump_output(
+            io::Cursor::new(b"out\xff\n".to_vec()),
+            OutputTarget::File(shared.clone()),
+            false,
+            None,
+            OutputActivity::new(),
+            None,
+        );
+        pump_output(
+            io::Cursor::new(b"err\n".to_vec()),
+            OutputTarget::File(shared),
+            true,
+            None,
+            OutputActivity::new(),
+            None,
+        );
+        assert_eq!(fs::read(&path).unwrap(), b"out\xff\nerr\n");
+    }
+
+    #[test]
+    fn rotated_names_are_recognised_strictly() {
+        let parse = |name| parse_rotated_name(name, "out", ".log");
+        assert_eq!(
+            parse("out-20250102T030405.678.log").map(|(_, counter)| counter),
+            Some(0)
+        );
+        assert_eq!(
+            parse("out-20250102T030405.678-3.log").map(|(_, counter)| counter),
+            Some(3)
+        );
+        assert_eq!(parse("out.log"), None);
+        assert_eq!(parse("out-err.log"), None);
+        assert_eq!(parse("out-err-20250102T030405.678.log"), None);
+        assert_eq!(parse("out-20250102T030405.678.txt"), None);
+        assert_eq!(parse("out-2025.log"), None);
+        assert!(parse("out-20250102T030405.678.log.gz").is_some());
+        assert!(parse("out-20250102T030405.678.log.zst").is_some());
+        assert_eq!(parse("out-20250102T030405.678.log.gz.partial"), None);
+    }
+
+    #[test]
+    fn compress_file_round_trips() {
+        let dir = TempDir::new("compress");
+        // Several zstd blocks' worth, to exercise the streaming encoder.
+        let content = b"line one\nline two\n".repeat(20_000);
+
+        let path = dir.0.join("gzip.log");
+        fs::write(&path, &content).unwrap();
+        let compressed = compress_file(&path, RotateCompression::Gzip).unwrap();
+        assert_eq!(compressed, dir.0.join("gzip.log.gz"));
+        assert!(!path.exists());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&compressed).unwrap())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        let path = dir.0.join("zstd.log");
+        fs::write(&path, &content).unwrap();
+        let compressed = compress_file(&path, RotateCompression::Zstd).unwrap();
+        assert_eq!(compressed, dir.0.join("zstd.log.zst"));
+        assert!(!path.exists());
+        let mut decoded = Vec::new();
+        ruzstd::decoding::StreamingDecoder::new(File::open(&compressed).unwrap())
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+    }
+
+    #[test]
+    fn online_rotation_compresses_in_background() {
+        let dir = TempDir::new("compress");
+        let path = dir.0.join("out.log");
+        let rotating = RotationPolicy {
+            keep: 2,
+            compress: RotateCompression::Gzip,
+            ..policy(true, 0, 4)
+        };
+
+        let mut file = OutputFile::open(&path, Some(rotating)).unwrap();
+        for _ in 0..4 {
+            file.write_chunk(b"data").unwrap();
+        }
+
+        wait_for(|| {
+            let names = files_in(&dir.0);
+            names.len() == 3
+                && names
+                    .iter()
+                    .filter(|name| name.ends_with(".log.gz"))
+                    .count()
+                    == 2
+        });
+    }
+
+    #[test]
+    fn retention_keeps_newest_files() {
+        let dir = TempDir::new("retain");
+        let path = dir.0.join("out.log");
+        for name in [
+            "out.log",
+            "out-20250101T000000.000.log",
+            "out-20250102T000000.000.log",
+            "out-20250102T000000.000-1.log",
+            "out-20250103T000000.000.log",
+            "err-20250101T000000.000.log",
+        ] {
+            fs::write(dir.0.join(name), name).unwrap();
+        }
+
+        let removed = prune_rotated(&path, 2, 0, Local::now()).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            files_in(&dir.0),
+            vec![
+                "err-20250101T000000.000.log",
+                "out-20250102T000000.000-1.log",
+                "out-20250103T000000.000.log",
+                "out.log",
+            ]
+        );
+    }
+
+    #[test]
+    fn retention_deletes_expired_files() {
+        let dir = TempDir::new("retain");
+        let path = dir.0.join("out.log");
+        for name in ["out-20250101T000000.000.log", "out-20250103T000000.000.log"] {
+            fs::write(dir.0.join(name), name).unwrap();
+        }
+
+        let now = local("20250103T120000.000");
+        prune_rotated(&path, 0, 86400, now).unwrap();
+        assert_eq!(files_in(&dir.0), vec!["out-20250103T000000.000.log"]);
+    }
+
+    #[test]
+    fn retention_deletes_leftover_partial_archives() {
+        let dir = TempDir::new("retain");
+        for name in [
+            "out-20250101T000000.000.log",
+            "out-20250101T000000.000.log.gz.partial",
+            "out-20250102T000000.000.log.zst.partial",
+            "out.log.partial",
+            "err-20250101T000000.000.log.gz.partial",
+        ] {
+            fs::write(dir.0.join(name), name).unwrap();
+        }
+
+        let removed = prune_rotated(&dir.0.join("out.log"), 0, 0, Local::now()).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            files_in(&dir.0),
+            vec![
+                "err-20250101T000000.000.log.gz.partial",
+                "out-20250101T000000.000.log",
+                "out.log.partial",
+            ]
+        );
+    }
+
+    #[test]
+    fn retention_disabled_by_default() {
+        let dir = TempDir::new("retain");
+        fs::write(dir.0.join("out-20000101T000000.000.log"), "").unwrap();
+        assert!(
+            prune_rotated(&dir.0.join("out.log"), 0, 0, Local::now())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn online_rotation_applies_retention_per_file() {
+        let dir = TempDir::new("retain");
+        let out = dir.0.join("out.log");
+        let err = dir.0.join("err.log");
+        let rotating = RotationPolicy {
+            keep: 1,
+            ..policy(true, 0, 1)
+        };
+
+        let mut stdout = OutputFile::open(&out, Some(rotating)).unwrap();
+        let mut stderr = OutputFile::open(&err, Some(rotating)).unwrap();
+        for _ in 0..3 {
+            stdout.write_chunk(b"o").unwrap();
+            stderr.write_chunk(b"e").unwrap();
+        }
+
+        let names = files_in(&dir.0);
+        assert_eq!(names.len(), 4, "{names:?}");
+        assert_eq!(
+            names.iter().filter(|name| name.starts_with("out-")).count(),
+            1
+        );
+        assert_eq!(
+            names.iter().filter(|name| name.starts_with("err-")).count(),
+            1
+        );
+    }
+
+    fn framer(timestamp_format: Option<&str>, tag: bool, is_stderr: bool) -> LineFramer {
+        LineFramer::new(
+            LinePrefix {
+                timestamp_format: timestamp_format.map(str::to_string),
+                tag,
+            },
+            is_stderr,
+        )
+    }
+
+    #[test]
+    fn framer_prefixes_complete_lines() {
+        let mut framer = framer(Some("%H:%M:%S"), true, true);
+        let now = local("20250102T030405.678");
+        assert_eq!(
+            framer.push(b"one\r\ntwo\n", now),
+            b"03:04:05 [stderr] one\r\n03:04:05 [stderr] two\n"
+        );
+        assert!(framer.finish().is_empty());
+    }
+
+    #[test]
+    fn framer_falls_back_to_the_default_format_for_invalid_ones() {
+        let mut framer = framer(Some("%Q"), false, false);
+        let now = local("20250102T030405.678");
+        assert_eq!(
+            framer.push(b"line\n", now),
+            b"2025-01-02 03:04:05.678 line\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn framer_joins_lines_split_across_chunks() {
+        let mut framer = framer(Some("%H:%M:%S"), false, false);
+        let first = local("20250102T030405.000");
+        let second = local("20250102T030406.000");
+        assert!(framer.push(b"hel", first).is_empty());
+        assert_eq!(
+            framer.push(b"lo\nwor", second),
+            b"03:04:05 hello\n".to_vec()
+        );
+        assert_eq!(framer.finish(), b"03:04:06 wor\n".to_vec());
+    }
+
+    #[test]
+    fn framer_passes_non_utf8_bytes_through() {
+        let mut framer = framer(None, true, false);
+        assert_eq!(
+            framer.push(b"\xff\xfe\n", Local::now()),
+            b"[stdout] \xff\xfe\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn framer_splits_overlong_lines() {
+        let mut framer = framer(None, true, false);
+        let framed = framer.push(&vec![b'x'; MAX_LINE_LENGTH + 10], Local::now());
+        assert_eq!(framed.len(), "[stdout] ".len() + MAX_LINE_LENGTH);
+        assert_eq!(framer.finish().len(), "[stdout] ".len() + 11);
+    }
+
+    #[test]
+    fn pump_output_writes_tagged_lines_to_shared_file() {
+        let dir = TempDir::new("frame");
+        let path = dir.0.join("out.log");
+        let file = Arc::new(Mutex::new(OutputFile::open(&path, None).unwrap()));
+        let prefix = LinePrefix {
+            timestamp_format: None,
+            tag: true,
+        };
+
+        pump_output(
+            &b"a\npartial"[..],
+            OutputTarget::File(file.clone()),
+            false,
+            Some(prefix.clone()),
+            OutputActivity::new(),
+            None,
+        );
+        pump_output(
+            &b"b\n"[..],
+            OutputTarget::File(file),
+            true,
+            Some(prefix),
+            OutputActivity::new(),
+            None,
+        );
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[stdout] a\n[stdout] partial\n[stderr] b\n"
+        );
+    }
+
+    #[test]
+    fn pump_output_records_activity_even_when_discarding() {
+        let activity = OutputActivity::new();
+        let launched = activity.latest();
+        thread::sleep(Duration::from_millis(20));
+
+        pump_output(
+            &b"heartbeat\n"[..],
+            OutputTarget::Discard,
+            false,
+            None,
+            activity.clone(),
+            None,
+        );
+        assert!(activity.latest() >= launched + Duration::from_millis(20));
+
+        let silent = OutputActivity::new();
+        pump_output(
+            &b""[..],
+            OutputTarget::Discard,
+            false,
+            None,
+            silent.clone(),
+            None,
+        );
+        assert_eq!(silent.latest(), silent.origin);
+    }
+
+    #[test]
+    fn line_matcher_fires_once_on_the_first_matching_line() {
+        let (sender, receiver) = mpsc::channel();
+        let matcher = LineMatcher::new(regex::Regex::new(r"^ready on \d+$").unwrap(), move || {
+            sender.send(()).unwrap();
+        });
+
+        pump_output(
+            &b"starting\nready on 80\r\nready on 81\n"[..],
+            OutputTarget::Discard,
+            false,
+            None,
+            OutputActivity::new(),
+            Some(matcher),
+        );
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn line_matcher_sees_lines_split_across_reads_and_the_last_line() {
+        let (sender, receiver) = mpsc::channel();
+        let mut matcher = LineMatcher::new(regex::Regex::new("^ready$").unwrap(), move || {
+            sender.send(()).unwrap();
+        });
+        matcher.push(b"rea");
+        matcher.push(b"dy");
+        assert!(receiver.try_recv().is_err());
+        matcher.finish();
+        assert!(receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn same_file_path_compares_resolved_paths() {
+        assert!(same_file_path(
+            Path::new("a/out.log"),
+            Path::new("a/./out.log")
+        ));
+        assert!(!same_file_path(
+            Path::new("a/out.log"),
+            Path::new("a/err.log")
+        ));
+    }
+}
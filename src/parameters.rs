@@ -1,6 +1,14 @@
 use std::path::PathBuf;
 
-use crate::config::{ExitAction, ProcessPriority, ServiceConfig, ServiceStartType};
+use crate::config::{
+    CpuAffinity, DEFAULT_HEALTH_CHECK_INTERVAL, DEFAULT_HEALTH_CHECK_THRESHOLD,
+    DEFAULT_HEALTH_CHECK_TIMEOUT, DEFAULT_LAUNCH_ATTEMPTS, DEFAULT_LOG_KEEP, DEFAULT_LOG_MAX_BYTES,
+    DEFAULT_READY_TIMEOUT, DEFAULT_RESTART_BACKOFF_BASE, DEFAULT_RESTART_BACKOFF_MAX,
+    DEFAULT_RESTART_BACKOFF_MULTIPLIER, DEFAULT_RESTART_LIMIT_WINDOW, DEFAULT_START_TIMEOUT,
+    DEFAULT_TIMESTAMP_FORMAT, ExitAction, HealthCheck, LogFormat, ProcessPriority, ReadyCheck,
+    RestartBackoff, RotateCompression, ServiceConfig, ServiceStartType, StartCondition,
+    StatusRange, is_valid_time_format,
+};
 use crate::error::{AppError, AppResult};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -11,7 +19,9 @@ pub enum ServiceParameter {
     DisplayName,
     Description,
     Start,
+    Dependencies,
     AppPriority,
+    AppAffinity,
     AppNoConsole,
     AppThrottle,
     AppStdout,
@@ -22,11 +32,111 @@ pub enum ServiceParameter {
     AppStopMethodWindow,
     AppStopMethodThreads,
     AppRestartDelay,
+    AppRestartBackoff,
+    AppRestartBackoffBase,
+    AppRestartBackoffMultiplier,
+    AppRestartBackoffMax,
+    AppRestartJitter,
+    AppRestartResetAfter,
+    AppRestartLimit,
+    AppRestartLimitWindow,
+    AppLaunchAttempts,
     AppExitAction,
+    AppExit,
+    AppEnvironment,
     AppEnvironmentExtra,
+    AppRotateFiles,
+    AppRotateOnline,
+    AppRotateSeconds,
+    AppRotateBytes,
+    AppRotateKeep,
+    AppRotateMaxAge,
+    AppRotateCompress,
+    AppTimestampOutput,
+    AppTimestampFormat,
+    AppOutputTag,
+    AppOutputTimeout,
+    AppLogFormat,
+    AppLogDirectory,
+    AppLogMaxBytes,
+    AppLogKeep,
+    AppHealthCheck,
+    AppHealthCheckStatus,
+    AppHealthCheckInterval,
+    AppHealthCheckTimeout,
+    AppHealthCheckThreshold,
+    AppHealthCheckGrace,
+    AppReadyCheck,
+    AppReadyTimeout,
+    AppNotify,
+    AppWatchdogTimeout,
+    AppStartCondition,
+    AppStartTimeout,
 }
 
 impl ServiceParameter {
+    pub const ALL: &[Self] = &[
+        Self::Application,
+        Self::AppDirectory,
+        Self::AppParameters,
+        Self::DisplayName,
+        Self::Description,
+        Self::Start,
+        Self::Dependencies,
+        Self::AppPriority,
+        Self::AppAffinity,
+        Self::AppNoConsole,
+        Self::AppThrottle,
+        Self::AppStdout,
+        Self::AppStderr,
+        Self::AppStdin,
+        Self::AppStopMethod,
+        Self::AppStopMethodConsole,
+        Self::AppStopMethodWindow,
+        Self::AppStopMethodThreads,
+        Self::AppRestartDelay,
+        Self::AppRestartBackoff,
+        Self::AppRestartBackoffBase,
+        Self::AppRestartBackoffMultiplier,
+        Self::AppRestartBackoffMax,
+        Self::AppRestartJitter,
+        Self::AppRestartResetAfter,
+        Self::AppRestartLimit,
+        Self::AppRestartLimitWindow,
+        Self::AppLaunchAttempts,
+        Self::AppExitAction,
+        Self::AppExit,
+        Self::AppEnvironment,
+        Self::AppEnvironmentExtra,
+        Self::AppRotateFiles,
+        Self::AppRotateOnline,
+        Self::AppRotateSeconds,
+        Self::AppRotateBytes,
+        Self::AppRotateKeep,
+        Self::AppRotateMaxAge,
+        Self::AppRotateCompress,
+        Self::AppTimestampOutput,
+        Self::AppTimestampFormat,
+        Self::AppOutputTag,
+        Self::AppOutputTimeout,
+        Self::AppLogFormat,
+        Self::AppLogDirectory,
+        Self::AppLogMaxBytes,
+        Self::AppLogKeep,
+        Self::AppHealthCheck,
+        Self::AppHealthCheckStatus,
+        Self::AppHealthCheckInterval,
+        Self::AppHealthCheckTimeout,
+        Self::AppHealthCheckThreshold,
+        Self::AppHealthCheckGrace,
+        Self::AppReadyCheck,
+        Self::AppReadyTimeout,
+        Self::AppNotify,
+        Self::AppWatchdogTimeout,
+        Self::AppStartCondition,
+        Self::AppStartTimeout,
+    ];
+
     pub fn parse(parameter: &str) -> AppResult<Self> {
         match parameter.to_uppercase().as_str() {
             "APPLICATION" => Ok(Self::Application),
@@ -34,8 +144,10 @@ impl ServiceParameter {
             "APPPARAMETERS" => Ok(Self::AppParameters),
             "DISPLAYNAME" => Ok(Self::DisplayName),
             "DESCRIPTION" => Ok(Self::Description),
+            "DEPENDENCIES" | "DEPENDONSERVICE" => Ok(Self::Dependencies),
             "START" => Ok(Self::Start),
             "APPPRIORITY" => Ok(Self::AppPriority),
+            "APPAFFINITY" => Ok(Self::AppAffinity),
             "APPNOCONSOLE" => Ok(Self::AppNoConsole),
             "APPTHROTTLE" => Ok(Self::AppThrottle),
             "APPSTDOUT" => Ok(Self::AppStdout),
@@ -46,8 +158,46 @@ impl ServiceParameter {
             "APPSTOPMETHOD_WINDOW" => Ok(Self::AppStopMethodWindow),
             "APPSTOPMETHOD_THREADS" => Ok(Self::AppStopMethodThreads),
             "APPRESTARTDELAY" => Ok(Self::AppRestartDelay),
+            "APPRESTARTBACKOFF" => Ok(Self::AppRestartBackoff),
+            "APPRESTARTBACKOFFBASE" => Ok(Self::AppRestartBackoffBase),
+            "APPRESTARTBACKOFFMULTIPLIER" => Ok(Self::AppRestartBackoffMultiplier),
+            "APPRESTARTBACKOFFMAX" => Ok(Self::AppRestartBackoffMax),
+            "APPRESTARTJITTER" => Ok(Self::AppRestartJitter),
+            "APPRESTARTRESETAFTER" => Ok(Self::AppRestartResetAfter),
+            "APPRESTARTLIMIT" => Ok(Self::AppRestartLimit),
+            "APPRESTARTLIMITWINDOW" => Ok(Self::AppRestartLimitWindow),
+            "APPLAUNCHATTEMPTS" => Ok(Self::AppLaunchAttempts),
             "APPEXITACTION" => Ok(Self::AppExitAction),
+            "APPEXIT" => Ok(Self::AppExit),
+            "APPENVIRONMENT" => Ok(Self::AppEnvironment),
             "APPENVIRONMENTEXTRA" => Ok(Self::AppEnvironmentExtra),
+            "APPROTATEFILES" => Ok(Self::AppRotateFiles),
+            "APPROTATEONLINE" => Ok(Self::AppRotateOnline),
+            "APPROTATESECONDS" => Ok(Self::AppRotateSeconds),
+            "APPROTATEBYTES" => Ok(Self::AppRotateBytes),
+            "APPROTATEKEEP" => Ok(Self::AppRotateKeep),
+            "APPROTATEMAXAGE" => Ok(Self::AppRotateMaxAge),
+            "APPROTATECOMPRESS" => Ok(Self::AppRotateCompress),
+            "APPTIMESTAMPOUTPUT" => Ok(Self::AppTimestampOutput),
+            "APPTIMESTAMPFORMAT" => Ok(Self::AppTimestampFormat),
+            "APPOUTPUTTAG" => Ok(Self::AppOutputTag),
+            "APPOUTPUTTIMEOUT" => Ok(Self::AppOutputTimeout),
+            "APPLOGFORMAT" => Ok(Self::AppLogFormat),
+            "APPLOGDIRECTORY" => Ok(Self::AppLogDirectory),
+            "APPLOGMAXBYTES" => Ok(Self::AppLogMaxBytes),
+            "APPLOGKEEP" => Ok(Self::AppLogKeep),
+            "APPHEALTHCHECK" => Ok(Self::AppHealthCheck),
+            "APPHEALTHCHECKSTATUS" => Ok(Self::AppHealthCheckStatus),
+            "APPHEALTHCHECKINTERVAL" => Ok(Self::AppHealthCheckInterval),
+            "APPHEALTHCHECKTIMEOUT" => Ok(Self::AppHealthCheckTimeout),
+            "APPHEALTHCHECKTHRESHOLD" => Ok(Self::AppHealthCheckThreshold),
+            "APPHEALTHCHECKGRACE" => Ok(Self::AppHealthCheckGrace),
+            "APPREADYCHECK" => Ok(Self::AppReadyCheck),
+            "APPREADYTIMEOUT" => Ok(Self::AppReadyTimeout),
+            "APPNOTIFY" => Ok(Self::AppNotify),
+            "APPWATCHDOGTIMEOUT" => Ok(Self::AppWatchdogTimeout),
+            "APPSTARTCONDITION" => Ok(Self::AppStartCondition),
+            "APPSTARTTIMEOUT" => Ok(Self::AppStartTimeout),
             _ => Err(AppError::UnknownParameter(parameter.to_string())),
         }
     }
@@ -59,12 +209,20 @@ impl ServiceParameter {
             | Self::AppParameters
             | Self::DisplayName
             | Self::Description
+            | Self::Dependencies
+            | Self::AppExit
             | Self::AppStdout
             | Self::AppStderr
             | Self::AppStdin
-            | Self::AppEnvironmentExtra => String::new(),
+            | Self::AppLogDirectory
+            | Self::AppEnvironment
+            | Self::AppEnvironmentExtra
+            | Self::AppHealthCheck
+            | Self::AppReadyCheck
+            | Self::AppStartCondition => String::new(),
             Self::Start => "SERVICE_AUTO_START".to_string(),
             Self::AppPriority => "NORMAL_PRIORITY_CLASS".to_string(),
+            Self::AppAffinity => "All".to_string(),
             Self::AppNoConsole => "0".to_string(),
             Self::AppThrottle => "1500".to_string(),
             Self::AppStopMethod => "0".to_string(),
@@ -72,8 +230,85 @@ impl ServiceParameter {
             Self::AppStopMethodWindow => "1500".to_string(),
             Self::AppStopMethodThreads => "1500".to_string(),
             Self::AppRestartDelay => "0".to_string(),
+            Self::AppRestartBackoff => "Exponential".to_string(),
+            Self::AppRestartBackoffBase => DEFAULT_RESTART_BACKOFF_BASE.to_string(),
+            Self::AppRestartBackoffMultiplier => DEFAULT_RESTART_BACKOFF_MULTIPLIER.to_string(),
+            Self::AppRestartBackoffMax => DEFAULT_RESTART_BACKOFF_MAX.to_string(),
+            Self::AppRestartJitter => "0".to_string(),
+            Self::AppRestartResetAfter => "0".to_string(),
+            Self::AppRestartLimit => "0".to_string(),
+            Self::AppRestartLimitWindow => DEFAULT_RESTART_LIMIT_WINDOW.to_string(),
+            Self::AppLaunchAttempts => DEFAULT_LAUNCH_ATTEMPTS.to_string(),
             Self::AppExitAction => "Restart".to_string(),
+            Self::AppRotateFiles => "0".to_string(),
+            Self::AppRotateOnline => "0".to_string(),
+            Self::AppRotateSeconds => "86400".to_string(),
+            Self::AppRotateBytes => "1048576".to_string(),
+            Self::AppRotateKeep => "0".to_string(),
+            Self::AppRotateMaxAge => "0".to_string(),
+            Self::AppRotateCompress => "None".to_string(),
+            Self::AppTimestampOutput => "0".to_string(),
+            Self::AppTimestampFormat => DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            Self::AppOutputTag => "0".to_string(),
+            Self::AppOutputTimeout => "0".to_string(),
+            Self::AppLogFormat => "Text".to_string(),
+            Self::AppLogMaxBytes => DEFAULT_LOG_MAX_BYTES.to_string(),
+            Self::AppLogKeep => DEFAULT_LOG_KEEP.to_string(),
+            Self::AppHealthCheckStatus => StatusRange::SUCCESS.as_cli_value(),
+            Self::AppHealthCheckInterval => DEFAULT_HEALTH_CHECK_INTERVAL.to_string(),
+            Self::AppHealthCheckTimeout => DEFAULT_HEALTH_CHECK_TIMEOUT.to_string(),
+            Self::AppHealthCheckThreshold => DEFAULT_HEALTH_CHECK_THRESHOLD.to_string(),
+            Self::AppHealthCheckGrace => "0".to_string(),
+            Self::AppReadyTimeout => DEFAULT_READY_TIMEOUT.to_string(),
+            Self::AppNotify => "0".to_string(),
+            Self::AppWatchdogTimeout => "0".to_string(),
+            Self::AppStartTimeout => DEFAULT_START_TIMEOUT.to_string(),
+        }
+    }
+
+    /// The value `reset` writes. APPLICATION has no meaningful default.
+    pub fn reset_value(self) -> AppResult<String> {
+        if self == Self::Application {
+            return Err(AppError::Message(
+                "APPLICATION cannot be reset to an empty value; set a new path instead".to_string(),
+            ));
         }
+        Ok(self.default_value())
+    }
+
+    /// AppEnvironment and AppEnvironmentExtra hold one `KEY=VALUE` entry
+    /// per value.
+    pub fn is_environment(self) -> bool {
+        matches!(self, Self::AppEnvironment | Self::AppEnvironmentExtra)
+    }
+
+    /// The entries of a parameter that takes one entry per value, or
+    /// `None` for single-valued parameters.
+    pub fn list(self, config: &ServiceConfig) -> Option<&Vec<String>> {
+        match self {
+            Self::Dependencies => Some(&config.dependencies),
+            Self::AppEnvironment => Some(&config.app_environment),
+            Self::AppEnvironmentExtra => Some(&config.app_environment_extra),
+            _ => None,
+        }
+    }
+
+    pub fn list_mut(self, config: &mut ServiceConfig) -> Option<&mut Vec<String>> {
+        match self {
+            Self::Dependencies => Some(&mut config.dependencies),
+            Self::AppEnvironment => Some(&mut config.app_environment),
+            Self::AppEnvironmentExtra => Some(&mut config.app_environment_extra),
+            _ => None,
+        }
+    }
+
+    /// DisplayName, Description, Start and Dependencies live in the SCM
+    /// service record rather than under the service's Parameters key.
+    pub fn is_scm_owned(self) -> bool {
+        matches!(
+            self,
+            Self::DisplayName | Self::Description | Self::Start | Self::Dependencies
+        )
     }
 
     pub fn apply(self, config: &mut ServiceConfig, value: &str) -> AppResult<()> {
@@ -83,6 +318,7 @@ impl ServiceParameter {
                     return Err(AppError::InvalidParameterValue {
                         parameter: self.as_str().to_string(),
                         value: value.to_string(),
+                        reason: None,
                     });
                 }
                 config.application = PathBuf::from(value);
@@ -104,6 +340,7 @@ impl ServiceParameter {
                     AppError::InvalidParameterValue {
                         parameter: self.as_str().to_string(),
                         value: value.to_string(),
+                        reason: None,
                     }
                 })?;
             }
@@ -112,6 +349,16 @@ impl ServiceParameter {
                     AppError::InvalidParameterValue {
                         parameter: self.as_str().to_string(),
                         value: value.to_string(),
+                        reason: None,
+                    }
+                })?;
+            }
+            Self::AppAffinity => {
+                config.app_affinity = CpuAffinity::parse(value).map_err(|reason| {
+                    AppError::InvalidParameterValue {
+                        parameter: self.as_str().to_string(),
+                        value: value.to_string(),
+                        reason: Some(reason),
                     }
                 })?;
             }
@@ -145,19 +392,196 @@ impl ServiceParameter {
             Self::AppRestartDelay => {
                 config.app_restart_delay = parse_u32(self, value)?;
             }
+            Self::AppRestartBackoff => {
+                config.app_restart_backoff =
+                    RestartBackoff::from_str(value).ok_or_else(|| invalid(self, value))?;
+            }
+            Self::AppRestartBackoffBase => {
+                config.app_restart_backoff_base = parse_u32(self, value)?;
+            }
+            Self::AppRestartBackoffMultiplier => {
+                config.app_restart_backoff_multiplier = parse_u32(self, value)?;
+            }
+            Self::AppRestartBackoffMax => {
+                config.app_restart_backoff_max = parse_u32(self, value)?;
+            }
+            Self::AppRestartJitter => {
+                let jitter = parse_u32(self, value)?;
+                if jitter > 100 {
+                    return Err(AppError::InvalidParameterValue {
+                        parameter: self.as_str().to_string(),
+                        value: value.to_string(),
+                        reason: Some("jitter is a percentage from 0 to 100".to_string()),
+                    });
+                }
+                config.app_restart_jitter = jitter;
+            }
+            Self::AppRestartResetAfter => {
+                config.app_restart_reset_after = parse_u32(self, value)?;
+            }
+            Self::AppRestartLimit => {
+                config.app_restart_limit = parse_u32(self, value)?;
+            }
+            Self::AppRestartLimitWindow => {
+                config.app_restart_limit_window = parse_u32(self, value)?;
+            }
+            Self::AppLaunchAttempts => {
+                config.app_launch_attempts = parse_u32(self, value)?;
+            }
             Self::AppExitAction => {
                 config.app_exit_default =
                     ExitAction::from_str(value).ok_or_else(|| AppError::InvalidParameterValue {
                         parameter: self.as_str().to_string(),
                         value: value.to_string(),
+                        reason: None,
                     })?;
             }
+            Self::AppExit => {
+                let mut table = config.clone();
+                table.app_exit.clear();
+                for line in value.lines().filter(|line| !line.trim().is_empty()) {
+                    match line.split_whitespace().collect::<Vec<_>>()[..] {
+                        [code, action] => apply_exit_entry(&mut table, code, action)?,
+                        _ => {
+                            return Err(AppError::InvalidParameterValue {
+                                parameter: self.as_str().to_string(),
+                                value: line.to_string(),
+                                reason: Some("expected '<code|Default> <action>'".to_string()),
+                            });
+                        }
+                    }
+                }
+                config.app_exit_default = table.app_exit_default;
+                config.app_exit = table.app_exit;
+            }
+            Self::Dependencies => {
+                config.dependencies = list_lines(value);
+            }
+            Self::AppEnvironment => {
+                config.app_environment = list_lines(value);
+            }
             Self::AppEnvironmentExtra => {
-                config.app_environment_extra = value
-                    .lines()
-                    .filter(|line| !line.is_empty())
-                    .map(str::to_string)
-                    .collect();
+                config.app_environment_extra = list_lines(value);
+            }
+            Self::AppRotateFiles => {
+                config.app_rotate_files = value != "0";
+            }
+            Self::AppRotateOnline => {
+                config.app_rotate_online = value != "0";
+            }
+            Self::AppRotateSeconds => {
+                config.app_rotate_seconds = parse_u32(self, value)?;
+            }
+            Self::AppRotateBytes => {
+                config.app_rotate_bytes = value.parse().map_err(|_| invalid(self, value))?;
+            }
+            Self::AppRotateKeep => {
+                config.app_rotate_keep = parse_u32(self, value)?;
+            }
+            Self::AppRotateMaxAge => {
+                config.app_rotate_max_age = parse_u32(self, value)?;
+            }
+            Self::AppRotateCompress => {
+                config.app_rotate_compress =
+                    RotateCompression::from_str(value).ok_or_else(|| invalid(self, value))?;
+            }
+            Self::AppTimestampOutput => {
+                config.app_timestamp_output = value != "0";
+            }
+            Self::AppTimestampFormat => {
+                config.app_timestamp_format = if value.is_empty() {
+                    DEFAULT_TIMESTAMP_FORMAT.to_string()
+                } else if is_valid_time_format(value) {
+                    value.to_string()
+                } else {
+                    return Err(invalid(self, value));
+                };
+            }
+            Self::AppOutputTag => {
+                config.app_output_tag = value != "0";
+            }
+            Self::AppOutputTimeout => {
+                config.app_output_timeout = parse_u32(self, value)?;
+            }
+            Self::AppLogFormat => {
+                config.app_log_format =
+                    LogFormat::from_str(value).ok_or_else(|| invalid(self, value))?;
+            }
+            Self::AppLogDirectory => {
+                config.app_log_directory = empty_to_none_path(value);
+            }
+            Self::AppLogMaxBytes => {
+                config.app_log_max_bytes = value.parse().map_err(|_| invalid(self, value))?;
+            }
+            Self::AppLogKeep => {
+                config.app_log_keep = parse_u32(self, value)?;
+            }
+            Self::AppHealthCheck => {
+                config.app_health_check = HealthCheck::parse(value).map_err(|reason| {
+                    AppError::InvalidParameterValue {
+                        parameter: self.as_str().to_string(),
+                        value: value.to_string(),
+                        reason: Some(reason),
+                    }
+                })?;
+            }
+            Self::AppHealthCheckStatus => {
+                config.app_health_check_status = StatusRange::parse(value).map_err(|reason| {
+                    AppError::InvalidParameterValue {
+                        parameter: self.as_str().to_string(),
+                        value: value.to_string(),
+                        reason: Some(reason),
+                    }
+                })?;
+            }
+            Self::AppHealthCheckInterval => {
+                config.app_health_check_interval = parse_nonzero_u32(self, value)?;
+            }
+            Self::AppHealthCheckTimeout => {
+                config.app_health_check_timeout = parse_nonzero_u32(self, value)?;
+            }
+            Self::AppHealthCheckThreshold => {
+                config.app_health_check_threshold = parse_nonzero_u32(self, value)?;
+            }
+            Self::AppHealthCheckGrace => {
+                config.app_health_check_grace = parse_u32(self, value)?;
+            }
+            Self::AppReadyCheck => {
+                config.app_ready_check =
+                    ReadyCheck::parse(value).map_err(|reason| AppError::InvalidParameterValue {
+                        parameter: self.as_str().to_string(),
+                        value: value.to_string(),
+                        reason: Some(reason),
+                    })?;
+            }
+            Self::AppReadyTimeout => {
+                config.app_ready_timeout = parse_u32(self, value)?;
+            }
+            Self::AppNotify => {
+                config.app_notify = value != "0";
+            }
+            Self::AppWatchdogTimeout => {
+                config.app_watchdog_timeout = parse_u32(self, value)?;
+            }
+            Self::AppStartCondition => {
+                let mut conditions = Vec::new();
+                for line in list_lines(value) {
+                    match StartCondition::parse(&line) {
+                        Ok(Some(condition)) => conditions.push(condition),
+                        Ok(None) => {}
+                        Err(reason) => {
+                            return Err(AppError::InvalidParameterValue {
+                                parameter: self.as_str().to_string(),
+                                value: line,
+                                reason: Some(reason),
+                            });
+                        }
+                    }
+                }
+                config.app_start_condition = conditions;
+            }
+            Self::AppStartTimeout => {
+                config.app_start_timeout = parse_u32(self, value)?;
             }
         }
 
@@ -175,8 +599,13 @@ impl ServiceParameter {
             Self::AppParameters => config.app_parameters.clone().unwrap_or_default(),
             Self::DisplayName => config.display_name.clone().unwrap_or_default(),
             Self::Description => config.description.clone().unwrap_or_default(),
+            Self::Dependencies => config.dependencies.join("\n"),
             Self::Start => config.start_type.as_cli_value().to_string(),
             Self::AppPriority => config.app_priority.as_cli_value().to_string(),
+            Self::AppAffinity => match config.app_affinity {
+                Some(affinity) => affinity.as_cli_value(),
+                None => "All".to_string(),
+            },
             Self::AppNoConsole => bool_to_flag(config.app_no_console),
             Self::AppThrottle => config.app_throttle.to_string(),
             Self::AppStdout => config
@@ -199,8 +628,68 @@ impl ServiceParameter {
             Self::AppStopMethodWindow => config.app_stop_method_window.to_string(),
             Self::AppStopMethodThreads => config.app_stop_method_threads.to_string(),
             Self::AppRestartDelay => config.app_restart_delay.to_string(),
+            Self::AppRestartBackoff => config.app_restart_backoff.as_cli_value().to_string(),
+            Self::AppRestartBackoffBase => config.app_restart_backoff_base.to_string(),
+            Self::AppRestartBackoffMultiplier => config.app_restart_backoff_multiplier.to_string(),
+            Self::AppRestartBackoffMax => config.app_restart_backoff_max.to_string(),
+            Self::AppRestartJitter => config.app_restart_jitter.to_string(),
+            Self::AppRestartResetAfter => config.app_restart_reset_after.to_string(),
+            Self::AppRestartLimit => config.app_restart_limit.to_string(),
+            Self::AppRestartLimitWindow => config.app_restart_limit_window.to_string(),
+            Self::AppLaunchAttempts => config.app_launch_attempts.to_string(),
             Self::AppExitAction => config.app_exit_default.as_registry_value().to_string(),
+            Self::AppExit => config
+                .app_exit
+                .iter()
+                .map(|(code, action)| format!("{code} {}", action.as_registry_value()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::AppEnvironment => config.app_environment.join("\n"),
             Self::AppEnvironmentExtra => config.app_environment_extra.join("\n"),
+            Self::AppRotateFiles => bool_to_flag(config.app_rotate_files),
+            Self::AppRotateOnline => bool_to_flag(config.app_rotate_online),
+            Self::AppRotateSeconds => config.app_rotate_seconds.to_string(),
+            Self::AppRotateBytes => config.app_rotate_bytes.to_string(),
+            Self::AppRotateKeep => config.app_rotate_keep.to_string(),
+            Self::AppRotateMaxAge => config.app_rotate_max_age.to_string(),
+            Self::AppRotateCompress => config.app_rotate_compress.as_cli_value().to_string(),
+            Self::AppTimestampOutput => bool_to_flag(config.app_timestamp_output),
+            Self::AppTimestampFormat => config.app_timestamp_format.clone(),
+            Self::AppOutputTag => bool_to_flag(config.app_output_tag),
+            Self::AppOutputTimeout => config.app_output_timeout.to_string(),
+            Self::AppLogFormat => config.app_log_format.as_cli_value().to_string(),
+            Self::AppLogDirectory => config
+                .app_log_directory
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Self::AppLogMaxBytes => config.app_log_max_bytes.to_string(),
+            Self::AppLogKeep => config.app_log_keep.to_string(),
+            Self::AppHealthCheck => config
+                .app_health_check
+                .as_ref()
+                .map(HealthCheck::as_cli_value)
+                .unwrap_or_default(),
+            Self::AppHealthCheckStatus => config.app_health_check_status.as_cli_value(),
+            Self::AppHealthCheckInterval => config.app_health_check_interval.to_string(),
+            Self::AppHealthCheckTimeout => config.app_health_check_timeout.to_string(),
+            Self::AppHealthCheckThreshold => config.app_health_check_threshold.to_string(),
+            Self::AppHealthCheckGrace => config.app_health_check_grace.to_string(),
+            Self::AppReadyCheck => config
+                .app_ready_check
+                .as_ref()
+                .map(ReadyCheck::as_cli_value)
+                .unwrap_or_default(),
+            Self::AppReadyTimeout => config.app_ready_timeout.to_string(),
+            Self::AppNotify => bool_to_flag(config.app_notify),
+            Self::AppWatchdogTimeout => config.app_watchdog_timeout.to_string(),
+            Self::AppStartCondition => config
+                .app_start_condition
+                .iter()
+                .map(StartCondition::as_cli_value)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::AppStartTimeout => config.app_start_timeout.to_string(),
         }
     }
 
@@ -211,8 +700,10 @@ impl ServiceParameter {
             Self::AppParameters => "APPPARAMETERS",
             Self::DisplayName => "DISPLAYNAME",
             Self::Description => "DESCRIPTION",
+            Self::Dependencies => "DEPENDENCIES",
             Self::Start => "START",
             Self::AppPriority => "APPPRIORITY",
+            Self::AppAffinity => "APPAFFINITY",
             Self::AppNoConsole => "APPNOCONSOLE",
             Self::AppThrottle => "APPTHROTTLE",
             Self::AppStdout => "APPSTDOUT",
@@ -223,17 +714,109 @@ impl ServiceParameter {
             Self::AppStopMethodWindow => "APPSTOPMETHOD_WINDOW",
             Self::AppStopMethodThreads => "APPSTOPMETHOD_THREADS",
             Self::AppRestartDelay => "APPRESTARTDELAY",
+            Self::AppRestartBackoff => "APPRESTARTBACKOFF",
+            Self::AppRestartBackoffBase => "APPRESTARTBACKOFFBASE",
+            Self::AppRestartBackoffMultiplier => "APPRESTARTBACKOFFMULTIPLIER",
+            Self::AppRestartBackoffMax => "APPRESTARTBACKOFFMAX",
+            Self::AppRestartJitter => "APPRESTARTJITTER",
+            Self::AppRestartResetAfter => "APPRESTARTRESETAFTER",
+            Self::AppRestartLimit => "APPRESTARTLIMIT",
+            Self::AppRestartLimitWindow => "APPRESTARTLIMITWINDOW",
+            Self::AppLaunchAttempts => "APPLAUNCHATTEMPTS",
             Self::AppExitAction => "APPEXITACTION",
+            Self::AppExit => "APPEXIT",
+            Self::AppEnvironment => "APPENVIRONMENT",
             Self::AppEnvironmentExtra => "APPENVIRONMENTEXTRA",
+            Self::AppRotateFiles => "APPROTATEFILES",
+            Self::AppRotateOnline => "APPROTATEONLINE",
+            Self::AppRotateSeconds => "APPROTATESECONDS",
+            Self::AppRotateBytes => "APPROTATEBYTES",
+            Self::AppRotateKeep => "APPROTATEKEEP",
+            Self::AppRotateMaxAge => "APPROTATEMAXAGE",
+            Self::AppRotateCompress => "APPROTATECOMPRESS",
+            Self::AppTimestampOutput => "APPTIMESTAMPOUTPUT",
+            Self::AppTimestampFormat => "APPTIMESTAMPFORMAT",
+            Self::AppOutputTag => "APPOUTPUTTAG",
+            Self::AppOutputTimeout => "APPOUTPUTTIMEOUT",
+            Self::AppLogFormat => "APPLOGFORMAT",
+            Self::AppLogDirectory => "APPLOGDIRECTORY",
+            Self::AppLogMaxBytes => "APPLOGMAXBYTES",
+            Self::AppLogKeep => "APPLOGKEEP",
+            Self::AppHealthCheck => "APPHEALTHCHECK",
+            Self::AppHealthCheckStatus => "APPHEALTHCHECKSTATUS",
+            Self::AppHealthCheckInterval => "APPHEALTHCHECKINTERVAL",
+            Self::AppHealthCheckTimeout => "APPHEALTHCHECKTIMEOUT",
+            Self::AppHealthCheckThreshold => "APPHEALTHCHECKTHRESHOLD",
+            Self::AppHealthCheckGrace => "APPHEALTHCHECKGRACE",
+            Self::AppReadyCheck => "APPREADYCHECK",
+            Self::AppReadyTimeout => "APPREADYTIMEOUT",
+            Self::AppNotify => "APPNOTIFY",
+            Self::AppWatchdogTimeout => "APPWATCHDOGTIMEOUT",
+            Self::AppStartCondition => "APPSTARTCONDITION",
+            Self::AppStartTimeout => "APPSTARTTIMEOUT",
         }
     }
 }
 
+/// Sets the action for one exit code, or the default action for `Default`,
+/// as in `set <service> AppExit 3 Ignore`.
+pub fn apply_exit_entry(config: &mut ServiceConfig, code: &str, action: &str) -> AppResult<()> {
+    let parameter = ServiceParameter::AppExit;
+    let entry = || format!("{code} {action}");
+    let action = ExitAction::from_str(action).ok_or_else(|| AppError::InvalidParameterValue {
+        parameter: parameter.as_str().to_string(),
+        value: entry(),
+        reason: Some(format!(
+            "'{action}' is not an exit action; expected Restart, Ignore, Exit or Suicide"
+        )),
+    })?;
+    if code.eq_ignore_ascii_case("default") {
+        config.app_exit_default = action;
+        return Ok(());
+    }
+    // Exit codes are DWORDs; negative values are accepted as written by
+    // programs that report them as signed.
+    let code = code
+        .parse::<u32>()
+        .or_else(|_| code.parse::<i32>().map(|code| code as u32))
+        .map_err(|_| AppError::InvalidParameterValue {
+            parameter: parameter.as_str().to_string(),
+            value: entry(),
+            reason: Some(format!("'{code}' is not an exit code or 'Default'")),
+        })?;
+    config.app_exit.insert(code, action);
+    Ok(())
+}
+
 fn parse_u32(parameter: ServiceParameter, value: &str) -> AppResult<u32> {
-    value.parse().map_err(|_| AppError::InvalidParameterValue {
+    value.parse().map_err(|_| invalid(parameter, value))
+}
+
+fn parse_nonzero_u32(parameter: ServiceParameter, value: &str) -> AppResult<u32> {
+    match parse_u32(parameter, value)? {
+        0 => Err(AppError::InvalidParameterValue {
+            parameter: parameter.as_str().to_string(),
+            value: value.to_string(),
+            reason: Some("must be at least 1".to_string()),
+        }),
+        value => Ok(value),
+    }
+}
+
+fn invalid(parameter: ServiceParameter, value: &str) -> AppError {
+    AppError::InvalidParameterValue {
         parameter: parameter.as_str().to_string(),
         value: value.to_string(),
-    })
+        reason: None,
+    }
+}
+
+fn list_lines(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
 }
 
 fn empty_to_none_string(value: &str) -> Option<String> {
@@ -288,15 +871,75 @@ mod tests {
             (ServiceParameter::Application, r"C:\app.exe"),
             (ServiceParameter::AppParameters, "--port 80"),
             (ServiceParameter::AppThrottle, "3000"),
+            (ServiceParameter::AppRestartBackoff, "Linear"),
+            (ServiceParameter::AppRestartBackoffBase, "500"),
+            (ServiceParameter::AppRestartBackoffMultiplier, "3"),
+            (ServiceParameter::AppRestartBackoffMax, "60000"),
+            (ServiceParameter::AppRestartJitter, "20"),
+            (ServiceParameter::AppRestartResetAfter, "600000"),
+            (ServiceParameter::AppRestartLimit, "5"),
+            (ServiceParameter::AppRestartLimitWindow, "900"),
+            (ServiceParameter::AppLaunchAttempts, "0"),
+            (ServiceParameter::AppAffinity, "0-3,6,8-9"),
             (ServiceParameter::AppExitAction, "Ignore"),
             (ServiceParameter::AppNoConsole, "1"),
             (ServiceParameter::AppStdout, r"C:\logs\out.log"),
+            (ServiceParameter::AppRotateFiles, "1"),
+            (ServiceParameter::AppRotateOnline, "1"),
+            (ServiceParameter::AppRotateSeconds, "3600"),
+            (ServiceParameter::AppRotateBytes, "10737418240"),
+            (ServiceParameter::AppRotateKeep, "10"),
+            (ServiceParameter::AppRotateMaxAge, "604800"),
+            (ServiceParameter::AppRotateCompress, "Zstd"),
+            (ServiceParameter::AppTimestampOutput, "1"),
+            (ServiceParameter::AppTimestampFormat, "%H:%M:%S"),
+            (ServiceParameter::AppOutputTag, "1"),
+            (ServiceParameter::AppOutputTimeout, "120000"),
+            (ServiceParameter::AppLogFormat, "Logfmt"),
+            (ServiceParameter::AppLogDirectory, r"D:\logs"),
+            (ServiceParameter::AppLogMaxBytes, "0"),
+            (ServiceParameter::AppLogKeep, "20"),
+            (
+                ServiceParameter::AppHealthCheck,
+                "http://127.0.0.1:8080/health?deep=1",
+            ),
+            (ServiceParameter::AppHealthCheckStatus, "200-299"),
+            (ServiceParameter::AppHealthCheckInterval, "2000"),
+            (ServiceParameter::AppHealthCheckTimeout, "500"),
+            (ServiceParameter::AppHealthCheckThreshold, "5"),
+            (ServiceParameter::AppHealthCheckGrace, "30000"),
+            (
+                ServiceParameter::AppReadyCheck,
+                "stdout:^Listening on :\\d+",
+            ),
+            (ServiceParameter::AppReadyTimeout, "0"),
+            (ServiceParameter::AppNotify, "1"),
+            (ServiceParameter::AppWatchdogTimeout, "30000"),
+            (
+                ServiceParameter::AppStartCondition,
+                "tcp://db.internal:5432\npath:\\\\fileserver\\share\nservice:Postgres",
+            ),
+            (ServiceParameter::AppStartTimeout, "0"),
         ] {
             parameter.apply(&mut config, value).unwrap();
             assert_eq!(parameter.read(&config), value, "{}", parameter.as_str());
         }
     }
 
+    #[test]
+    fn all_lists_every_parameter_once() {
+        for parameter in ServiceParameter::ALL {
+            assert_eq!(
+                ServiceParameter::parse(parameter.as_str()).unwrap(),
+                *parameter
+            );
+        }
+        let mut names: Vec<_> = ServiceParameter::ALL.iter().map(|p| p.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), ServiceParameter::ALL.len());
+    }
+
     #[test]
     fn apply_rejects_empty_application() {
         let mut config = ServiceConfig::default();
@@ -329,6 +972,299 @@ mod tests {
                 .apply(&mut config, "TURBO")
                 .is_err()
         );
+        assert!(
+            ServiceParameter::AppRotateBytes
+                .apply(&mut config, "-1")
+                .is_err()
+        );
+        assert!(
+            ServiceParameter::AppTimestampFormat
+                .apply(&mut config, "%Y-%Q")
+                .is_err()
+        );
+        assert!(
+            ServiceParameter::AppRestartBackoff
+                .apply(&mut config, "Random")
+                .is_err()
+        );
+        assert!(
+            ServiceParameter::AppRestartJitter
+                .apply(&mut config, "101")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn exit_actions_are_looked_up_by_code() {
+        let mut config = ServiceConfig::default();
+        apply_exit_entry(&mut config, "0", "Exit").unwrap();
+        apply_exit_entry(&mut config, "3", "ignore").unwrap();
+        apply_exit_entry(&mut config, "-1", "Suicide").unwrap();
+        apply_exit_entry(&mut config, "default", "Exit").unwrap();
+
+        assert_eq!(config.exit_action(0), ExitAction::Exit);
+        assert_eq!(config.exit_action(3), ExitAction::Ignore);
+        assert_eq!(config.exit_action(u32::MAX), ExitAction::Suicide);
+        assert_eq!(config.exit_action(4), ExitAction::Exit);
+        assert_eq!(config.app_exit_default, ExitAction::Exit);
+
+        apply_exit_entry(&mut config, "3", "Restart").unwrap();
+        assert_eq!(config.exit_action(3), ExitAction::Restart);
+    }
+
+    #[test]
+    fn exit_table_round_trips_and_resets() {
+        let mut config = ServiceConfig::default();
+        let parameter = ServiceParameter::AppExit;
+        assert_eq!(parameter.read(&config), "");
+
+        parameter
+            .apply(&mut config, "Default Ignore\n3 Exit\n0 Suicide")
+            .unwrap();
+        assert_eq!(parameter.read(&config), "0 Suicide\n3 Exit");
+        assert_eq!(ServiceParameter::AppExitAction.read(&config), "Ignore");
+
+        parameter
+            .apply(&mut config, &parameter.reset_value().unwrap())
+            .unwrap();
+        assert!(config.app_exit.is_empty());
+        assert_eq!(config.app_exit_default, ExitAction::Ignore);
+    }
+
+    #[test]
+    fn exit_entries_reject_bad_codes_and_actions() {
+        let mut config = ServiceConfig::default();
+        let error = apply_exit_entry(&mut config, "3", "Reboot").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid value '3 Reboot' for parameter 'APPEXIT': 'Reboot' is not an exit \
+             action; expected Restart, Ignore, Exit or Suicide"
+        );
+        assert!(apply_exit_entry(&mut config, "three", "Exit").is_err());
+        assert!(
+            ServiceParameter::AppExit
+                .apply(&mut config, "3 Exit\n4")
+                .is_err()
+        );
+        assert!(config.app_exit.is_empty());
+    }
+
+    #[test]
+    fn affinity_accepts_nssm_lists() {
+        let mut config = ServiceConfig::default();
+        let parameter = ServiceParameter::AppAffinity;
+        assert_eq!(parameter.read(&config), "All");
+
+        parameter.apply(&mut config, "3, 1-2,0,7").unwrap();
+        assert_eq!(config.app_affinity.unwrap().mask(), 0b1000_1111);
+        assert_eq!(parameter.read(&config), "0-3,7");
+
+        parameter.apply(&mut config, "63").unwrap();
+        assert_eq!(config.app_affinity.unwrap().mask(), 1 << 63);
+
+        parameter.apply(&mut config, "all").unwrap();
+        assert_eq!(config.app_affinity, None);
+        parameter.apply(&mut config, "0").unwrap();
+        parameter.apply(&mut config, "").unwrap();
+        assert_eq!(config.app_affinity, None);
+    }
+
+    #[test]
+    fn affinity_errors_explain_the_problem() {
+        let mut config = ServiceConfig::default();
+        for (value, reason) in [
+            ("0-3,x", "'x' is not a CPU number"),
+            ("4-2", "range 4-2 is backwards"),
+            ("64", "CPU 64 is out of range; CPUs are numbered 0 to 63"),
+            ("0,,1", "empty entry in CPU list"),
+            ("-1", "'' is not a CPU number"),
+        ] {
+            let error = ServiceParameter::AppAffinity
+                .apply(&mut config, value)
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Invalid value '{value}' for parameter 'APPAFFINITY': {reason}")
+            );
+        }
+        assert_eq!(config.app_affinity, None);
+    }
+
+    #[test]
+    fn health_checks_parse_each_kind() {
+        let mut config = ServiceConfig::default();
+        let parameter = ServiceParameter::AppHealthCheck;
+        for (value, check, read) in [
+            (
+                "HTTP://localhost",
+                HealthCheck::Http {
+                    host: "localhost".to_string(),
+                    port: 80,
+                    path: "/".to_string(),
+                },
+                "http://localhost:80/",
+            ),
+            (
+                "http://[::1]:8080/ready",
+                HealthCheck::Http {
+                    host: "::1".to_string(),
+                    port: 8080,
+                    path: "/ready".to_string(),
+                },
+                "http://[::1]:8080/ready",
+            ),
+            (
+                "tcp://db.local:5432",
+                HealthCheck::Tcp {
+                    host: "db.local".to_string(),
+                    port: 5432,
+                },
+                "tcp://db.local:5432",
+            ),
+            (
+                "exec: check.cmd --quick",
+                HealthCheck::Exec("check.cmd --quick".to_string()),
+                "exec:check.cmd --quick",
+            ),
+        ] {
+            parameter.apply(&mut config, value).unwrap();
+            assert_eq!(config.app_health_check.as_ref(), Some(&check), "{value}");
+            assert_eq!(parameter.read(&config), read);
+        }
+
+        parameter.apply(&mut config, "").unwrap();
+        assert_eq!(config.app_health_check, None);
+    }
+
+    #[test]
+    fn health_check_errors_explain_the_problem() {
+        let mut config = ServiceConfig::default();
+        for (parameter, value, reason) in [
+            (
+                ServiceParameter::AppHealthCheck,
+                "https://localhost/",
+                "HTTPS health checks are not supported; check a plain HTTP port or use exec:",
+            ),
+            (
+                ServiceParameter::AppHealthCheck,
+                "tcp://localhost",
+                "the port is missing",
+            ),
+            (
+                ServiceParameter::AppHealthCheck,
+                "http://localhost:0/",
+                "'0' is not a port number",
+            ),
+            (
+                ServiceParameter::AppHealthCheck,
+                "ping localhost",
+                "expected http://host[:port][/path], tcp://host:port or exec:<command line>",
+            ),
+            (
+                ServiceParameter::AppHealthCheckStatus,
+                "399-200",
+                "range 399-200 is backwards",
+            ),
+            (
+                ServiceParameter::AppHealthCheckStatus,
+                "2xx",
+                "'2xx' is not an HTTP status from 100 to 599",
+            ),
+            (
+                ServiceParameter::AppHealthCheckThreshold,
+                "0",
+                "must be at least 1",
+            ),
+        ] {
+            let error = parameter.apply(&mut config, value).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Invalid value '{value}' for parameter '{}': {reason}",
+                    parameter.as_str()
+                )
+            );
+        }
+        assert_eq!(config, ServiceConfig::default());
+    }
+
+    #[test]
+    fn ready_checks_parse_each_kind() {
+        let mut config = ServiceConfig::default();
+        let parameter = ServiceParameter::AppReadyCheck;
+        for (value, check) in [
+            (
+                "tcp://127.0.0.1:8080",
+                ReadyCheck::Tcp {
+                    host: "127.0.0.1".to_string(),
+                    port: 8080,
+                },
+            ),
+            (
+                r"file:C:\app\ready.flag",
+                ReadyCheck::File(PathBuf::from(r"C:\app\ready.flag")),
+            ),
+            ("notify", ReadyCheck::Notify),
+            (
+                "stdout:Server started",
+                ReadyCheck::Stdout("Server started".to_string()),
+            ),
+        ] {
+            parameter.apply(&mut config, value).unwrap();
+            assert_eq!(config.app_ready_check.as_ref(), Some(&check), "{value}");
+            assert_eq!(parameter.read(&config), value);
+        }
+
+        assert!(parameter.apply(&mut config, "stdout:(unclosed").is_err());
+        assert!(parameter.apply(&mut config, "file:").is_err());
+        assert!(parameter.apply(&mut config, "http://localhost/").is_err());
+        assert_eq!(
+            config.app_ready_check,
+            Some(ReadyCheck::Stdout("Server started".to_string()))
+        );
+        parameter.apply(&mut config, "").unwrap();
+        assert_eq!(config.app_ready_check, None);
+    }
+
+    #[test]
+    fn start_conditions_parse_one_per_line() {
+        let mut config = ServiceConfig::default();
+        let parameter = ServiceParameter::AppStartCondition;
+        parameter
+            .apply(
+                &mut config,
+                "TCP://[::1]:5432\n\npath:D:\\data\nservice: Postgres ",
+            )
+            .unwrap();
+        assert_eq!(
+            config.app_start_condition,
+            vec![
+                StartCondition::Tcp {
+                    host: "::1".to_string(),
+                    port: 5432,
+                },
+                StartCondition::Path(PathBuf::from(r"D:\data")),
+                StartCondition::Service("Postgres".to_string()),
+            ]
+        );
+        assert_eq!(
+            parameter.read(&config),
+            "tcp://[::1]:5432\npath:D:\\data\nservice:Postgres"
+        );
+
+        // One bad entry rejects the whole value and names the entry.
+        let error = parameter
+            .apply(&mut config, "service:Postgres\ntcp://db")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid value 'tcp://db' for parameter 'APPSTARTCONDITION': the port is missing"
+        );
+        assert!(parameter.apply(&mut config, "http://db:80").is_err());
+        assert!(parameter.apply(&mut config, "service:").is_err());
+        assert_eq!(config.app_start_condition.len(), 3);
+        parameter.apply(&mut config, "").unwrap();
+        assert!(config.app_start_condition.is_empty());
     }
 
     #[test]
@@ -344,6 +1280,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn environment_replaces_separately_from_extra() {
+        let mut config = ServiceConfig::default();
+        ServiceParameter::AppEnvironment
+            .apply(&mut config, "PATH=C:\\bin\nTEMP=C:\\tmp")
+            .unwrap();
+        assert_eq!(config.app_environment, vec!["PATH=C:\\bin", "TEMP=C:\\tmp"]);
+        assert!(config.app_environment_extra.is_empty());
+        assert_eq!(
+            ServiceParameter::AppEnvironment.read(&config),
+            "PATH=C:\\bin\nTEMP=C:\\tmp"
+        );
+    }
+
     #[test]
     fn start_default_matches_install_default() {
         use crate::config::ServiceStartType;
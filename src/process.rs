@@ -0,0 +1,881 @@
+//! Platform control over a launched application.
+//!
+//! On Windows the application is put in a kill-on-close job object, given
+//! its AppPriority and AppAffinity, and stopped with console Ctrl-C,
+//! WM_CLOSE, WM_QUIT and TerminateProcess. On POSIX systems it runs in its
+//! own process group, which the same AppStopMethodSkip steps signal with
+//! SIGINT, SIGTERM, SIGHUP and SIGKILL.
+
+use std::fmt::{self, Display, Formatter};
+use std::process::{Child, Command, ExitStatus};
+use std::time::Duration;
+
+use crate::config::ServiceConfig;
+use crate::error::AppResult;
+
+#[cfg(unix)]
+pub use posix::*;
+#[cfg(windows)]
+pub use windows_impl::*;
+
+/// Grace period after the last stop method, which cannot be ignored,
+/// before giving up waiting.
+const KILL_WAIT: Duration = Duration::from_millis(5000);
+
+/// The steps of the stop sequence, in the order they are tried. Each can be
+/// skipped with its AppStopMethodSkip bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMethod {
+    /// Ctrl-C on Windows, SIGINT elsewhere.
+    Console,
+    /// WM_CLOSE on Windows, SIGTERM elsewhere.
+    Window,
+    /// WM_QUIT on Windows, SIGHUP elsewhere.
+    Threads,
+    /// TerminateProcess on Windows, SIGKILL elsewhere.
+    Terminate,
+}
+
+impl StopMethod {
+    pub const ALL: [Self; 4] = [Self::Console, Self::Window, Self::Threads, Self::Terminate];
+
+    fn skip_bit(self) -> u32 {
+        match self {
+            Self::Console => 1,
+            Self::Window => 2,
+            Self::Threads => 4,
+            Self::Terminate => 8,
+        }
+    }
+
+    pub fn skipped(self, config: &ServiceConfig) -> bool {
+        config.app_stop_method_skip & self.skip_bit() != 0
+    }
+
+    /// How long to wait for the application to exit after this step.
+    pub fn timeout(self, config: &ServiceConfig) -> Duration {
+        match self {
+            Self::Console => Duration::from_millis(config.app_stop_method_console.into()),
+            Self::Window => Duration::from_millis(config.app_stop_method_window.into()),
+            Self::Threads => Duration::from_millis(config.app_stop_method_threads.into()),
+            Self::Terminate => KILL_WAIT,
+        }
+    }
+}
+
+/// How an application ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildExit {
+    Code(i32),
+    /// Killed by this signal (POSIX only).
+    #[cfg_attr(windows, allow(dead_code))]
+    Signal(i32),
+}
+
+impl ChildExit {
+    /// The exit code AppExit actions and the service's exit code use. A
+    /// signal counts as 128 plus its number, as in POSIX shells.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Code(code) => code,
+            Self::Signal(signal) => 128 + signal,
+        }
+    }
+}
+
+impl Display for ChildExit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "exited with code {code}"),
+            Self::Signal(signal) => match signal_name(*signal) {
+                Some(name) => write!(f, "was killed by signal {signal} ({name})"),
+                None => write!(f, "was killed by signal {signal}"),
+            },
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        6 => "SIGABRT",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}
+
+/// What the supervisor needs from the platform to run and stop an
+/// application.
+pub trait ProcessControl: Sync {
+    /// Adjusts the command before the application is spawned.
+    fn configure(&self, command: &mut Command);
+
+    /// Ties a spawned application to the supervisor so it is cleaned up
+    /// with the returned guard.
+    fn attach(&self, child: &Child) -> AppResult<ChildGuard>;
+
+    /// Something another thread can block on until the application exits.
+    fn exit_waiter(&self, child: &Child) -> AppResult<ExitWaiter>;
+
+    /// Applies AppPriority and AppAffinity to a spawned application.
+    fn apply_scheduling(&self, child: &Child, config: &ServiceConfig) -> AppResult<()>;
+
+    /// Whether `method` can reach the application at all.
+    fn supports(&self, method: StopMethod, config: &ServiceConfig) -> bool;
+
+    /// The name of `method` in log messages.
+    fn describe(&self, method: StopMethod) -> &'static str;
+
+    /// Sends one step of the stop sequence; returns whether it was sent.
+    fn send_stop(&self, child: &mut Child, method: StopMethod) -> bool;
+
+    fn exit_of(&self, status: ExitStatus) -> ChildExit;
+}
+
+/// Ties the child to the supervisor, prepares to wait for its exit and
+/// applies AppPriority and AppAffinity. On failure the child is killed, so
+/// it never runs outside the supervisor's control.
+pub fn prepare_child(
+    child: &mut Child,
+    config: &ServiceConfig,
+) -> AppResult<(ChildGuard, ExitWaiter)> {
+    let control = platform();
+    let child_id = child.id();
+    let prepared = control.attach(child).and_then(|guard| {
+        let waiter = control.exit_waiter(child)?;
+        Ok((guard, waiter))
+    });
+    let prepared = match prepared {
+        Ok(prepared) => prepared,
+        Err(error) => {
+            log::error!("Failed to attach child process {child_id} to cleanup job: {error}");
+            kill_after_launch_failure(child);
+            return Err(error);
+        }
+    };
+
+    control.apply_scheduling(child, config)?;
+    Ok(prepared)
+}
+
+fn kill_after_launch_failure(child: &mut Child) {
+    if let Err(error) = child.kill() {
+        log::warn!(
+            "Failed to kill child process {} after launch setup failed: {error}",
+            child.id()
+        );
+    }
+    let _ = child.wait();
+}
+
+#[cfg(windows)]
+mod windows_impl {
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::{Child, Command, ExitStatus};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use log::warn;
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::System::Console::CTRL_C_EVENT;
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+        SetInformationJobObject,
+    };
+    use windows::Win32::System::Threading::{PROCESS_CREATION_FLAGS, SetPriorityClass};
+    use windows::core::PCWSTR;
+
+    use super::{ChildExit, ProcessControl, StopMethod};
+    use crate::config::{CpuAffinity, ServiceConfig};
+    use crate::error::AppResult;
+
+    /// Set while we raise Ctrl-C on a console we share with the child, so
+    /// our own handler can tell that event from one the user typed.
+    static RAISING_CTRL_C: AtomicBool = AtomicBool::new(false);
+
+    pub struct WindowsProcesses;
+
+    pub fn platform() -> &'static dyn ProcessControl {
+        &WindowsProcesses
+    }
+
+    /// Kill-on-close job object holding the application and everything it
+    /// starts, so nothing outlives the supervisor.
+    pub struct ChildGuard {
+        handle: HANDLE,
+    }
+
+    impl ChildGuard {
+        /// `Child::try_wait`; the job object does not depend on the
+        /// application's process staying unreaped.
+        pub fn try_wait(&mut self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+            child.try_wait()
+        }
+    }
+
+    impl Drop for ChildGuard {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = CloseHandle(self.handle);
+            }
+        }
+    }
+
+    /// A second handle to the application's process, so waiting on it
+    /// does not depend on the `Child` staying alive.
+    pub struct ExitWaiter {
+        handle: HANDLE,
+    }
+
+    // A process handle can be waited on from any thread.
+    unsafe impl Send for ExitWaiter {}
+
+    impl ExitWaiter {
+        /// Blocks until the process exits; `Child::try_wait` then reads its
+        /// exit code.
+        pub fn wait(self) -> io::Result<()> {
+            use windows::Win32::Foundation::WAIT_OBJECT_0;
+            use windows::Win32::System::Threading::{INFINITE, WaitForSingleObject};
+
+            if unsafe { WaitForSingleObject(self.handle, INFINITE) } == WAIT_OBJECT_0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+    }
+
+    impl Drop for ExitWaiter {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = CloseHandle(self.handle);
+            }
+        }
+    }
+
+    impl ProcessControl for WindowsProcesses {
+        fn configure(&self, _command: &mut Command) {}
+
+        fn attach(&self, child: &Child) -> AppResult<ChildGuard> {
+            unsafe {
+                let job = ChildGuard {
+                    handle: CreateJobObjectW(None, PCWSTR::null())?,
+                };
+                let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+                limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+                SetInformationJobObject(
+                    job.handle,
+                    JobObjectExtendedLimitInformation,
+                    &limits as *const _ as *const _,
+                    std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                )?;
+                AssignProcessToJobObject(job.handle, HANDLE(child.as_raw_handle()))?;
+
+                Ok(job)
+            }
+        }
+
+        fn exit_waiter(&self, child: &Child) -> AppResult<ExitWaiter> {
+            use windows::Win32::Foundation::{DUPLICATE_SAME_ACCESS, DuplicateHandle};
+            use windows::Win32::System::Threading::GetCurrentProcess;
+
+            let mut handle = HANDLE::default();
+            unsafe {
+                DuplicateHandle(
+                    GetCurrentProcess(),
+                    HANDLE(child.as_raw_handle()),
+                    GetCurrentProcess(),
+                    &mut handle,
+                    0,
+                    false,
+                    DUPLICATE_SAME_ACCESS,
+                )?;
+            }
+            Ok(ExitWaiter { handle })
+        }
+
+        fn apply_scheduling(&self, child: &Child, config: &ServiceConfig) -> AppResult<()> {
+            set_priority(child.id(), config)?;
+            set_affinity(child.id(), config);
+            Ok(())
+        }
+
+        fn supports(&self, method: StopMethod, config: &ServiceConfig) -> bool {
+            method != StopMethod::Console || !config.app_no_console
+        }
+
+        fn describe(&self, method: StopMethod) -> &'static str {
+            match method {
+                StopMethod::Console => "Ctrl-C",
+                StopMethod::Window => "WM_CLOSE",
+                StopMethod::Threads => "WM_QUIT",
+                StopMethod::Terminate => "TerminateProcess",
+            }
+        }
+
+        fn send_stop(&self, child: &mut Child, method: StopMethod) -> bool {
+            match method {
+                StopMethod::Console => send_ctrl_c(child.id()),
+                StopMethod::Window => post_close(child.id()),
+                StopMethod::Threads => post_quit(child.id()),
+                StopMethod::Terminate => match child.kill() {
+                    Ok(()) => true,
+                    Err(error) => {
+                        warn!("Failed to kill child process: {error}");
+                        false
+                    }
+                },
+            }
+        }
+
+        /// ExitStatus::code() is always Some on Windows; a process killed
+        /// via TerminateProcess reports the exit code passed to that call.
+        fn exit_of(&self, status: ExitStatus) -> ChildExit {
+            ChildExit::Code(status.code().unwrap_or(1))
+        }
+    }
+
+    fn set_priority(child_id: u32, config: &ServiceConfig) -> AppResult<()> {
+        unsafe {
+            use windows::Win32::System::Threading::{OpenProcess, PROCESS_SET_INFORMATION};
+
+            match OpenProcess(PROCESS_SET_INFORMATION, false, child_id) {
+                Ok(process_handle) => {
+                    let _ = SetPriorityClass(
+                        process_handle,
+                        PROCESS_CREATION_FLAGS(config.app_priority.to_windows_value()),
+                    );
+                    let _ = CloseHandle(process_handle);
+                    Ok(())
+                }
+                Err(error) => {
+                    warn!("Failed to set process priority for child process: {error}");
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Restricts the child to AppAffinity's CPUs, leaving out any this
+    /// machine does not have. Failures are logged; the child keeps running
+    /// unpinned.
+    fn set_affinity(child_id: u32, config: &ServiceConfig) {
+        use windows::Win32::System::Threading::{
+            GetCurrentProcess, GetProcessAffinityMask, OpenProcess, PROCESS_QUERY_INFORMATION,
+            PROCESS_SET_INFORMATION, SetProcessAffinityMask,
+        };
+
+        let Some(affinity) = config.app_affinity else {
+            return;
+        };
+
+        let mut process_mask = 0usize;
+        let mut system_mask = 0usize;
+        if let Err(error) = unsafe {
+            GetProcessAffinityMask(GetCurrentProcess(), &mut process_mask, &mut system_mask)
+        } {
+            warn!("Failed to query the system CPU affinity mask: {error}");
+            return;
+        }
+
+        let requested = affinity.mask() as usize;
+        let mask = requested & system_mask;
+        if mask == 0 {
+            warn!(
+                "AppAffinity {} names no CPU on this system; not setting CPU affinity",
+                affinity.as_cli_value()
+            );
+            return;
+        }
+        if mask != requested {
+            warn!(
+                "AppAffinity {} includes CPUs this system does not have; using {}",
+                affinity.as_cli_value(),
+                CpuAffinity::from_mask(mask as u64)
+                    .map_or_else(String::new, |mask| mask.as_cli_value())
+            );
+        }
+
+        unsafe {
+            match OpenProcess(
+                PROCESS_SET_INFORMATION | PROCESS_QUERY_INFORMATION,
+                false,
+                child_id,
+            ) {
+                Ok(process_handle) => {
+                    if let Err(error) = SetProcessAffinityMask(process_handle, mask) {
+                        warn!("Failed to set CPU affinity for child process: {error}");
+                    }
+                    let _ = CloseHandle(process_handle);
+                }
+                Err(error) => warn!("Failed to set CPU affinity for child process: {error}"),
+            }
+        }
+    }
+
+    /// Whether a Ctrl-C event just delivered to this process was raised by
+    /// the stop sequence rather than typed by the user. Clears the mark.
+    pub fn take_raised_ctrl_c() -> bool {
+        RAISING_CTRL_C.swap(false, Ordering::SeqCst)
+    }
+
+    fn send_ctrl_c(child_id: u32) -> bool {
+        unsafe {
+            use windows::Win32::System::Console::{
+                AllocConsole, AttachConsole, FreeConsole, GenerateConsoleCtrlEvent,
+                GetConsoleProcessList,
+            };
+
+            // Process group 0 signals every process attached to the console,
+            // this one included; the mark lets our handler ignore it.
+            let raise = || {
+                RAISING_CTRL_C.store(true, Ordering::SeqCst);
+                let sent = GenerateConsoleCtrlEvent(CTRL_C_EVENT, 0).is_ok();
+                if !sent {
+                    RAISING_CTRL_C.store(false, Ordering::SeqCst);
+                    warn!("Failed to send Ctrl-C event");
+                }
+                sent
+            };
+
+            let mut attached = [0u32; 64];
+            let count = GetConsoleProcessList(&mut attached) as usize;
+            if attached[..count.min(attached.len())].contains(&child_id) {
+                return raise();
+            }
+
+            // A process can only be attached to one console at a time, so
+            // borrow the child's and then take a fresh one of our own for
+            // subsequent children and stop attempts.
+            let _ = FreeConsole();
+            let sent = if AttachConsole(child_id).is_ok() {
+                let sent = raise();
+                let _ = FreeConsole();
+                sent
+            } else {
+                warn!("Failed to attach to child process console; skipping Ctrl-C");
+                false
+            };
+            let _ = AllocConsole();
+            sent
+        }
+    }
+
+    fn post_close(child_id: u32) -> bool {
+        unsafe {
+            use windows::Win32::Foundation::{HWND, LPARAM, TRUE};
+            use windows::Win32::UI::WindowsAndMessaging::{
+                EnumWindows, GetWindowThreadProcessId, PostMessageW, WM_CLOSE,
+            };
+            use windows::core::BOOL;
+
+            unsafe extern "system" fn enum_window_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+                let target_pid = lparam.0 as u32;
+                let mut window_pid = 0u32;
+                unsafe {
+                    GetWindowThreadProcessId(hwnd, Some(&mut window_pid));
+                    if window_pid == target_pid {
+                        let _ = PostMessageW(
+                            Some(hwnd),
+                            WM_CLOSE,
+                            windows::Win32::Foundation::WPARAM(0),
+                            LPARAM(0),
+                        );
+                    }
+                }
+                TRUE
+            }
+
+            let _ = EnumWindows(Some(enum_window_proc), LPARAM(child_id as isize));
+        }
+        true
+    }
+
+    fn post_quit(child_id: u32) -> bool {
+        unsafe {
+            use windows::Win32::Foundation::{LPARAM, WPARAM};
+            use windows::Win32::System::Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, TH32CS_SNAPTHREAD, THREADENTRY32, Thread32First,
+                Thread32Next,
+            };
+            use windows::Win32::UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT};
+
+            let snapshot = match CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) {
+                Ok(snapshot) => snapshot,
+                Err(error) => {
+                    warn!("Failed to snapshot threads for WM_QUIT: {error}");
+                    return false;
+                }
+            };
+
+            let mut entry = THREADENTRY32 {
+                dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+                ..Default::default()
+            };
+            if Thread32First(snapshot, &mut entry).is_ok() {
+                loop {
+                    if entry.th32OwnerProcessID == child_id {
+                        let _ =
+                            PostThreadMessageW(entry.th32ThreadID, WM_QUIT, WPARAM(0), LPARAM(0));
+                    }
+                    entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+                    if Thread32Next(snapshot, &mut entry).is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = CloseHandle(snapshot);
+        }
+        true
+    }
+}
+
+#[cfg(unix)]
+mod posix {
+    use std::io;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::{Child, Command, ExitStatus};
+
+    use log::warn;
+
+    use super::{ChildExit, ProcessControl, StopMethod};
+    use crate::config::{ProcessPriority, ServiceConfig};
+    use crate::error::AppResult;
+
+    pub struct PosixProcesses;
+
+    pub fn platform() -> &'static dyn ProcessControl {
+        &PosixProcesses
+    }
+
+    /// The application's process group. Dropping the guard kills whatever
+    /// is left in it, as closing the job object does on Windows.
+    ///
+    /// The group ID is the application's PID, which is only ours while the
+    /// application is unreaped, so the group is never signalled after that.
+    pub struct ChildGuard {
+        process_group: libc::pid_t,
+        leader_reaped: bool,
+    }
+
+    impl ChildGuard {
+        /// `Child::try_wait`, killing what is left of the process group
+        /// once the application has exited but before it is reaped.
+        pub fn try_wait(&mut self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+            if !self.leader_reaped && leader_exited(self.process_group)? {
+                self.kill_group();
+            }
+            let status = child.try_wait()?;
+            if status.is_some() {
+                self.leader_reaped = true;
+            }
+            Ok(status)
+        }
+
+        fn kill_group(&self) {
+            // ESRCH just means the group is already empty.
+            unsafe {
+                libc::kill(-self.process_group, libc::SIGKILL);
+            }
+        }
+    }
+
+    impl Drop for ChildGuard {
+        fn drop(&mut self) {
+            if !self.leader_reaped {
+                self.kill_group();
+            }
+        }
+    }
+
+    /// Whether `pid` has exited, leaving it unreaped.
+    fn leader_exited(pid: libc::pid_t) -> io::Result<bool> {
+        loop {
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let result = unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    pid as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+                )
+            };
+            if result == 0 {
+                // With WNOHANG, a still running process leaves si_pid zero.
+                return Ok(unsafe { info.si_pid() } != 0);
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // Already reaped: there is no group left to kill safely.
+                Some(libc::ECHILD) => return Ok(false),
+                _ => return Err(error),
+            }
+        }
+    }
+
+    pub struct ExitWaiter {
+        pid: libc::pid_t,
+    }
+
+    impl ExitWaiter {
+        /// Blocks until the process exits. The process is left unreaped,
+        /// so its PID stays ours until `Child::try_wait` collects it.
+        pub fn wait(self) -> io::Result<()> {
+            loop {
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                let result = unsafe {
+                    libc::waitid(
+                        libc::P_PID,
+                        self.pid as libc::id_t,
+                        &mut info,
+                        libc::WEXITED | libc::WNOWAIT,
+                    )
+                };
+                if result == 0 {
+                    return Ok(());
+                }
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // Already reaped by the stop sequence.
+                    Some(libc::ECHILD) => return Ok(()),
+                    _ => return Err(error),
+                }
+            }
+        }
+    }
+
+    impl ProcessControl for PosixProcesses {
+        /// Starts the application as the leader of a new process group, so
+        /// signals reach everything it starts but not the supervisor, and
+        /// on Linux has it killed if the supervisor dies first.
+        fn configure(&self, command: &mut Command) {
+            command.process_group(0);
+
+            #[cfg(target_os = "linux")]
+            {
+                let supervisor = unsafe { libc::getpid() };
+                // Only async-signal-safe calls between fork and exec. The
+                // death signal follows the spawning thread, which is the
+                // supervisor loop's for the application's whole life.
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+                            return Err(io::Error::last_os_error());
+                        }
+                        // The supervisor died before the death signal was armed.
+                        if libc::getppid() != supervisor {
+                            libc::_exit(1);
+                        }
+                        Ok(())
+                    });
+                }
+            }
+        }
+
+        fn attach(&self, child: &Child) -> AppResult<ChildGuard> {
+            Ok(ChildGuard {
+                process_group: child.id() as libc::pid_t,
+                leader_reaped: false,
+            })
+        }
+
+        fn exit_waiter(&self, child: &Child) -> AppResult<ExitWaiter> {
+            Ok(ExitWaiter {
+                pid: child.id() as libc::pid_t,
+            })
+        }
+
+        fn apply_scheduling(&self, _child: &Child, config: &ServiceConfig) -> AppResult<()> {
+            if config.app_priority != ProcessPriority::Normal {
+                warn!("AppPriority is only supported on Windows; ignoring it");
+            }
+            if config.app_affinity.is_some() {
+                warn!("AppAffinity is only supported on Windows; ignoring it");
+            }
+            Ok(())
+        }
+
+        fn supports(&self, _method: StopMethod, _config: &ServiceConfig) -> bool {
+            true
+        }
+
+        fn describe(&self, method: StopMethod) -> &'static str {
+            match method {
+                StopMethod::Console => "SIGINT",
+                StopMethod::Window => "SIGTERM",
+                StopMethod::Threads => "SIGHUP",
+                StopMethod::Terminate => "SIGKILL",
+            }
+        }
+
+        fn send_stop(&self, child: &mut Child, method: StopMethod) -> bool {
+            let signal = match method {
+                StopMethod::Console => libc::SIGINT,
+                StopMethod::Window => libc::SIGTERM,
+                StopMethod::Threads => libc::SIGHUP,
+                StopMethod::Terminate => libc::SIGKILL,
+            };
+            // The child has not been reaped while we hold it, so its PID,
+            // and with it the process group, cannot have been reused.
+            if unsafe { libc::kill(-(child.id() as libc::pid_t), signal) } == 0 {
+                true
+            } else {
+                warn!(
+                    "Failed to send {} to process group {}: {}",
+                    self.describe(method),
+                    child.id(),
+                    io::Error::last_os_error()
+                );
+                false
+            }
+        }
+
+        fn exit_of(&self, status: ExitStatus) -> ChildExit {
+            match (status.code(), status.signal()) {
+                (Some(code), _) => ChildExit::Code(code),
+                (None, Some(signal)) => ChildExit::Signal(signal),
+                (None, None) => ChildExit::Code(1),
+            }
+        }
+    }
+
+    /// Ctrl-C in the terminal only reaches the supervisor, since the
+    /// application runs in a process group of its own.
+    pub fn take_raised_ctrl_c() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_count_as_128_plus_their_number() {
+        assert_eq!(ChildExit::Code(3).code(), 3);
+        assert_eq!(ChildExit::Signal(9).code(), 137);
+        assert_eq!(ChildExit::Code(3).to_string(), "exited with code 3");
+        assert_eq!(
+            ChildExit::Signal(15).to_string(),
+            "was killed by signal 15 (SIGTERM)"
+        );
+        assert_eq!(ChildExit::Signal(42).to_string(), "was killed by signal 42");
+    }
+
+    #[test]
+    fn stop_methods_follow_skip_bits() {
+        let config = ServiceConfig {
+            app_stop_method_skip: 1 | 4,
+            app_stop_method_window: 250,
+            ..Default::default()
+        };
+        let skipped: Vec<_> = StopMethod::ALL
+            .into_iter()
+            .filter(|method| method.skipped(&config))
+            .collect();
+        assert_eq!(skipped, vec![StopMethod::Console, StopMethod::Threads]);
+        assert_eq!(
+            StopMethod::Window.timeout(&config),
+            Duration::from_millis(250)
+        );
+    }
+
+    #[cfg(unix)]
+    fn spawn_in_group(script: &str) -> (Child, ChildGuard) {
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", script])
+            .stdout(std::process::Stdio::piped());
+        platform().configure(&mut command);
+        let child = command.spawn().unwrap();
+        let guard = platform().attach(&child).unwrap();
+        (child, guard)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_exits_are_reported_as_signals() {
+        let (mut child, _guard) = spawn_in_group("exec sleep 30");
+        assert!(platform().send_stop(&mut child, StopMethod::Window));
+        let exit = platform().exit_of(child.wait().unwrap());
+        assert_eq!(exit, ChildExit::Signal(libc::SIGTERM));
+        assert_eq!(exit.code(), 128 + libc::SIGTERM);
+    }
+
+    /// The state letter from /proc/<pid>/stat, or None once the process
+    /// is gone.
+    #[cfg(target_os = "linux")]
+    fn process_state(pid: libc::pid_t) -> Option<char> {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        stat.rsplit_once(") ")?.1.chars().next()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dropping_the_guard_kills_the_process_group() {
+        use std::io::BufRead;
+
+        let (mut child, guard) = spawn_in_group("sleep 30 & echo $!; wait");
+        let mut line = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let grandchild: libc::pid_t = line.trim().parse().unwrap();
+
+        drop(guard);
+        assert_eq!(
+            platform().exit_of(child.wait().unwrap()),
+            ChildExit::Signal(libc::SIGKILL)
+        );
+
+        // The orphaned grandchild dies too; it may linger as a zombie until
+        // whoever inherited it reaps it.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !matches!(process_state(grandchild), None | Some('Z')) {
+            assert!(std::time::Instant::now() < deadline, "grandchild survived");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reaping_the_application_kills_its_group_first() {
+        use std::io::BufRead;
+
+        let (mut child, mut guard) = spawn_in_group("sleep 30 & echo $!");
+        let mut line = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let grandchild: libc::pid_t = line.trim().parse().unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            if let Some(status) = guard.try_wait(&mut child).unwrap() {
+                break status;
+            }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "application still running"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert_eq!(platform().exit_of(status), ChildExit::Code(0));
+        while !matches!(process_state(grandchild), None | Some('Z')) {
+            assert!(std::time::Instant::now() < deadline, "grandchild survived");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
@@ -291,6 +291,46 @@ impl RegistryKey {
         Ok(Some(value))
     }
 
+    /// Names of the key's values; the default value is named "".
+    pub fn enum_values(&self) -> AppResult<Vec<String>> {
+        let mut names = Vec::new();
+        let mut index = 0u32;
+
+        loop {
+            // Value names are limited to 16,383 characters.
+            let mut name_len = 16384u32;
+            let mut name = vec![0u16; name_len as usize];
+            let result = unsafe {
+                RegEnumValueW(
+                    self.handle,
+                    index,
+                    Some(PWSTR::from_raw(name.as_mut_ptr())),
+                    &mut name_len,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+
+            if result == ERROR_FILE_NOT_FOUND || result == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            if result != WIN32_ERROR(0) {
+                return Err(AppError::Registry {
+                    operation: "enum values",
+                    path: self.path.clone(),
+                    code: result.0,
+                });
+            }
+
+            names.push(String::from_utf16_lossy(&name[..name_len as usize]));
+            index += 1;
+        }
+
+        Ok(names)
+    }
+
     pub fn enum_subkeys(&self) -> AppResult<Vec<String>> {
         let mut names = Vec::new();
         let mut index = 0u32;
@@ -344,7 +384,7 @@ pub fn to_wide(value: &str) -> Vec<u16> {
 
 /// Encode strings as a REG_MULTI_SZ blob: each string null-terminated,
 /// with a final extra null. An empty list encodes as two nulls.
-fn encode_multi_sz(values: &[String]) -> Vec<u16> {
+pub fn encode_multi_sz(values: &[String]) -> Vec<u16> {
     let mut wide = Vec::new();
     for value in values {
         wide.extend(value.encode_utf16());
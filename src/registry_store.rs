@@ -0,0 +1,447 @@
+//! The default configuration store: NSSM's registry layout under
+//! `HKLM\SYSTEM\CurrentControlSet\Services\<service>\Parameters`.
+
+use std::path::PathBuf;
+
+use log::warn;
+use windows::Win32::System::Registry::{KEY_READ, KEY_WRITE};
+
+use crate::config::{
+    CpuAffinity, DEFAULT_TIMESTAMP_FORMAT, ExitAction, HealthCheck, LogFormat, ProcessPriority,
+    ReadyCheck, RestartBackoff, RotateCompression, ServiceConfig, StartCondition, StatusRange,
+    is_valid_time_format,
+};
+use crate::config_store::ConfigStore;
+use crate::error::AppResult;
+use crate::registry::RegistryKey;
+
+const SERVICES_ROOT: &str = "SYSTEM\\CurrentControlSet\\Services";
+const PARAMETERS_SUBKEY: &str = "Parameters";
+
+pub struct RegistryStore;
+
+impl ConfigStore for RegistryStore {
+    fn load(&self, service_name: &str) -> AppResult<ServiceConfig> {
+        load_service_config(service_name)
+    }
+
+    fn save(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        save_service_config(service_name, config)
+    }
+
+    fn remove(&self, service_name: &str) -> AppResult<()> {
+        match RegistryKey::delete_tree_local_machine(&parameters_key_path(service_name)) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                warn!("Failed to delete registry configuration: {error}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Services whose Parameters key names an Application, i.e. the ones
+    /// created by nssm-rs (or NSSM).
+    fn list(&self) -> AppResult<Vec<String>> {
+        let mut services =
+            RegistryKey::open_local_machine(SERVICES_ROOT, KEY_READ)?.enum_subkeys()?;
+        services.retain(|service_name| has_nssm_config(service_name));
+        services.sort();
+        Ok(services)
+    }
+
+    fn keeps_scm_fields(&self) -> bool {
+        false
+    }
+
+    fn service_arguments(&self) -> Option<Vec<String>> {
+        Some(Vec::new())
+    }
+}
+
+fn save_service_config(service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+    let key = RegistryKey::create_local_machine(&parameters_key_path(service_name), KEY_WRITE)?;
+
+    key.set_string("Application", &config.application.to_string_lossy())?;
+    set_or_delete_path(&key, "AppDirectory", config.app_directory.as_ref())?;
+    set_or_delete_string(&key, "AppParameters", config.app_parameters.as_deref())?;
+    key.set_dword("AppPriority", config.app_priority.to_windows_value())?;
+    set_or_delete_string(
+        &key,
+        "AppAffinity",
+        config
+            .app_affinity
+            .map(|affinity| affinity.as_cli_value())
+            .as_deref(),
+    )?;
+    key.set_dword("AppNoConsole", u32::from(config.app_no_console))?;
+    key.set_dword("AppThrottle", config.app_throttle)?;
+    key.set_dword("AppStopMethodSkip", config.app_stop_method_skip)?;
+    key.set_dword("AppStopMethodConsole", config.app_stop_method_console)?;
+    key.set_dword("AppStopMethodWindow", config.app_stop_method_window)?;
+    key.set_dword("AppStopMethodThreads", config.app_stop_method_threads)?;
+    key.set_dword("AppRestartDelay", config.app_restart_delay)?;
+    key.set_string(
+        "AppRestartBackoff",
+        config.app_restart_backoff.as_cli_value(),
+    )?;
+    key.set_dword("AppRestartBackoffBase", config.app_restart_backoff_base)?;
+    key.set_dword(
+        "AppRestartBackoffMultiplier",
+        config.app_restart_backoff_multiplier,
+    )?;
+    key.set_dword("AppRestartBackoffMax", config.app_restart_backoff_max)?;
+    key.set_dword("AppRestartJitter", config.app_restart_jitter)?;
+    key.set_dword("AppRestartResetAfter", config.app_restart_reset_after)?;
+    key.set_dword("AppRestartLimit", config.app_restart_limit)?;
+    key.set_dword("AppRestartLimitWindow", config.app_restart_limit_window)?;
+    key.set_dword("AppLaunchAttempts", config.app_launch_attempts)?;
+    key.set_string(
+        "AppExitDefault",
+        config.app_exit_default.as_registry_value(),
+    )?;
+    save_exit_actions(service_name, config)?;
+    set_or_delete_path(&key, "AppStdout", config.app_stdout.as_ref())?;
+    set_or_delete_path(&key, "AppStderr", config.app_stderr.as_ref())?;
+    set_or_delete_path(&key, "AppStdin", config.app_stdin.as_ref())?;
+    key.set_dword("AppRotateFiles", u32::from(config.app_rotate_files))?;
+    key.set_dword("AppRotateOnline", u32::from(config.app_rotate_online))?;
+    key.set_dword("AppRotateSeconds", config.app_rotate_seconds)?;
+    // NSSM splits the 64-bit byte threshold into two DWORDs.
+    key.set_dword("AppRotateBytes", config.app_rotate_bytes as u32)?;
+    key.set_dword("AppRotateBytesHigh", (config.app_rotate_bytes >> 32) as u32)?;
+    key.set_dword("AppRotateKeep", config.app_rotate_keep)?;
+    key.set_dword("AppRotateMaxAge", config.app_rotate_max_age)?;
+    key.set_string(
+        "AppRotateCompress",
+        config.app_rotate_compress.as_cli_value(),
+    )?;
+    key.set_dword("AppTimestampOutput", u32::from(config.app_timestamp_output))?;
+    key.set_string("AppTimestampFormat", &config.app_timestamp_format)?;
+    key.set_dword("AppOutputTag", u32::from(config.app_output_tag))?;
+    key.set_dword("AppOutputTimeout", config.app_output_timeout)?;
+    key.set_string("AppLogFormat", config.app_log_format.as_cli_value())?;
+    set_or_delete_path(&key, "AppLogDirectory", config.app_log_directory.as_ref())?;
+    key.set_dword("AppLogMaxBytes", config.app_log_max_bytes as u32)?;
+    key.set_dword(
+        "AppLogMaxBytesHigh",
+        (config.app_log_max_bytes >> 32) as u32,
+    )?;
+    key.set_dword("AppLogKeep", config.app_log_keep)?;
+    if config.app_environment.is_empty() {
+        key.delete_value("AppEnvironment")?;
+    } else {
+        key.set_multi_string("AppEnvironment", &config.app_environment)?;
+    }
+    if config.app_environment_extra.is_empty() {
+        key.delete_value("AppEnvironmentExtra")?;
+    } else {
+        key.set_multi_string("AppEnvironmentExtra", &config.app_environment_extra)?;
+    }
+    set_or_delete_string(
+        &key,
+        "AppHealthCheck",
+        config
+            .app_health_check
+            .as_ref()
+            .map(HealthCheck::as_cli_value)
+            .as_deref(),
+    )?;
+    key.set_string(
+        "AppHealthCheckStatus",
+        &config.app_health_check_status.as_cli_value(),
+    )?;
+    key.set_dword("AppHealthCheckInterval", config.app_health_check_interval)?;
+    key.set_dword("AppHealthCheckTimeout", config.app_health_check_timeout)?;
+    key.set_dword("AppHealthCheckThreshold", config.app_health_check_threshold)?;
+    key.set_dword("AppHealthCheckGrace", config.app_health_check_grace)?;
+    set_or_delete_string(
+        &key,
+        "AppReadyCheck",
+        config
+            .app_ready_check
+            .as_ref()
+            .map(ReadyCheck::as_cli_value)
+            .as_deref(),
+    )?;
+    key.set_dword("AppReadyTimeout", config.app_ready_timeout)?;
+    key.set_dword("AppNotify", u32::from(config.app_notify))?;
+    key.set_dword("AppWatchdogTimeout", config.app_watchdog_timeout)?;
+    if config.app_start_condition.is_empty() {
+        key.delete_value("AppStartCondition")?;
+    } else {
+        let conditions: Vec<String> = config
+            .app_start_condition
+            .iter()
+            .map(StartCondition::as_cli_value)
+            .collect();
+        key.set_multi_string("AppStartCondition", &conditions)?;
+    }
+    key.set_dword("AppStartTimeout", config.app_start_timeout)?;
+
+    Ok(())
+}
+
+fn load_service_config(service_name: &str) -> AppResult<ServiceConfig> {
+    let key = RegistryKey::open_local_machine(&parameters_key_path(service_name), KEY_READ)?;
+    let mut config = ServiceConfig::default();
+
+    if let Some(value) = key.get_string("Application")? {
+        config.application = PathBuf::from(value);
+    }
+    if let Some(value) = key.get_string("AppDirectory")? {
+        config.app_directory = (!value.is_empty()).then(|| PathBuf::from(value));
+    }
+    if let Some(value) = key.get_string("AppParameters")? {
+        config.app_parameters = (!value.is_empty()).then_some(value);
+    }
+    if let Some(value) = key.get_dword("AppPriority")? {
+        config.app_priority = ProcessPriority::from_windows_value(value);
+    }
+    if let Some(value) = key.get_string("AppAffinity")? {
+        config.app_affinity = CpuAffinity::parse(&value).ok().flatten();
+    }
+    if let Some(value) = key.get_dword("AppNoConsole")? {
+        config.app_no_console = value != 0;
+    }
+    if let Some(value) = key.get_dword("AppThrottle")? {
+        config.app_throttle = value;
+    }
+    if let Some(value) = key.get_dword("AppStopMethodSkip")? {
+        config.app_stop_method_skip = value;
+    }
+    if let Some(value) = key.get_dword("AppStopMethodConsole")? {
+        config.app_stop_method_console = value;
+    }
+    if let Some(value) = key.get_dword("AppStopMethodWindow")? {
+        config.app_stop_method_window = value;
+    }
+    if let Some(value) = key.get_dword("AppStopMethodThreads")? {
+        config.app_stop_method_threads = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartDelay")? {
+        config.app_restart_delay = value;
+    }
+    if let Some(value) = key.get_string("AppRestartBackoff")?
+        && let Some(backoff) = RestartBackoff::from_str(&value)
+    {
+        config.app_restart_backoff = backoff;
+    }
+    if let Some(value) = key.get_dword("AppRestartBackoffBase")? {
+        config.app_restart_backoff_base = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartBackoffMultiplier")? {
+        config.app_restart_backoff_multiplier = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartBackoffMax")? {
+        config.app_restart_backoff_max = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartJitter")? {
+        config.app_restart_jitter = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartResetAfter")? {
+        config.app_restart_reset_after = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartLimit")? {
+        config.app_restart_limit = value;
+    }
+    if let Some(value) = key.get_dword("AppRestartLimitWindow")? {
+        config.app_restart_limit_window = value;
+    }
+    if let Some(value) = key.get_dword("AppLaunchAttempts")? {
+        config.app_launch_attempts = value;
+    }
+    load_exit_actions(service_name, &mut config)?;
+    if let Some(value) = key.get_string("AppExitDefault")?
+        && let Some(exit_action) = ExitAction::from_str(&value)
+    {
+        config.app_exit_default = exit_action;
+    }
+    if let Some(value) = key.get_string("AppStdout")? {
+        config.app_stdout = (!value.is_empty()).then(|| PathBuf::from(value));
+    }
+    if let Some(value) = key.get_string("AppStderr")? {
+        config.app_stderr = (!value.is_empty()).then(|| PathBuf::from(value));
+    }
+    if let Some(value) = key.get_string("AppStdin")? {
+        config.app_stdin = (!value.is_empty()).then(|| PathBuf::from(value));
+    }
+    if let Some(value) = key.get_dword("AppRotateFiles")? {
+        config.app_rotate_files = value != 0;
+    }
+    if let Some(value) = key.get_dword("AppRotateOnline")? {
+        config.app_rotate_online = value != 0;
+    }
+    if let Some(value) = key.get_dword("AppRotateSeconds")? {
+        config.app_rotate_seconds = value;
+    }
+    if let Some(low) = key.get_dword("AppRotateBytes")? {
+        let high = key.get_dword("AppRotateBytesHigh")?.unwrap_or(0);
+        config.app_rotate_bytes = (u64::from(high) << 32) | u64::from(low);
+    }
+    if let Some(value) = key.get_dword("AppRotateKeep")? {
+        config.app_rotate_keep = value;
+    }
+    if let Some(value) = key.get_dword("AppRotateMaxAge")? {
+        config.app_rotate_max_age = value;
+    }
+    if let Some(value) = key.get_string("AppRotateCompress")?
+        && let Some(compression) = RotateCompression::from_str(&value)
+    {
+        config.app_rotate_compress = compression;
+    }
+    if let Some(value) = key.get_dword("AppTimestampOutput")? {
+        config.app_timestamp_output = value != 0;
+    }
+    if let Some(value) = key.get_string("AppTimestampFormat")?
+        && !value.is_empty()
+    {
+        if is_valid_time_format(&value) {
+            config.app_timestamp_format = value;
+        } else {
+            warn!(
+                "Ignoring invalid AppTimestampFormat {value:?}; using {DEFAULT_TIMESTAMP_FORMAT:?}"
+            );
+        }
+    }
+    if let Some(value) = key.get_dword("AppOutputTag")? {
+        config.app_output_tag = value != 0;
+    }
+    if let Some(value) = key.get_dword("AppOutputTimeout")? {
+        config.app_output_timeout = value;
+    }
+    if let Some(value) = key.get_string("AppLogFormat")?
+        && let Some(format) = LogFormat::from_str(&value)
+    {
+        config.app_log_format = format;
+    }
+    if let Some(value) = key.get_string("AppLogDirectory")? {
+        config.app_log_directory = (!value.is_empty()).then(|| PathBuf::from(value));
+    }
+    if let Some(low) = key.get_dword("AppLogMaxBytes")? {
+        let high = key.get_dword("AppLogMaxBytesHigh")?.unwrap_or(0);
+        config.app_log_max_bytes = (u64::from(high) << 32) | u64::from(low);
+    }
+    if let Some(value) = key.get_dword("AppLogKeep")? {
+        config.app_log_keep = value;
+    }
+    if let Some(values) = key.get_multi_string("AppEnvironment")? {
+        config.app_environment = values;
+    }
+    if let Some(values) = key.get_multi_string("AppEnvironmentExtra")? {
+        config.app_environment_extra = values;
+    }
+    if let Some(value) = key.get_string("AppHealthCheck")? {
+        config.app_health_check = HealthCheck::parse(&value).ok().flatten();
+    }
+    if let Some(value) = key.get_string("AppHealthCheckStatus")?
+        && let Ok(range) = StatusRange::parse(&value)
+    {
+        config.app_health_check_status = range;
+    }
+    if let Some(value) = key.get_dword("AppHealthCheckInterval")?
+        && value != 0
+    {
+        config.app_health_check_interval = value;
+    }
+    if let Some(value) = key.get_dword("AppHealthCheckTimeout")?
+        && value != 0
+    {
+        config.app_health_check_timeout = value;
+    }
+    if let Some(value) = key.get_dword("AppHealthCheckThreshold")?
+        && value != 0
+    {
+        config.app_health_check_threshold = value;
+    }
+    if let Some(value) = key.get_dword("AppHealthCheckGrace")? {
+        config.app_health_check_grace = value;
+    }
+    if let Some(value) = key.get_string("AppReadyCheck")? {
+        config.app_ready_check = ReadyCheck::parse(&value).ok().flatten();
+    }
+    if let Some(value) = key.get_dword("AppReadyTimeout")? {
+        config.app_ready_timeout = value;
+    }
+    if let Some(value) = key.get_dword("AppNotify")? {
+        config.app_notify = value != 0;
+    }
+    if let Some(value) = key.get_dword("AppWatchdogTimeout")? {
+        config.app_watchdog_timeout = value;
+    }
+    if let Some(values) = key.get_multi_string("AppStartCondition")? {
+        config.app_start_condition = values
+            .iter()
+            .filter_map(|value| StartCondition::parse(value).ok().flatten())
+            .collect();
+    }
+    if let Some(value) = key.get_dword("AppStartTimeout")? {
+        config.app_start_timeout = value;
+    }
+
+    Ok(config)
+}
+
+/// Per-code exit actions live in an AppExit subkey, one string value per
+/// exit code, as in NSSM. Its default value holds the default action so
+/// NSSM reads the same table.
+fn save_exit_actions(service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+    let path = exit_key_path(service_name);
+    RegistryKey::delete_tree_local_machine(&path)?;
+    let key = RegistryKey::create_local_machine(&path, KEY_WRITE)?;
+    key.set_string("", config.app_exit_default.as_registry_value())?;
+    for (code, action) in &config.app_exit {
+        key.set_string(&code.to_string(), action.as_registry_value())?;
+    }
+    Ok(())
+}
+
+/// Reads the AppExit subkey. Its default value is only used for services
+/// configured by NSSM; AppExitDefault, read afterwards, takes precedence.
+fn load_exit_actions(service_name: &str, config: &mut ServiceConfig) -> AppResult<()> {
+    let Ok(key) = RegistryKey::open_local_machine(&exit_key_path(service_name), KEY_READ) else {
+        return Ok(());
+    };
+    for name in key.enum_values()? {
+        let Some(action) = key
+            .get_string(&name)?
+            .and_then(|value| ExitAction::from_str(&value))
+        else {
+            continue;
+        };
+        if name.is_empty() {
+            config.app_exit_default = action;
+        } else if let Ok(code) = name.parse::<u32>() {
+            config.app_exit.insert(code, action);
+        }
+    }
+    Ok(())
+}
+
+fn has_nssm_config(service_name: &str) -> bool {
+    RegistryKey::open_local_machine(&parameters_key_path(service_name), KEY_READ)
+        .and_then(|key| key.get_string("Application"))
+        .map(|value| value.is_some())
+        .unwrap_or(false)
+}
+
+fn parameters_key_path(service_name: &str) -> String {
+    format!("{SERVICES_ROOT}\\{service_name}\\{PARAMETERS_SUBKEY}")
+}
+
+fn exit_key_path(service_name: &str) -> String {
+    format!("{}\\AppExit", parameters_key_path(service_name))
+}
+
+fn set_or_delete_path(key: &RegistryKey, name: &str, value: Option<&PathBuf>) -> AppResult<()> {
+    match value {
+        Some(path) => key.set_string(name, &path.to_string_lossy()),
+        None => key.delete_value(name),
+    }
+}
+
+fn set_or_delete_string(key: &RegistryKey, name: &str, value: Option<&str>) -> AppResult<()> {
+    match value {
+        Some(value) => key.set_string(name, value),
+        None => key.delete_value(name),
+    }
+}
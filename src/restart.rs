@@ -0,0 +1,460 @@
+//! Restart policy: how long to wait before relaunching the application.
+//!
+//! A run shorter than AppThrottle is a failure. Consecutive failures back
+//! off along AppRestartBackoff from AppRestartBackoffBase up to
+//! AppRestartBackoffMax, varied by AppRestartJitter percent. A run lasting
+//! AppRestartResetAfter (AppThrottle when 0) forgets earlier failures;
+//! other restarts wait AppRestartDelay.
+//!
+//! AppRestartLimit stops a crash loop: once more than that many restarts
+//! happen within AppRestartLimitWindow, or that many runs in a row fail,
+//! the service gives up and stops with `RESTART_LIMIT_EXIT_CODE`.
+//!
+//! A launch that fails counts as a failed run and is retried the same way,
+//! up to AppLaunchAttempts times in a row.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{RestartBackoff, ServiceConfig};
+use crate::error::AppError;
+
+/// Service-specific exit code reported when AppRestartLimit is exceeded
+/// ("NS" followed by 1), so monitoring can tell it from application exit
+/// codes.
+pub const RESTART_LIMIT_EXIT_CODE: u32 = 0x4E53_0001;
+
+/// Service-specific exit code reported after AppLaunchAttempts failed
+/// launches in a row.
+pub const LAUNCH_FAILED_EXIT_CODE: u32 = 0x4E53_0002;
+
+/// Service-specific exit code reported when AppReadyCheck is not met
+/// within AppReadyTimeout.
+pub const READY_TIMEOUT_EXIT_CODE: u32 = 0x4E53_0003;
+
+/// Service-specific exit code reported when AppStartCondition is not met
+/// within AppStartTimeout.
+pub const START_CONDITION_EXIT_CODE: u32 = 0x4E53_0004;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchErrorKind {
+    NotFound,
+    AccessDenied,
+    BadExecutable,
+    Stdin,
+    Other,
+}
+
+impl LaunchErrorKind {
+    /// Classifies an error from spawning the application.
+    pub fn from_io(error: &io::Error) -> Self {
+        // ERROR_BAD_EXE_FORMAT on Windows, ENOEXEC elsewhere.
+        const BAD_EXECUTABLE: i32 = if cfg!(windows) { 193 } else { 8 };
+        match error.kind() {
+            io::ErrorKind::NotFound => Self::NotFound,
+            io::ErrorKind::PermissionDenied => Self::AccessDenied,
+            _ if error.raw_os_error() == Some(BAD_EXECUTABLE) => Self::BadExecutable,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::NotFound => "application or AppDirectory not found",
+            Self::AccessDenied => "access denied",
+            Self::BadExecutable => "not a valid executable",
+            Self::Stdin => "cannot open AppStdin",
+            Self::Other => "launch failed",
+        }
+    }
+}
+
+/// Why the application could not be started.
+#[derive(Debug)]
+pub struct LaunchError {
+    pub kind: LaunchErrorKind,
+    pub error: AppError,
+}
+
+impl LaunchError {
+    pub fn spawn(error: io::Error) -> Self {
+        Self {
+            kind: LaunchErrorKind::from_io(&error),
+            error: error.into(),
+        }
+    }
+
+    pub fn stdin(path: &Path, error: io::Error) -> Self {
+        Self {
+            kind: LaunchErrorKind::Stdin,
+            error: AppError::Message(format!(
+                "Failed to open AppStdin file '{}': {error}",
+                path.display()
+            )),
+        }
+    }
+
+    pub fn other(error: AppError) -> Self {
+        Self {
+            kind: LaunchErrorKind::Other,
+            error,
+        }
+    }
+}
+
+impl Display for LaunchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.describe(), self.error)
+    }
+}
+
+/// Counts restarts against AppRestartLimit.
+#[derive(Debug)]
+pub struct RestartLimiter {
+    limit: u32,
+    window: Duration,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartLimiter {
+    pub fn new(config: &ServiceConfig) -> Self {
+        Self {
+            limit: config.app_restart_limit,
+            window: Duration::from_secs(u64::from(config.app_restart_limit_window)),
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// A line for the runtime log describing the limit, if there is one.
+    pub fn describe(&self) -> Option<String> {
+        (self.limit > 0).then(|| {
+            format!(
+                "Restart limit: {} restarts within {}s or {} consecutive failures",
+                self.limit,
+                self.window.as_secs(),
+                self.limit
+            )
+        })
+    }
+
+    /// Records a restart at `now`. Returns why the service should give up
+    /// instead, if the limit is exceeded.
+    pub fn record(&mut self, now: Instant, consecutive_failures: u32) -> Option<String> {
+        if self.limit == 0 {
+            return None;
+        }
+        if consecutive_failures > self.limit {
+            return Some(format!(
+                "{consecutive_failures} consecutive failures exceed AppRestartLimit {}",
+                self.limit
+            ));
+        }
+
+        while self
+            .restarts
+            .front()
+            .is_some_and(|&restart| now.duration_since(restart) >= self.window)
+        {
+            self.restarts.pop_front();
+        }
+        self.restarts.push_back(now);
+        (self.restarts.len() > self.limit as usize).then(|| {
+            format!(
+                "{} restarts within {}s exceed AppRestartLimit {}",
+                self.restarts.len(),
+                self.window.as_secs(),
+                self.limit
+            )
+        })
+    }
+}
+
+/// Delay before relaunching an application that ran for `runtime`,
+/// updating the failure count. `random` is in `[0, 1)` and picks the
+/// jitter.
+pub fn restart_delay_duration(
+    config: &ServiceConfig,
+    runtime: Duration,
+    consecutive_failures: &mut u32,
+    random: f64,
+) -> Duration {
+    if runtime.as_millis() < u128::from(config.app_throttle) {
+        *consecutive_failures = consecutive_failures.saturating_add(1);
+        return jittered(config, backoff_delay(config, *consecutive_failures), random);
+    }
+
+    let reset_after = match config.app_restart_reset_after {
+        0 => config.app_throttle,
+        reset_after => reset_after,
+    };
+    if runtime.as_millis() >= u128::from(reset_after) {
+        *consecutive_failures = 0;
+    }
+    Duration::from_millis(u64::from(config.app_restart_delay))
+}
+
+/// Backoff after `failures` consecutive failures (at least one), before
+/// jitter.
+pub fn backoff_delay(config: &ServiceConfig, failures: u32) -> Duration {
+    let base = u64::from(config.app_restart_backoff_base);
+    let failures = failures.max(1);
+    let millis = match config.app_restart_backoff {
+        RestartBackoff::Fixed => base,
+        RestartBackoff::Linear => base.saturating_mul(u64::from(failures)),
+        RestartBackoff::Exponential => u64::from(config.app_restart_backoff_multiplier)
+            .checked_pow(failures - 1)
+            .map_or(u64::MAX, |factor| base.saturating_mul(factor)),
+    };
+    Duration::from_millis(millis.min(u64::from(config.app_restart_backoff_max)))
+}
+
+/// Moves `delay` up or down by up to AppRestartJitter percent, without
+/// going over AppRestartBackoffMax.
+fn jittered(config: &ServiceConfig, delay: Duration, random: f64) -> Duration {
+    if config.app_restart_jitter == 0 {
+        return delay;
+    }
+    let spread = f64::from(config.app_restart_jitter.min(100)) / 100.0;
+    let factor = 1.0 + spread * (2.0 * random - 1.0);
+    let millis = (delay.as_millis() as f64 * factor).round() as u64;
+    Duration::from_millis(millis.min(u64::from(config.app_restart_backoff_max)))
+}
+
+/// A number in `[0, 1)` for jitter; it only needs to differ between
+/// services restarting at the same moment.
+pub fn random_fraction() -> f64 {
+    let bits = RandomState::new().hash_one(SystemTime::now());
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> ServiceConfig {
+        ServiceConfig {
+            app_throttle: 1500,
+            app_restart_delay: 0,
+            ..Default::default()
+        }
+    }
+
+    fn delays(config: &ServiceConfig, count: usize) -> Vec<u64> {
+        let mut failures = 0;
+        (0..count)
+            .map(|_| {
+                restart_delay_duration(config, Duration::from_millis(1), &mut failures, 0.5)
+                    .as_millis() as u64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fast_failures_back_off_exponentially() {
+        let config = test_config();
+        let mut failures = 0;
+        let fast_exit = Duration::from_millis(100);
+
+        assert_eq!(
+            restart_delay_duration(&config, fast_exit, &mut failures, 0.5),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            restart_delay_duration(&config, fast_exit, &mut failures, 0.5),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            restart_delay_duration(&config, fast_exit, &mut failures, 0.5),
+            Duration::from_secs(8)
+        );
+        assert_eq!(failures, 3);
+    }
+
+    #[test]
+    fn backoff_is_capped_at_256_seconds() {
+        let config = test_config();
+        let mut failures = 20;
+        assert_eq!(
+            restart_delay_duration(&config, Duration::from_millis(1), &mut failures, 0.5),
+            Duration::from_secs(256)
+        );
+        failures = u32::MAX;
+        assert_eq!(
+            restart_delay_duration(&config, Duration::from_millis(1), &mut failures, 0.5),
+            Duration::from_secs(256)
+        );
+    }
+
+    #[test]
+    fn healthy_runtime_resets_failure_counter() {
+        let mut config = test_config();
+        config.app_restart_delay = 500;
+        let mut failures = 5;
+
+        let delay = restart_delay_duration(&config, Duration::from_secs(60), &mut failures, 0.5);
+        assert_eq!(delay, Duration::from_millis(500));
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn fixed_and_linear_curves() {
+        let mut config = test_config();
+        config.app_restart_backoff_base = 1000;
+        config.app_restart_backoff_max = 3500;
+
+        config.app_restart_backoff = RestartBackoff::Fixed;
+        assert_eq!(delays(&config, 4), [1000, 1000, 1000, 1000]);
+
+        config.app_restart_backoff = RestartBackoff::Linear;
+        assert_eq!(delays(&config, 5), [1000, 2000, 3000, 3500, 3500]);
+    }
+
+    #[test]
+    fn exponential_uses_the_multiplier() {
+        let mut config = test_config();
+        config.app_restart_backoff_base = 100;
+        config.app_restart_backoff_multiplier = 3;
+        config.app_restart_backoff_max = 5000;
+        assert_eq!(delays(&config, 5), [100, 300, 900, 2700, 5000]);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_percentage_and_the_cap() {
+        let mut config = test_config();
+        config.app_restart_backoff = RestartBackoff::Fixed;
+        config.app_restart_backoff_base = 1000;
+        config.app_restart_jitter = 20;
+
+        let delay = |random| {
+            restart_delay_duration(&config, Duration::ZERO, &mut 0, random).as_millis() as u64
+        };
+        assert_eq!(delay(0.0), 800);
+        assert_eq!(delay(0.5), 1000);
+        assert_eq!(delay(0.75), 1100);
+
+        config.app_restart_backoff_max = 1000;
+        let delay = restart_delay_duration(&config, Duration::ZERO, &mut 0, 0.99);
+        assert_eq!(delay, Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn reset_after_keeps_failures_for_medium_runtimes() {
+        let mut config = test_config();
+        config.app_restart_reset_after = 60_000;
+        config.app_restart_delay = 250;
+        let mut failures = 3;
+
+        let delay = restart_delay_duration(&config, Duration::from_secs(10), &mut failures, 0.5);
+        assert_eq!(delay, Duration::from_millis(250));
+        assert_eq!(failures, 3);
+
+        restart_delay_duration(&config, Duration::from_secs(60), &mut failures, 0.5);
+        assert_eq!(failures, 0);
+    }
+
+    fn limited_config(limit: u32, window: u32) -> ServiceConfig {
+        ServiceConfig {
+            app_restart_limit: limit,
+            app_restart_limit_window: window,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn restart_limit_counts_restarts_within_the_window() {
+        let mut limiter = RestartLimiter::new(&limited_config(3, 60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(limiter.record(at(0), 0), None);
+        assert_eq!(limiter.record(at(10), 0), None);
+        assert_eq!(limiter.record(at(20), 0), None);
+        // The first restart has left the window by now.
+        assert_eq!(limiter.record(at(60), 0), None);
+        assert_eq!(
+            limiter.record(at(61), 0).as_deref(),
+            Some("4 restarts within 60s exceed AppRestartLimit 3")
+        );
+    }
+
+    #[test]
+    fn restart_limit_counts_consecutive_failures() {
+        let mut limiter = RestartLimiter::new(&limited_config(3, 1));
+        let now = Instant::now();
+        assert_eq!(limiter.record(now, 3), None);
+        assert_eq!(
+            limiter.record(now + Duration::from_secs(600), 4).as_deref(),
+            Some("4 consecutive failures exceed AppRestartLimit 3")
+        );
+    }
+
+    #[test]
+    fn zero_restart_limit_never_gives_up() {
+        let mut limiter = RestartLimiter::new(&limited_config(0, 60));
+        assert_eq!(limiter.describe(), None);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(limiter.record(now, u32::MAX), None);
+        }
+    }
+
+    #[test]
+    fn launch_errors_are_classified() {
+        let not_found = LaunchError::spawn(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(not_found.kind, LaunchErrorKind::NotFound);
+        assert!(
+            not_found
+                .to_string()
+                .starts_with("application or AppDirectory not found: "),
+            "{not_found}"
+        );
+
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            LaunchErrorKind::from_io(&denied),
+            LaunchErrorKind::AccessDenied
+        );
+        let bad_exe = io::Error::from_raw_os_error(if cfg!(windows) { 193 } else { 8 });
+        assert_eq!(
+            LaunchErrorKind::from_io(&bad_exe),
+            LaunchErrorKind::BadExecutable
+        );
+        assert_eq!(
+            LaunchErrorKind::from_io(&io::Error::other("boom")),
+            LaunchErrorKind::Other
+        );
+
+        let stdin = LaunchError::stdin(
+            Path::new(r"C:\in.txt"),
+            io::Error::from(io::ErrorKind::NotFound),
+        );
+        assert_eq!(stdin.kind, LaunchErrorKind::Stdin);
+        assert!(
+            stdin
+                .to_string()
+                .starts_with(r"cannot open AppStdin: Failed to open AppStdin file 'C:\in.txt'"),
+            "{stdin}"
+        );
+    }
+
+    #[test]
+    fn launch_failures_back_off_like_exits() {
+        let config = test_config();
+        let mut failures = 0;
+        restart_delay_duration(&config, Duration::ZERO, &mut failures, 0.5);
+        let delay = restart_delay_duration(&config, Duration::ZERO, &mut failures, 0.5);
+        assert_eq!(delay, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn random_fraction_is_in_range() {
+        for _ in 0..100 {
+            let random = random_fraction();
+            assert!((0.0..1.0).contains(&random), "{random}");
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! Line formats for the supervisor's own runtime log.
+//!
+//! Besides the human-readable text format, records can be written as JSON
+//! lines or logfmt for log shippers. Those carry the service name and,
+//! while an application is running, its PID and restart generation, which
+//! the supervisor loop publishes here.
+//!
+//! When running as a service the log goes to `<directory>\<service>.log`,
+//! rotated by size to `<service>.log.1` ... `<service>.log.N`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::config::LogFormat;
+
+static FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Text as u8);
+/// Set when `--log-format` was given, which wins over AppLogFormat.
+static FORMAT_PINNED: AtomicBool = AtomicBool::new(false);
+static SERVICE_NAME: OnceLock<String> = OnceLock::new();
+/// 0 while no application is running.
+static CHILD_PID: AtomicU32 = AtomicU32::new(0);
+static RESTART_COUNT: AtomicU32 = AtomicU32::new(0);
+/// `--log-dir` / NSSM_RS_LOG_DIR.
+static GLOBAL_LOG_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+static SERVICE_LOG: Mutex<Option<RotatingLog>> = Mutex::new(None);
+
+/// Selects the format given with `--log-format` and the directory given
+/// with `--log-dir`, if any.
+pub fn init(cli_format: Option<LogFormat>, log_directory: Option<PathBuf>) {
+    if let Some(format) = cli_format {
+        FORMAT.store(format as u8, Ordering::Relaxed);
+        FORMAT_PINNED.store(true, Ordering::Relaxed);
+    }
+    if let Some(directory) = log_directory {
+        let _ = GLOBAL_LOG_DIRECTORY.set(directory);
+    }
+}
+
+/// Applies a service's AppLogFormat unless `--log-format` overrides it.
+pub fn use_service_format(format: LogFormat) {
+    if !FORMAT_PINNED.load(Ordering::Relaxed) {
+        FORMAT.store(format as u8, Ordering::Relaxed);
+    }
+}
+
+pub fn set_service_name(service_name: &str) {
+    let _ = SERVICE_NAME.set(service_name.to_string());
+}
+
+/// Records the running application, or `None` once it has exited.
+pub fn set_child(pid: Option<u32>, restart_count: u32) {
+    CHILD_PID.store(pid.unwrap_or(0), Ordering::Relaxed);
+    RESTART_COUNT.store(restart_count, Ordering::Relaxed);
+}
+
+fn current_format() -> LogFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        value if value == LogFormat::Json as u8 => LogFormat::Json,
+        value if value == LogFormat::Logfmt as u8 => LogFormat::Logfmt,
+        _ => LogFormat::Text,
+    }
+}
+
+/// The `env_logger` format callback.
+pub fn write_record(buf: &mut impl Write, record: &log::Record) -> io::Result<()> {
+    let pid = CHILD_PID.load(Ordering::Relaxed);
+    let entry = LogEntry {
+        timestamp: Local::now(),
+        level: record.level(),
+        service: SERVICE_NAME.get().map(String::as_str),
+        child_pid: (pid != 0).then_some(pid),
+        restart_count: SERVICE_NAME
+            .get()
+            .map(|_| RESTART_COUNT.load(Ordering::Relaxed)),
+        location: format!(
+            "{}:{}",
+            record.file().unwrap_or("unknown"),
+            record.line().unwrap_or(0)
+        ),
+        message: record.args().to_string(),
+    };
+    writeln!(buf, "{}", entry.format(current_format()))
+}
+
+/// Where `service_name`'s runtime log lives: AppLogDirectory if set,
+/// otherwise the global directory, otherwise %ProgramData%\nssm-rs\logs.
+pub fn service_log_path(service_name: &str, service_directory: Option<&Path>) -> PathBuf {
+    resolve_log_directory(
+        service_directory,
+        GLOBAL_LOG_DIRECTORY.get().map(PathBuf::as_path),
+    )
+    .join(log_file_name(service_name))
+}
+
+/// The `--log-dir` given to this process, baked into the command line of
+/// services it installs.
+#[cfg(windows)]
+pub fn global_log_directory() -> Option<&'static Path> {
+    GLOBAL_LOG_DIRECTORY.get().map(PathBuf::as_path)
+}
+
+/// `service_log_path` as seen by an installed service rather than by this
+/// process: `service_global` is the `--log-dir` on the service's command
+/// line, falling back to NSSM_RS_LOG_DIR.
+#[cfg(windows)]
+pub fn installed_service_log_path(
+    service_name: &str,
+    service_directory: Option<&Path>,
+    service_global: Option<&Path>,
+) -> PathBuf {
+    let environment = std::env::var_os("NSSM_RS_LOG_DIR").map(PathBuf::from);
+    resolve_log_directory(service_directory, service_global.or(environment.as_deref()))
+        .join(log_file_name(service_name))
+}
+
+fn resolve_log_directory(service_directory: Option<&Path>, global: Option<&Path>) -> PathBuf {
+    if let Some(directory) = service_directory.or(global) {
+        return directory.to_path_buf();
+    }
+    std::env::var_os("ProgramData")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+        .join("nssm-rs")
+        .join("logs")
+}
+
+fn log_file_name(service_name: &str) -> String {
+    let sanitized: String = service_name
+        .chars()
+        .map(|ch| {
+            if matches!(ch, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                ch
+            }
+        })
+        .collect();
+    format!("{sanitized}.log")
+}
+
+/// The runtime log file currently written by `ServiceLogWriter`.
+#[cfg(windows)]
+pub fn current_service_log() -> Option<PathBuf> {
+    lock_service_log().as_ref().map(|log| log.path.clone())
+}
+
+/// Sends the runtime log to `path`, or just updates the limits if it is
+/// already written there.
+pub fn open_service_log(path: &Path, max_bytes: u64, keep: u32) -> io::Result<()> {
+    let mut service_log = lock_service_log();
+    if let Some(log) = service_log.as_mut().filter(|log| log.path == path) {
+        log.max_bytes = max_bytes;
+        log.keep = keep;
+        return Ok(());
+    }
+    *service_log = Some(RotatingLog::open(path, max_bytes, keep)?);
+    Ok(())
+}
+
+fn lock_service_log() -> std::sync::MutexGuard<'static, Option<RotatingLog>> {
+    SERVICE_LOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The `env_logger` target while running as a service. Falls back to
+/// stdout until a log file has been opened.
+pub struct ServiceLogWriter;
+
+impl Write for ServiceLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match lock_service_log().as_mut() {
+            Some(log) => log.write_record(buf).map(|()| buf.len()),
+            None => io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match lock_service_log().as_mut() {
+            Some(log) => log.file.flush(),
+            None => io::stdout().flush(),
+        }
+    }
+}
+
+/// An append-mode log file rotated once it would grow past `max_bytes`,
+/// keeping `keep` numbered predecessors. Zero `max_bytes` disables
+/// rotation; zero `keep` truncates instead.
+struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: u32,
+}
+
+impl RotatingLog {
+    fn open(path: &Path, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    /// Writes one record, rotating first if it would not fit. A record is
+    /// never split across files.
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + record.len() as u64 > self.max_bytes {
+            // A failed rotation must not lose the record; keep appending.
+            let _ = self.rotate();
+        }
+        self.file.write_all(record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            // The append-mode handle follows the truncated end.
+            File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let numbered = |index: u32| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{index}"));
+            PathBuf::from(name)
+        };
+        match fs::remove_file(numbered(self.keep)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+        for index in (1..self.keep).rev() {
+            let from = numbered(index);
+            if from.exists() {
+                fs::rename(&from, numbered(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, numbered(1))?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// One runtime log record and its context.
+#[derive(Debug)]
+struct LogEntry<'a> {
+    timestamp: DateTime<Local>,
+    level: log::Level,
+    service: Option<&'a str>,
+    child_pid: Option<u32>,
+    restart_count: Option<u32>,
+    location: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    timestamp: String,
+    level: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    child_pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restart_count: Option<u32>,
+    location: &'a str,
+    message: &'a str,
+}
+
+impl LogEntry<'_> {
+    fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => format!(
+                "[{} {} {}] {}",
+                self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                self.level,
+                self.location,
+                self.message
+            ),
+            LogFormat::Json => {
+                let entry = JsonEntry {
+                    timestamp: self.rfc3339_timestamp(),
+                    level: self.level.as_str(),
+                    service: self.service,
+                    child_pid: self.child_pid,
+                    restart_count: self.restart_count,
+                    location: &self.location,
+                    message: &self.message,
+                };
+                serde_json::to_string(&entry).unwrap_or_default()
+            }
+            LogFormat::Logfmt => {
+                let mut fields = vec![
+                    ("time", self.rfc3339_timestamp()),
+                    ("level", self.level.as_str().to_lowercase()),
+                ];
+                if let Some(service) = self.service {
+                    fields.push(("service", service.to_string()));
+                }
+                if let Some(pid) = self.child_pid {
+                    fields.push(("child_pid", pid.to_string()));
+                }
+                if let Some(restart_count) = self.restart_count {
+                    fields.push(("restart_count", restart_count.to_string()));
+                }
+                fields.push(("location", self.location.clone()));
+                fields.push(("msg", self.message.clone()));
+
+                fields
+                    .iter()
+                    .map(|(key, value)| format!("{key}={}", logfmt_value(value)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+        }
+    }
+
+    fn rfc3339_timestamp(&self) -> String {
+        self.timestamp
+            .format("%Y-%m-%dT%H:%M:%S%.3f%:z")
+            .to_string()
+    }
+}
+
+/// Quotes a logfmt value when it is empty or contains spaces, quotes,
+/// `=` or control characters.
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|ch| ch == ' ' || ch == '"' || ch == '=' || ch.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_store::tests::TempDir;
+    use chrono::TimeZone;
+
+    fn entry(service: Option<&str>, child_pid: Option<u32>) -> LogEntry<'_> {
+        LogEntry {
+            timestamp: Local.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap(),
+            level: log::Level::Info,
+            service,
+            child_pid,
+            restart_count: service.map(|_| 2),
+            location: "src/service_runner.rs:42".to_string(),
+            message: "Application exited with \"code\" 1".to_string(),
+        }
+    }
+
+    #[test]
+    fn text_format_is_unchanged() {
+        assert_eq!(
+            entry(Some("web"), Some(1234)).format(LogFormat::Text),
+            "[2025-01-02 03:04:05 INFO src/service_runner.rs:42] Application exited with \"code\" 1"
+        );
+    }
+
+    #[test]
+    fn json_format_carries_context() {
+        let line = entry(Some("web"), Some(1234)).format(LogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["service"], "web");
+        assert_eq!(value["child_pid"], 1234);
+        assert_eq!(value["restart_count"], 2);
+        assert_eq!(value["message"], "Application exited with \"code\" 1");
+        assert!(
+            value["timestamp"]
+                .as_str()
+                .unwrap()
+                .starts_with("2025-01-02T03:04:05.000")
+        );
+    }
+
+    #[test]
+    fn json_format_omits_missing_context() {
+        let line = entry(None, None).format(LogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert!(value.get("service").is_none());
+        assert!(value.get("child_pid").is_none());
+        assert!(value.get("restart_count").is_none());
+    }
+
+    #[test]
+    fn logfmt_format_quotes_values() {
+        let line = entry(Some("my service"), None).format(LogFormat::Logfmt);
+        assert!(line.starts_with("time=2025-01-02T03:04:05.000"), "{line}");
+        assert!(
+            line.ends_with(
+                " level=info service=\"my service\" restart_count=2 \
+                 location=src/service_runner.rs:42 msg=\"Application exited with \\\"code\\\" 1\""
+            ),
+            "{line}"
+        );
+    }
+
+    #[test]
+    fn logfmt_value_escapes_control_characters() {
+        assert_eq!(logfmt_value("plain"), "plain");
+        assert_eq!(logfmt_value(""), "\"\"");
+        assert_eq!(logfmt_value("a\nb\\c"), "\"a\\nb\\\\c\"");
+    }
+
+    #[test]
+    fn log_directory_prefers_service_then_global() {
+        let service = Path::new(r"D:\svc");
+        let global = Path::new(r"D:\all");
+        assert_eq!(resolve_log_directory(Some(service), Some(global)), service);
+        assert_eq!(resolve_log_directory(None, Some(global)), global);
+        assert!(resolve_log_directory(None, None).ends_with(Path::new("nssm-rs").join("logs")));
+    }
+
+    #[test]
+    fn log_file_name_replaces_reserved_characters() {
+        assert_eq!(log_file_name(r"a/b\c:d"), "a_b_c_d.log");
+    }
+
+    #[test]
+    fn rotating_log_keeps_numbered_files() {
+        let dir = TempDir::new("runtime-log");
+        let path = dir.0.join("svc.log");
+        let mut log = RotatingLog::open(&path, 10, 2).unwrap();
+
+        for record in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_record(record.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.0.join("svc.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.0.join("svc.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.0.join("svc.log.3").exists());
+    }
+
+    #[test]
+    fn rotating_log_counts_existing_content() {
+        let dir = TempDir::new("runtime-log");
+        let path = dir.0.join("svc.log");
+        fs::write(&path, "0123456789").unwrap();
+
+        let mut log = RotatingLog::open(&path, 12, 1).unwrap();
+        log.write_record(b"abc\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\n");
+        assert_eq!(
+            fs::read_to_string(dir.0.join("svc.log.1")).unwrap(),
+            "0123456789"
+        );
+    }
+
+    #[test]
+    fn rotating_log_without_kept_files_truncates() {
+        let dir = TempDir::new("runtime-log");
+        let path = dir.0.join("svc.log");
+        let mut log = RotatingLog::open(&path, 8, 0).unwrap();
+        log.write_record(b"first\n").unwrap();
+        log.write_record(b"second\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn oversized_record_is_written_whole() {
+        let dir = TempDir::new("runtime-log");
+        let path = dir.0.join("svc.log");
+        let mut log = RotatingLog::open(&path, 4, 1).unwrap();
+        log.write_record(b"longer than the limit\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "longer than the limit\n"
+        );
+    }
+}
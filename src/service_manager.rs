@@ -1,37 +1,58 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
 use log::{debug, info, warn};
-use windows::Win32::Foundation::{ERROR_SERVICE_NOT_ACTIVE, ERROR_SERVICE_SPECIFIC_ERROR};
-use windows::Win32::System::Registry::{KEY_READ, KEY_WRITE};
+use windows::Win32::Foundation::{
+    ERROR_SERVICE_ALREADY_RUNNING, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_NOT_ACTIVE,
+    ERROR_SERVICE_SPECIFIC_ERROR,
+};
 use windows::Win32::System::Services::*;
 use windows::core::PCWSTR;
 
 use windows::core::PWSTR;
 
-use crate::config::{ProcessPriority, ServiceConfig, ServiceStartType};
+use crate::config::{ServiceConfig, ServiceStartType};
+use crate::config_store::{self, ConfigStore, list_values, single_value};
+use crate::deps::DependencyGraph;
 use crate::error::{AppError, AppResult};
+use crate::manifest::Reconcile;
+use crate::notify;
 use crate::parameters::ServiceParameter;
-use crate::registry::{RegistryKey, to_wide};
-
-const SERVICES_ROOT: &str = "SYSTEM\\CurrentControlSet\\Services";
-const PARAMETERS_SUBKEY: &str = "Parameters";
+use crate::registry::{encode_multi_sz, to_wide};
+use crate::runtime_log;
+
+/// The parts of QueryServiceConfigW's result nssm-rs reads.
+struct ScmConfig {
+    /// Raw SCM start type value.
+    start_type: u32,
+    display_name: String,
+    dependencies: Vec<String>,
+    /// The `--log-dir` on the service's command line.
+    log_directory: Option<PathBuf>,
+}
 
 /// Standard DELETE access right, needed for DeleteService.
 const DELETE_ACCESS: u32 = 0x0001_0000;
 
+/// How long a pending service may go without advancing its checkpoint,
+/// unless its wait hint asks for longer.
 const START_TIMEOUT: Duration = Duration::from_secs(30);
 const STOP_TIMEOUT: Duration = Duration::from_secs(60);
 
-/// Polls the service status until it reaches `target`, the service stops
-/// unexpectedly while waiting for SERVICE_RUNNING, or the timeout expires.
+/// Polls the service status until it reaches `target` or the service stops
+/// unexpectedly while waiting for SERVICE_RUNNING. As with the SCM's own
+/// waits, a pending service that keeps advancing `dwCheckPoint` within its
+/// `dwWaitHint` is waited for however long it takes; the wait fails once
+/// that progress stalls for both the hint and `timeout`.
 fn wait_for_service_state(
     service_handle: SC_HANDLE,
     target: SERVICE_STATUS_CURRENT_STATE,
     timeout: Duration,
 ) -> AppResult<()> {
-    let start = std::time::Instant::now();
+    let mut last_checkpoint = None;
+    let mut progress_at = std::time::Instant::now();
     loop {
         let mut status = SERVICE_STATUS::default();
         unsafe { QueryServiceStatus(service_handle, &mut status) }?;
@@ -49,10 +70,21 @@ fn wait_for_service_state(
                 "Service stopped during startup (exit code {exit_code})"
             )));
         }
-        if start.elapsed() >= timeout {
+
+        let pending = status.dwCurrentState == SERVICE_START_PENDING
+            || status.dwCurrentState == SERVICE_STOP_PENDING;
+        if pending && last_checkpoint.is_some_and(|checkpoint| status.dwCheckPoint > checkpoint) {
+            progress_at = std::time::Instant::now();
+        }
+        last_checkpoint = Some(status.dwCheckPoint);
+
+        let allowed = timeout.max(Duration::from_millis(status.dwWaitHint.into()));
+        if progress_at.elapsed() >= allowed {
             return Err(AppError::Message(format!(
-                "Timed out waiting for service state {} (current state {})",
-                target.0, status.dwCurrentState.0
+                "Timed out waiting for service state {} (current state {}, no progress for {}s)",
+                target.0,
+                status.dwCurrentState.0,
+                allowed.as_secs()
             )));
         }
 
@@ -60,27 +92,37 @@ fn wait_for_service_state(
     }
 }
 
+/// The `--log-dir` given in an installed service's command line.
+fn command_line_log_directory(command_line: &str) -> Option<PathBuf> {
+    let arguments = crate::cmdline::parse_command_line(command_line);
+    let position = arguments
+        .iter()
+        .position(|argument| argument == "--log-dir")?;
+    arguments.get(position + 1).map(PathBuf::from)
+}
+
 pub struct ServiceManager {
     handle: SC_HANDLE,
+    store: Box<dyn ConfigStore>,
 }
 
 impl ServiceManager {
     /// Connects to the SCM with the minimal rights needed for everything
     /// except service creation. Per-service rights are requested when the
     /// individual service handle is opened.
-    pub fn new() -> AppResult<Self> {
-        Self::with_access(SC_MANAGER_CONNECT)
+    pub fn new(store: Box<dyn ConfigStore>) -> AppResult<Self> {
+        Self::with_access(SC_MANAGER_CONNECT, store)
     }
 
     /// Connects with the additional right required by CreateServiceW.
-    pub fn new_for_install() -> AppResult<Self> {
-        Self::with_access(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)
+    pub fn new_for_install(store: Box<dyn ConfigStore>) -> AppResult<Self> {
+        Self::with_access(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE, store)
     }
 
-    fn with_access(access: u32) -> AppResult<Self> {
+    fn with_access(access: u32, store: Box<dyn ConfigStore>) -> AppResult<Self> {
         debug!("Creating new ServiceManager instance (access 0x{access:x})");
         let handle = unsafe { OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), access) }?;
-        Ok(Self { handle })
+        Ok(Self { handle, store })
     }
 
     pub fn install_service(
@@ -116,17 +158,35 @@ impl ServiceManager {
     }
 
     pub fn create_service(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        let store_arguments = self.store.service_arguments().ok_or_else(|| {
+            AppError::Message(
+                "The selected configuration store cannot be read by an installed service"
+                    .to_string(),
+            )
+        })?;
+        let log_arguments = runtime_log::global_log_directory().map(|directory| {
+            [
+                "--log-dir".to_string(),
+                directory.to_string_lossy().into_owned(),
+            ]
+        });
         let nssm_path = std::env::current_exe()?;
-        let service_command = format!(
-            "\"{}\" run {}",
-            nssm_path.to_string_lossy(),
-            crate::cmdline::quote_argument(service_name)
-        );
+        let mut service_command = format!("\"{}\"", nssm_path.to_string_lossy());
+        for argument in store_arguments
+            .iter()
+            .chain(log_arguments.iter().flatten())
+            .map(String::as_str)
+            .chain(["run", service_name])
+        {
+            service_command.push(' ');
+            service_command.push_str(&crate::cmdline::quote_argument(argument));
+        }
 
         let service_name_wide = to_wide(service_name);
         let display_name = config.display_name.as_deref().unwrap_or(service_name);
         let display_name_wide = to_wide(display_name);
         let service_command_wide = to_wide(&service_command);
+        let dependencies_wide = encode_multi_sz(&config.dependencies);
 
         let service_handle = unsafe {
             CreateServiceW(
@@ -140,7 +200,7 @@ impl ServiceManager {
                 PCWSTR::from_raw(service_command_wide.as_ptr()),
                 PCWSTR::null(),
                 None,
-                PCWSTR::null(),
+                PCWSTR::from_raw(dependencies_wide.as_ptr()),
                 PCWSTR::null(),
                 PCWSTR::null(),
             )
@@ -154,7 +214,7 @@ impl ServiceManager {
             self.set_scm_description(service_name, description)?;
         }
 
-        self.save_service_config(service_name, config)?;
+        self.store.save(service_name, config)?;
         info!("Service '{service_name}' installed successfully");
         Ok(())
     }
@@ -179,7 +239,7 @@ impl ServiceManager {
             Ok(())
         })?;
 
-        self.remove_service_config(service_name)?;
+        self.store.remove(service_name)?;
         info!("Service '{service_name}' removed successfully");
         Ok(())
     }
@@ -189,7 +249,13 @@ impl ServiceManager {
             service_name,
             SERVICE_START | SERVICE_QUERY_STATUS,
             |service_handle| {
-                unsafe { StartServiceW(service_handle, None) }?;
+                match unsafe { StartServiceW(service_handle, None) } {
+                    Ok(()) => {}
+                    Err(error) if error.code() == ERROR_SERVICE_ALREADY_RUNNING.to_hresult() => {
+                        return Ok(());
+                    }
+                    Err(error) => return Err(error.into()),
+                }
                 wait_for_service_state(service_handle, SERVICE_RUNNING, START_TIMEOUT)
             },
         )?;
@@ -229,6 +295,31 @@ impl ServiceManager {
         self.start_service(service_name)
     }
 
+    /// Starts several services, each after the services it depends on.
+    pub fn start_services(&self, service_names: &[String]) -> AppResult<()> {
+        for service_name in self.dependency_graph()?.start_order(service_names)? {
+            self.start_service(&service_name)?;
+        }
+        Ok(())
+    }
+
+    /// Stops several services, each before the services it depends on.
+    pub fn stop_services(&self, service_names: &[String]) -> AppResult<()> {
+        for service_name in self.dependency_graph()?.stop_order(service_names)? {
+            self.stop_service(&service_name)?;
+        }
+        Ok(())
+    }
+
+    /// The dependencies of every service in the store.
+    pub fn dependency_graph(&self) -> AppResult<DependencyGraph> {
+        Ok(DependencyGraph::new(
+            self.installed_configs()?
+                .into_iter()
+                .map(|(service_name, config)| (service_name, config.dependencies)),
+        ))
+    }
+
     pub fn set_service_parameter(
         &self,
         service_name: &str,
@@ -236,53 +327,42 @@ impl ServiceManager {
         values: &[String],
     ) -> AppResult<()> {
         let parameter = ServiceParameter::parse(parameter)?;
-        match parameter {
-            ServiceParameter::Start => {
-                let value = single_value(parameter, values)?;
-                let start_type = ServiceStartType::from_str(value).ok_or_else(|| {
-                    AppError::InvalidParameterValue {
-                        parameter: parameter.as_str().to_string(),
-                        value: value.to_string(),
-                    }
-                })?;
-                self.set_scm_start_type(service_name, start_type)?;
-            }
-            ServiceParameter::DisplayName => {
-                self.set_scm_display_name(service_name, single_value(parameter, values)?)?;
-            }
-            ServiceParameter::Description => {
-                self.set_scm_description(service_name, single_value(parameter, values)?)?;
-            }
-            ServiceParameter::AppEnvironmentExtra => {
-                // A single empty value clears the list.
-                let entries: Vec<String> = match values {
-                    [value] if value.is_empty() => Vec::new(),
-                    _ => values.to_vec(),
-                };
-                for entry in &entries {
-                    if !entry.contains('=') {
-                        return Err(AppError::InvalidParameterValue {
+        if parameter.is_scm_owned() {
+            match parameter {
+                ServiceParameter::Dependencies => {
+                    self.set_scm_dependencies(service_name, &list_values(values))?
+                }
+                ServiceParameter::Start => {
+                    let value = single_value(parameter, values)?;
+                    let start_type = ServiceStartType::from_str(value).ok_or_else(|| {
+                        AppError::InvalidParameterValue {
                             parameter: parameter.as_str().to_string(),
-                            value: entry.clone(),
-                        });
-                    }
+                            value: value.to_string(),
+                            reason: None,
+                        }
+                    })?;
+                    self.set_scm_start_type(service_name, start_type)?;
                 }
-                let mut config = self.load_service_config(service_name)?;
-                config.app_environment_extra = entries;
-                self.save_service_config(service_name, &config)?;
-            }
-            ServiceParameter::AppParameters if values.len() > 1 => {
-                let joined = crate::cmdline::join_arguments(values);
-                let mut config = self.load_service_config(service_name)?;
-                parameter.apply(&mut config, &joined)?;
-                self.save_service_config(service_name, &config)?;
+                // An empty display name (e.g. from `reset`) falls back to
+                // the service name, as at install time.
+                ServiceParameter::DisplayName => {
+                    let value = single_value(parameter, values)?;
+                    self.set_scm_display_name(
+                        service_name,
+                        if value.is_empty() {
+                            service_name
+                        } else {
+                            value
+                        },
+                    )?
+                }
+                _ => self.set_scm_description(service_name, single_value(parameter, values)?)?,
             }
-            _ => {
-                let value = single_value(parameter, values)?;
-                let mut config = self.load_service_config(service_name)?;
-                parameter.apply(&mut config, value)?;
-                self.save_service_config(service_name, &config)?;
+            if self.store.keeps_scm_fields() {
+                config_store::set_parameter(&*self.store, service_name, parameter, values)?;
             }
+        } else {
+            config_store::set_parameter(&*self.store, service_name, parameter, values)?;
         }
 
         info!(
@@ -296,25 +376,109 @@ impl ServiceManager {
 
     pub fn get_service_parameter(&self, service_name: &str, parameter: &str) -> AppResult<String> {
         let parameter = ServiceParameter::parse(parameter)?;
-        let value = match parameter {
-            ServiceParameter::Start => {
-                let (start_type, _) = self.query_scm_config(service_name)?;
-                match ServiceStartType::from_windows_value(start_type) {
-                    Some(start_type) => start_type.as_cli_value().to_string(),
-                    None => start_type.to_string(),
+        let value = if self.store.keeps_scm_fields() || !parameter.is_scm_owned() {
+            config_store::get_parameter(&*self.store, service_name, parameter)?
+        } else {
+            match parameter {
+                ServiceParameter::Dependencies => {
+                    self.query_scm_config(service_name)?.dependencies.join("\n")
                 }
-            }
-            ServiceParameter::DisplayName => self.query_scm_config(service_name)?.1,
-            ServiceParameter::Description => self.query_scm_description(service_name)?,
-            _ => {
-                let config = self.load_service_config(service_name)?;
-                parameter.read(&config)
+                ServiceParameter::Start => {
+                    let start_type = self.query_scm_config(service_name)?.start_type;
+                    match ServiceStartType::from_windows_value(start_type) {
+                        Some(start_type) => start_type.as_cli_value().to_string(),
+                        None => start_type.to_string(),
+                    }
+                }
+                ServiceParameter::DisplayName => self.query_scm_config(service_name)?.display_name,
+                _ => self.query_scm_description(service_name)?,
             }
         };
         println!("{}: {}", parameter.as_str(), value);
         Ok(value)
     }
 
+    pub fn reset_service_parameter(&self, service_name: &str, parameter: &str) -> AppResult<()> {
+        let parameter = ServiceParameter::parse(parameter)?;
+        let default_value = parameter.reset_value()?;
+        info!(
+            "Resetting parameter '{}' for service '{}' to '{}'",
+            parameter.as_str(),
+            service_name,
+            default_value
+        );
+        if parameter.is_scm_owned() {
+            self.set_service_parameter(service_name, parameter.as_str(), &[default_value])
+        } else {
+            config_store::reset_parameter(&*self.store, service_name, parameter)
+        }
+    }
+
+    /// The stored configuration with DisplayName, Description, Start and
+    /// Dependencies filled in from the SCM when the store does not keep them.
+    pub fn export_service(&self, service_name: &str) -> AppResult<ServiceConfig> {
+        let mut config = self.store.load(service_name)?;
+        if !self.store.keeps_scm_fields() {
+            let scm_config = self.query_scm_config(service_name)?;
+            if let Some(start_type) = ServiceStartType::from_windows_value(scm_config.start_type) {
+                config.start_type = start_type;
+            }
+            // create_service defaults the display name to the service name.
+            config.display_name =
+                (scm_config.display_name != service_name).then_some(scm_config.display_name);
+            config.dependencies = scm_config.dependencies;
+            let description = self.query_scm_description(service_name)?;
+            config.description = (!description.is_empty()).then_some(description);
+        }
+        Ok(config)
+    }
+
+    /// Replaces a service's configuration, installing the service first if
+    /// it does not exist yet.
+    pub fn import_service(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        config_store::validate_config(config)?;
+
+        if !self.service_exists(service_name)? {
+            info!("Service '{service_name}' is not installed; installing it");
+            return self.create_service(service_name, config);
+        }
+
+        self.set_scm_start_type(service_name, config.start_type)?;
+        self.set_scm_display_name(
+            service_name,
+            config.display_name.as_deref().unwrap_or(service_name),
+        )?;
+        self.set_scm_description(service_name, config.description.as_deref().unwrap_or(""))?;
+        self.set_scm_dependencies(service_name, &config.dependencies)?;
+        self.store.save(service_name, config)?;
+        info!("Configuration imported for service '{service_name}'");
+        Ok(())
+    }
+
+    /// Every service in the store, as `export_service` reports it.
+    pub fn installed_configs(&self) -> AppResult<BTreeMap<String, ServiceConfig>> {
+        self.store
+            .list()?
+            .into_iter()
+            .map(|service_name| {
+                let config = self.export_service(&service_name)?;
+                Ok((service_name, config))
+            })
+            .collect()
+    }
+
+    pub fn service_exists(&self, service_name: &str) -> AppResult<bool> {
+        match self.with_service_handle(service_name, SERVICE_QUERY_STATUS, |_| Ok(())) {
+            Ok(()) => Ok(true),
+            Err(AppError::Windows(error))
+                if error.code() == ERROR_SERVICE_DOES_NOT_EXIST.to_hresult() =>
+            {
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
     fn set_scm_start_type(
         &self,
         service_name: &str,
@@ -358,6 +522,28 @@ impl ServiceManager {
         })
     }
 
+    fn set_scm_dependencies(&self, service_name: &str, dependencies: &[String]) -> AppResult<()> {
+        // An empty list (two nulls) clears the dependencies; a null pointer
+        // would leave them unchanged.
+        let dependencies_wide = encode_multi_sz(dependencies);
+        self.with_service_handle(service_name, SERVICE_CHANGE_CONFIG, |handle| unsafe {
+            ChangeServiceConfigW(
+                handle,
+                ENUM_SERVICE_TYPE(SERVICE_NO_CHANGE),
+                SERVICE_START_TYPE(SERVICE_NO_CHANGE),
+                SERVICE_ERROR(SERVICE_NO_CHANGE),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                None,
+                PCWSTR::from_raw(dependencies_wide.as_ptr()),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+            )?;
+            Ok(())
+        })
+    }
+
     fn set_scm_description(&self, service_name: &str, description: &str) -> AppResult<()> {
         let description_wide = to_wide(description);
         self.with_service_handle(service_name, SERVICE_CHANGE_CONFIG, |handle| unsafe {
@@ -373,8 +559,7 @@ impl ServiceManager {
         })
     }
 
-    /// Returns the raw SCM start type value and the display name.
-    fn query_scm_config(&self, service_name: &str) -> AppResult<(u32, String)> {
+    fn query_scm_config(&self, service_name: &str) -> AppResult<ScmConfig> {
         self.with_service_handle(service_name, SERVICE_QUERY_CONFIG, |handle| unsafe {
             let mut needed = 0u32;
             let _ = QueryServiceConfigW(handle, None, 0, &mut needed);
@@ -387,7 +572,14 @@ impl ServiceManager {
                 &mut needed,
             )?;
             let config = &*(buffer.as_ptr() as *const QUERY_SERVICE_CONFIGW);
-            Ok((config.dwStartType.0, pwstr_to_string(config.lpDisplayName)))
+            Ok(ScmConfig {
+                start_type: config.dwStartType.0,
+                display_name: pwstr_to_string(config.lpDisplayName),
+                dependencies: multi_pwstr_to_vec(config.lpDependencies),
+                log_directory: command_line_log_directory(&pwstr_to_string(
+                    config.lpBinaryPathName,
+                )),
+            })
         })
     }
 
@@ -404,113 +596,6 @@ impl ServiceManager {
         })
     }
 
-    fn save_service_config(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
-        save_service_config(service_name, config)
-    }
-
-    fn load_service_config(&self, service_name: &str) -> AppResult<ServiceConfig> {
-        load_service_config(service_name)
-    }
-    fn remove_service_config(&self, service_name: &str) -> AppResult<()> {
-        match RegistryKey::delete_tree_local_machine(&parameters_key_path(service_name)) {
-            Ok(()) => Ok(()),
-            Err(error) => {
-                warn!("Failed to delete registry configuration: {error}");
-                Ok(())
-            }
-        }
-    }
-}
-
-pub fn save_service_config(service_name: &str, config: &ServiceConfig) -> AppResult<()> {
-    let key = RegistryKey::create_local_machine(&parameters_key_path(service_name), KEY_WRITE)?;
-
-    key.set_string("Application", &config.application.to_string_lossy())?;
-    set_or_delete_path(&key, "AppDirectory", config.app_directory.as_ref())?;
-    set_or_delete_string(&key, "AppParameters", config.app_parameters.as_deref())?;
-    key.set_dword("AppPriority", config.app_priority.to_windows_value())?;
-    key.set_dword("AppNoConsole", u32::from(config.app_no_console))?;
-    key.set_dword("AppThrottle", config.app_throttle)?;
-    key.set_dword("AppStopMethodSkip", config.app_stop_method_skip)?;
-    key.set_dword("AppStopMethodConsole", config.app_stop_method_console)?;
-    key.set_dword("AppStopMethodWindow", config.app_stop_method_window)?;
-    key.set_dword("AppStopMethodThreads", config.app_stop_method_threads)?;
-    key.set_dword("AppRestartDelay", config.app_restart_delay)?;
-    key.set_string(
-        "AppExitDefault",
-        config.app_exit_default.as_registry_value(),
-    )?;
-    set_or_delete_path(&key, "AppStdout", config.app_stdout.as_ref())?;
-    set_or_delete_path(&key, "AppStderr", config.app_stderr.as_ref())?;
-    set_or_delete_path(&key, "AppStdin", config.app_stdin.as_ref())?;
-    if config.app_environment_extra.is_empty() {
-        key.delete_value("AppEnvironmentExtra")?;
-    } else {
-        key.set_multi_string("AppEnvironmentExtra", &config.app_environment_extra)?;
-    }
-
-    Ok(())
-}
-
-pub fn load_service_config(service_name: &str) -> AppResult<ServiceConfig> {
-    let key = RegistryKey::open_local_machine(&parameters_key_path(service_name), KEY_READ)?;
-    let mut config = ServiceConfig::default();
-
-    if let Some(value) = key.get_string("Application")? {
-        config.application = PathBuf::from(value);
-    }
-    if let Some(value) = key.get_string("AppDirectory")? {
-        config.app_directory = (!value.is_empty()).then(|| PathBuf::from(value));
-    }
-    if let Some(value) = key.get_string("AppParameters")? {
-        config.app_parameters = (!value.is_empty()).then_some(value);
-    }
-    if let Some(value) = key.get_dword("AppPriority")? {
-        config.app_priority = ProcessPriority::from_windows_value(value);
-    }
-    if let Some(value) = key.get_dword("AppNoConsole")? {
-        config.app_no_console = value != 0;
-    }
-    if let Some(value) = key.get_dword("AppThrottle")? {
-        config.app_throttle = value;
-    }
-    if let Some(value) = key.get_dword("AppStopMethodSkip")? {
-        config.app_stop_method_skip = value;
-    }
-    if let Some(value) = key.get_dword("AppStopMethodConsole")? {
-        config.app_stop_method_console = value;
-    }
-    if let Some(value) = key.get_dword("AppStopMethodWindow")? {
-        config.app_stop_method_window = value;
-    }
-    if let Some(value) = key.get_dword("AppStopMethodThreads")? {
-        config.app_stop_method_threads = value;
-    }
-    if let Some(value) = key.get_dword("AppRestartDelay")? {
-        config.app_restart_delay = value;
-    }
-    if let Some(value) = key.get_string("AppExitDefault")?
-        && let Some(exit_action) = crate::config::ExitAction::from_str(&value)
-    {
-        config.app_exit_default = exit_action;
-    }
-    if let Some(value) = key.get_string("AppStdout")? {
-        config.app_stdout = (!value.is_empty()).then(|| PathBuf::from(value));
-    }
-    if let Some(value) = key.get_string("AppStderr")? {
-        config.app_stderr = (!value.is_empty()).then(|| PathBuf::from(value));
-    }
-    if let Some(value) = key.get_string("AppStdin")? {
-        config.app_stdin = (!value.is_empty()).then(|| PathBuf::from(value));
-    }
-    if let Some(values) = key.get_multi_string("AppEnvironmentExtra")? {
-        config.app_environment_extra = values;
-    }
-
-    Ok(config)
-}
-
-impl ServiceManager {
     pub fn query_service_status(&self, service_name: &str) -> AppResult<()> {
         self.with_service_handle(
             service_name,
@@ -539,38 +624,42 @@ impl ServiceManager {
                 );
                 println!("Checkpoint: {}", status.dwCheckPoint);
                 println!("Wait Hint: {}ms", status.dwWaitHint);
+                // What the application last sent as STATUS=, if it uses
+                // the notify protocol and is still running. The file is
+                // beside the service's runtime log, which follows the
+                // service's own `--log-dir`, not this command's.
+                if status.dwCurrentState != SERVICE_STOPPED
+                    && let Ok(config) = self.store.load(service_name)
+                    && let Ok(scm_config) = self.query_scm_config(service_name)
+                    && let Some(app_status) = notify::read_status(&notify::status_path_beside(
+                        &runtime_log::installed_service_log_path(
+                            service_name,
+                            config.app_log_directory.as_deref(),
+                            scm_config.log_directory.as_deref(),
+                        ),
+                    ))
+                {
+                    println!("Application Status: {app_status}");
+                }
                 Ok(())
             },
         )
     }
 
     pub fn list_nssm_services(&self) -> AppResult<()> {
-        let services = RegistryKey::open_local_machine(SERVICES_ROOT, KEY_READ)?.enum_subkeys()?;
+        let services = self.store.list()?;
 
         println!("Services managed by nssm-rs:");
-        let mut found_any = false;
-
-        for service_name in services {
-            if self.has_nssm_config(&service_name) {
-                println!("  {service_name}");
-                found_any = true;
-            }
+        for service_name in &services {
+            println!("  {service_name}");
         }
-
-        if !found_any {
+        if services.is_empty() {
             println!("  (none)");
         }
 
         Ok(())
     }
 
-    fn has_nssm_config(&self, service_name: &str) -> bool {
-        RegistryKey::open_local_machine(&parameters_key_path(service_name), KEY_READ)
-            .and_then(|key| key.get_string("Application"))
-            .map(|value| value.is_some())
-            .unwrap_or(false)
-    }
-
     fn with_service_handle<F, T>(
         &self,
         service_name: &str,
@@ -597,6 +686,25 @@ impl ServiceManager {
     }
 }
 
+impl Reconcile for ServiceManager {
+    fn install(&self, service_name: &str, config: &ServiceConfig) -> AppResult<()> {
+        self.create_service(service_name, config)
+    }
+
+    fn set(
+        &self,
+        service_name: &str,
+        parameter: ServiceParameter,
+        values: &[String],
+    ) -> AppResult<()> {
+        self.set_service_parameter(service_name, parameter.as_str(), values)
+    }
+
+    fn remove(&self, service_name: &str) -> AppResult<()> {
+        self.remove_service(service_name, true)
+    }
+}
+
 impl Drop for ServiceManager {
     fn drop(&mut self) {
         unsafe {
@@ -613,31 +721,20 @@ fn pwstr_to_string(value: PWSTR) -> String {
     }
 }
 
-fn parameters_key_path(service_name: &str) -> String {
-    format!("{SERVICES_ROOT}\\{service_name}\\{PARAMETERS_SUBKEY}")
-}
-
-fn single_value(parameter: ServiceParameter, values: &[String]) -> AppResult<&str> {
-    match values {
-        [value] => Ok(value),
-        _ => Err(AppError::Message(format!(
-            "Parameter '{}' expects exactly one value, got {}",
-            parameter.as_str(),
-            values.len()
-        ))),
-    }
-}
-
-fn set_or_delete_path(key: &RegistryKey, name: &str, value: Option<&PathBuf>) -> AppResult<()> {
-    match value {
-        Some(path) => key.set_string(name, &path.to_string_lossy()),
-        None => key.delete_value(name),
-    }
-}
-
-fn set_or_delete_string(key: &RegistryKey, name: &str, value: Option<&str>) -> AppResult<()> {
-    match value {
-        Some(value) => key.set_string(name, value),
-        None => key.delete_value(name),
+/// Reads a double-null-terminated list such as lpDependencies.
+fn multi_pwstr_to_vec(value: PWSTR) -> Vec<String> {
+    let mut values = Vec::new();
+    if value.is_null() {
+        return values;
+    }
+    let mut current = value.0;
+    unsafe {
+        while *current != 0 {
+            let item = PWSTR::from_raw(current);
+            let length = item.len();
+            values.push(String::from_utf16_lossy(item.as_wide()));
+            current = current.add(length + 1);
+        }
     }
+    values
 }
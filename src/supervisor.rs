@@ -0,0 +1,1527 @@
+//! The supervisor loop: launch the application, watch it, restart or stop
+//! it according to the service's configuration.
+//!
+//! The loop does not know whether it runs under the service control
+//! manager or in a terminal; it reports its state through a
+//! `StatusReporter` and reacts to `Event`s: stop requests from whoever
+//! controls it, exits reported by a waiter thread per application, the
+//! line AppReadyCheck waits for, the application's own notifications and
+//! failed health checks. Timers are deadlines on the same queue, so between
+//! events the supervisor sleeps; AppOutputTimeout and AppWatchdogTimeout
+//! are such deadlines.
+
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::config::{ExitAction, ReadyCheck, ServiceConfig};
+use crate::error::{AppError, AppResult};
+use crate::expand::{self, Variables};
+use crate::health::{self, HealthMonitor};
+use crate::notify::{self, Notification, NotifyChannel, Update};
+use crate::output::{
+    LineMatcher, LinePrefix, OutputActivity, OutputFile, OutputTarget, RotationPolicy, pump_output,
+    same_file_path,
+};
+use crate::process::{self, ChildExit, ChildGuard, StopMethod};
+use crate::restart::{self, LaunchError, RestartLimiter};
+use crate::runtime_log;
+
+/// Where the supervisor reports the service's state: the SCM when running
+/// as a service, the log when running in a terminal.
+pub trait StatusReporter {
+    /// The application is not running yet. Until the first launch succeeds
+    /// the service is still starting; `accept_stop` is set once a failed
+    /// launch leaves it waiting to retry.
+    fn start_pending(
+        &self,
+        checkpoint: u32,
+        wait_hint: Duration,
+        accept_stop: bool,
+    ) -> AppResult<()>;
+
+    /// The application has been launched.
+    fn running(&self) -> AppResult<()>;
+
+    /// The stop sequence is making progress.
+    fn stop_pending(&self, checkpoint: u32, wait_hint: Duration) -> AppResult<()>;
+}
+
+/// Something the supervisor loop reacts to.
+#[derive(Debug)]
+pub enum Event {
+    /// The SCM's stop or shutdown control, or Ctrl-C in the foreground.
+    Stop,
+    /// The application from launch `generation` exited, or waiting for it
+    /// failed.
+    ChildExited {
+        generation: u32,
+        result: std::io::Result<()>,
+    },
+    /// The application from launch `generation` wrote the line a
+    /// `stdout:` AppReadyCheck waits for.
+    Ready { generation: u32 },
+    /// The application from launch `generation` failed
+    /// AppHealthCheckThreshold health checks in a row.
+    Unhealthy { generation: u32, reason: String },
+    /// The application from launch `generation` sent a notification to
+    /// NOTIFY_SOCKET.
+    Notify {
+        generation: u32,
+        notification: Notification,
+    },
+}
+
+/// The supervisor's event queue.
+pub struct Events {
+    sender: mpsc::Sender<Event>,
+    receiver: mpsc::Receiver<Event>,
+    /// A stop that arrived while the application was being restarted; it
+    /// is delivered once the restart has stopped the old one.
+    deferred_stop: Cell<bool>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            deferred_stop: Cell::new(false),
+        }
+    }
+
+    /// A handle for control handlers to send `Event::Stop` through.
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
+
+    /// The next event, or `None` once `deadline` has passed.
+    fn next(&self, deadline: Option<Instant>) -> Option<Event> {
+        if self.deferred_stop.take() {
+            return Some(Event::Stop);
+        }
+        self.receive(deadline)
+    }
+
+    /// The next event on the queue itself, ignoring a deferred stop.
+    fn receive(&self, deadline: Option<Instant>) -> Option<Event> {
+        match deadline {
+            // Never disconnected, since we hold a sender ourselves.
+            None => self.receiver.recv().ok(),
+            Some(deadline) => self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How often `tcp://` and `file:` AppReadyChecks are tried, and START_PENDING
+/// progress is reported meanwhile.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often AppStartConditions are checked while the first launch waits
+/// for them.
+const START_CONDITION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How waiting for AppStartCondition ended.
+enum StartConditions {
+    Met,
+    Stopped,
+    TimedOut,
+}
+
+/// How waiting for AppReadyCheck ended.
+enum Readiness {
+    Ready,
+    /// A stop, the application's exit or a failed health check came
+    /// first; the event is back on the queue for `monitor_child`.
+    Interrupted,
+    TimedOut,
+}
+
+#[derive(Debug)]
+enum ProcessStatus {
+    Running,
+    Exited(ChildExit),
+    Unknown(std::io::Error),
+}
+
+/// Exit codes here are service-specific exit codes; 0 means success.
+enum LoopControl {
+    Restart(Option<Instant>),
+    Exit(u32),
+    /// The application exited but the service keeps running without it
+    /// (AppExitAction=Ignore) until a stop is requested.
+    Idle(u32),
+    /// The application's exit code; the supervisor exits with it without
+    /// reporting SERVICE_STOPPED (AppExit Suicide).
+    Suicide(i32),
+}
+
+struct RunningChild {
+    child: Child,
+    /// Tells this application's exit apart from earlier ones.
+    generation: u32,
+    /// Kills the application's process tree when dropped.
+    guard: ChildGuard,
+    _health: Option<HealthMonitor>,
+    /// The notify endpoint and what the application has sent to it.
+    notify: Option<NotifyChannel>,
+    /// When the application last wrote output, for AppOutputTimeout.
+    activity: OutputActivity,
+    stdout_thread: Option<thread::JoinHandle<()>>,
+    stderr_thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Runs the service until it is stopped or gives up, returning its
+/// service-specific exit code (0 for success).
+pub fn run(
+    service_name: &str,
+    config: &ServiceConfig,
+    reporter: &dyn StatusReporter,
+    events: &Events,
+) -> AppResult<u32> {
+    let mut restart_after: Option<Instant> = None;
+    let mut consecutive_failures = 0u32;
+    let mut reported_running = false;
+    let mut restart_count = 0u32;
+    let mut launch_failures = 0u32;
+    let mut start_checkpoint = 0u32;
+    let mut first_launch: Option<Instant> = None;
+    let mut generation = 0u32;
+    let status_path = notify::status_path(service_name, config);
+    let mut restart_limiter = RestartLimiter::new(config);
+    if let Some(description) = restart_limiter.describe() {
+        info!("{description}");
+    }
+
+    let first_config = expand::expand_config(
+        config,
+        &Variables::new(service_name, 0),
+        &expand::process_environment,
+    );
+    match wait_for_start_conditions(reporter, events, &first_config, &mut start_checkpoint) {
+        StartConditions::Met => {}
+        StartConditions::Stopped => return Ok(0),
+        StartConditions::TimedOut => return Ok(restart::START_CONDITION_EXIT_CODE),
+    }
+
+    loop {
+        if wait_for_restart_delay(events, restart_after) {
+            return Ok(0);
+        }
+
+        let launch_config = expand::expand_config(
+            config,
+            &Variables::new(service_name, restart_count),
+            &expand::process_environment,
+        );
+        generation = generation.wrapping_add(1);
+        let mut running_child = match launch_child(&launch_config, events, generation, &status_path)
+        {
+            Ok(child) => {
+                launch_failures = 0;
+                child
+            }
+            Err(error) => {
+                launch_failures += 1;
+                error!("Failed to launch application ({error})");
+                if config.app_launch_attempts != 0 && launch_failures >= config.app_launch_attempts
+                {
+                    error!("Giving up after {launch_failures} failed launch attempts");
+                    return Ok(restart::LAUNCH_FAILED_EXIT_CODE);
+                }
+                // A failed launch is a run of no length, so it backs off
+                // like an application that exits immediately.
+                restart_after =
+                    calculate_restart_delay(config, Duration::ZERO, &mut consecutive_failures);
+                let delay = restart_after.map_or(Duration::ZERO, |at| {
+                    at.saturating_duration_since(Instant::now())
+                });
+                info!(
+                    "Retrying launch in {delay:?} (attempt {} of {})",
+                    launch_failures + 1,
+                    match config.app_launch_attempts {
+                        0 => "unlimited".to_string(),
+                        attempts => attempts.to_string(),
+                    }
+                );
+                // Until the first launch succeeds the service is still
+                // starting; keep the SCM waiting, but let it stop us.
+                if !reported_running {
+                    report_start_progress(
+                        reporter,
+                        &mut start_checkpoint,
+                        delay + Duration::from_secs(10),
+                    );
+                }
+                continue;
+            }
+        };
+        runtime_log::set_child(Some(running_child.child.id()), restart_count);
+
+        // Only report RUNNING once the application has actually been
+        // launched and is ready; a broken configuration fails the start
+        // instead of flapping RUNNING -> STOPPED.
+        if !reported_running {
+            let ready_deadline = (config.app_ready_timeout > 0).then(|| {
+                *first_launch.get_or_insert_with(Instant::now)
+                    + Duration::from_millis(u64::from(config.app_ready_timeout))
+            });
+            match wait_until_ready(
+                reporter,
+                events,
+                &launch_config,
+                &mut running_child,
+                ready_deadline,
+                &mut start_checkpoint,
+            ) {
+                Readiness::Ready => {
+                    reporter.running()?;
+                    reported_running = true;
+                }
+                Readiness::Interrupted => {}
+                Readiness::TimedOut => {
+                    error!(
+                        "Application is not ready after {}ms (AppReadyTimeout); failing the start",
+                        config.app_ready_timeout
+                    );
+                    report_stop_progress(reporter, &mut 0, stop_wait_hint(config));
+                    stop_child_process(Some(reporter), events, &mut running_child, config);
+                    finalize_child_threads(running_child);
+                    runtime_log::set_child(None, restart_count);
+                    return Ok(restart::READY_TIMEOUT_EXIT_CODE);
+                }
+            }
+        }
+
+        let control = monitor_child(
+            reporter,
+            events,
+            config,
+            &mut running_child,
+            &mut consecutive_failures,
+        );
+        finalize_child_threads(running_child);
+        runtime_log::set_child(None, restart_count);
+
+        match control {
+            LoopControl::Restart(next_restart) => {
+                if let Some(reason) = restart_limiter.record(Instant::now(), consecutive_failures) {
+                    error!("Giving up on the application: {reason}");
+                    return Ok(restart::RESTART_LIMIT_EXIT_CODE);
+                }
+                restart_after = next_restart;
+                restart_count += 1;
+                if !reported_running {
+                    let delay = next_restart.map_or(Duration::ZERO, |at| {
+                        at.saturating_duration_since(Instant::now())
+                    });
+                    report_start_progress(
+                        reporter,
+                        &mut start_checkpoint,
+                        delay + Duration::from_secs(10),
+                    );
+                }
+            }
+            LoopControl::Exit(exit_code) => return Ok(exit_code),
+            LoopControl::Idle(exit_code) => {
+                info!("AppExitAction=Ignore: service stays running until stopped");
+                loop {
+                    match events.next(None) {
+                        Some(
+                            Event::ChildExited { .. }
+                            | Event::Ready { .. }
+                            | Event::Unhealthy { .. }
+                            | Event::Notify { .. },
+                        ) => continue,
+                        Some(Event::Stop) | None => return Ok(exit_code),
+                    }
+                }
+            }
+            LoopControl::Suicide(exit_code) => {
+                error!(
+                    "AppExit=Suicide: exiting without stopping the service so the SCM runs \
+                     its recovery actions"
+                );
+                log::logger().flush();
+                std::process::exit(exit_code);
+            }
+        }
+    }
+}
+
+/// Waits out a restart delay; returns whether a stop was requested
+/// meanwhile.
+fn wait_for_restart_delay(events: &Events, restart_after: Option<Instant>) -> bool {
+    let Some(deadline) = restart_after else {
+        return false;
+    };
+    loop {
+        match events.next(Some(deadline)) {
+            Some(Event::Stop) => return true,
+            // A late report from an earlier launch.
+            Some(
+                Event::ChildExited { .. }
+                | Event::Ready { .. }
+                | Event::Unhealthy { .. }
+                | Event::Notify { .. },
+            ) => continue,
+            None => return false,
+        }
+    }
+}
+
+/// Holds back the first launch until every AppStartCondition is met, so
+/// an application whose dependencies are not up yet does not crash-loop
+/// through its restart budget. The service stays START_PENDING meanwhile.
+fn wait_for_start_conditions(
+    reporter: &dyn StatusReporter,
+    events: &Events,
+    config: &ServiceConfig,
+    checkpoint: &mut u32,
+) -> StartConditions {
+    if config.app_start_condition.is_empty() {
+        return StartConditions::Met;
+    }
+    let deadline = (config.app_start_timeout > 0)
+        .then(|| Instant::now() + Duration::from_millis(u64::from(config.app_start_timeout)));
+    let working_dir = resolve_working_dir(config);
+    let mut waiting_for = None;
+
+    loop {
+        let unmet = config.app_start_condition.iter().find_map(|condition| {
+            health::check_start_condition(condition, &working_dir, START_CONDITION_POLL_INTERVAL)
+                .err()
+                .map(|reason| (condition, reason))
+        });
+        let Some((condition, reason)) = unmet else {
+            if waiting_for.is_some() {
+                info!("Start conditions met; launching the application");
+            }
+            return StartConditions::Met;
+        };
+        if waiting_for != Some(condition) {
+            info!(
+                "Waiting for start condition {} ({reason})",
+                condition.as_cli_value()
+            );
+            waiting_for = Some(condition);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            error!(
+                "Start condition {} not met after {}ms (AppStartTimeout): {reason}",
+                condition.as_cli_value(),
+                config.app_start_timeout
+            );
+            return StartConditions::TimedOut;
+        }
+        report_start_progress(
+            reporter,
+            checkpoint,
+            START_CONDITION_POLL_INTERVAL + Duration::from_secs(10),
+        );
+
+        let next_check = Instant::now() + START_CONDITION_POLL_INTERVAL;
+        let wake = deadline.map_or(next_check, |deadline| deadline.min(next_check));
+        while let Some(event) = events.next(Some(wake)) {
+            if let Event::Stop = event {
+                info!("Stop requested while waiting for start conditions");
+                return StartConditions::Stopped;
+            }
+        }
+    }
+}
+
+/// Keeps the service START_PENDING until AppReadyCheck is met: `tcp://`
+/// and `file:` checks are tried every READY_POLL_INTERVAL, a `stdout:`
+/// check waits for the output pump's `Event::Ready` and a `notify` check
+/// for `READY=1`.
+fn wait_until_ready(
+    reporter: &dyn StatusReporter,
+    events: &Events,
+    config: &ServiceConfig,
+    running: &mut RunningChild,
+    deadline: Option<Instant>,
+    checkpoint: &mut u32,
+) -> Readiness {
+    let Some(check) = &config.app_ready_check else {
+        return Readiness::Ready;
+    };
+    info!(
+        "Waiting for the application to be ready ({})",
+        check.as_cli_value()
+    );
+    let working_dir = resolve_working_dir(config);
+
+    loop {
+        if health::is_ready(check, &working_dir, READY_POLL_INTERVAL) {
+            info!("Application is ready");
+            return Readiness::Ready;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Readiness::TimedOut;
+        }
+        report_start_progress(
+            reporter,
+            checkpoint,
+            READY_POLL_INTERVAL + Duration::from_secs(10),
+        );
+
+        let next_poll = Instant::now() + READY_POLL_INTERVAL;
+        let wake = deadline.map_or(next_poll, |deadline| deadline.min(next_poll));
+        while let Some(event) = events.next(Some(wake)) {
+            match event {
+                Event::Ready { generation } if generation == running.generation => {
+                    info!("Application is ready");
+                    return Readiness::Ready;
+                }
+                Event::Notify {
+                    generation,
+                    notification,
+                } if generation == running.generation => {
+                    let update = handle_notification(running, notification);
+                    if update == Update::Ready && *check == ReadyCheck::Notify {
+                        info!("Application is ready");
+                        return Readiness::Ready;
+                    }
+                }
+                Event::Stop => {
+                    events.deferred_stop.set(true);
+                    return Readiness::Interrupted;
+                }
+                Event::ChildExited { generation, .. } | Event::Unhealthy { generation, .. }
+                    if generation == running.generation =>
+                {
+                    let _ = events.sender.send(event);
+                    return Readiness::Interrupted;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn launch_child(
+    config: &ServiceConfig,
+    events: &Events,
+    generation: u32,
+    status_path: &Path,
+) -> Result<RunningChild, LaunchError> {
+    let mut command = build_command(config)?;
+    let notify = if config.uses_notify() {
+        let channel = NotifyChannel::open(
+            config,
+            status_path.to_path_buf(),
+            events.sender(),
+            generation,
+        )
+        .map_err(|error| {
+            LaunchError::other(AppError::Message(format!(
+                "Failed to open the notify socket: {error}"
+            )))
+        })?;
+        command.env(notify::NOTIFY_SOCKET_VAR, channel.address());
+        if config.app_watchdog_timeout > 0 {
+            command.env(
+                notify::WATCHDOG_USEC_VAR,
+                (u64::from(config.app_watchdog_timeout) * 1000).to_string(),
+            );
+        }
+        Some(channel)
+    } else {
+        None
+    };
+    let mut child = command.spawn().map_err(LaunchError::spawn)?;
+    info!("Application launched with PID: {}", child.id());
+
+    let (guard, waiter) = process::prepare_child(&mut child, config).map_err(LaunchError::other)?;
+    let sender = events.sender();
+    thread::spawn(move || {
+        let result = waiter.wait();
+        let _ = sender.send(Event::ChildExited { generation, result });
+    });
+
+    let (stdout_target, stderr_target) = open_output_targets(config);
+    let line_prefix = LinePrefix::from_config(config);
+    let activity = OutputActivity::new();
+    let stdout_thread = spawn_output_thread(
+        child.stdout.take(),
+        stdout_target,
+        false,
+        line_prefix.clone(),
+        activity.clone(),
+        ready_line_matcher(config, events, generation),
+    );
+    let stderr_thread = spawn_output_thread(
+        child.stderr.take(),
+        stderr_target,
+        true,
+        line_prefix,
+        activity.clone(),
+        None,
+    );
+    let health = HealthMonitor::start(
+        config,
+        &resolve_working_dir(config),
+        events.sender(),
+        generation,
+    );
+
+    Ok(RunningChild {
+        child,
+        generation,
+        guard,
+        _health: health,
+        notify,
+        activity,
+        stdout_thread,
+        stderr_thread,
+    })
+}
+
+/// For a `stdout:` AppReadyCheck, watches stdout for the line and reports
+/// it as `Event::Ready`.
+fn ready_line_matcher(
+    config: &ServiceConfig,
+    events: &Events,
+    generation: u32,
+) -> Option<LineMatcher> {
+    let Some(ReadyCheck::Stdout(pattern)) = &config.app_ready_check else {
+        return None;
+    };
+    let pattern = match regex::Regex::new(pattern) {
+        Ok(pattern) => pattern,
+        Err(error) => {
+            warn!("Invalid AppReadyCheck pattern '{pattern}': {error}");
+            return None;
+        }
+    };
+    let sender = events.sender();
+    Some(LineMatcher::new(pattern, move || {
+        let _ = sender.send(Event::Ready { generation });
+    }))
+}
+
+fn build_command(config: &ServiceConfig) -> Result<Command, LaunchError> {
+    let mut command = Command::new(&config.application);
+    command.current_dir(resolve_working_dir(config));
+    process::platform().configure(&mut command);
+
+    if let Some(parameters) = &config.app_parameters {
+        command.args(crate::cmdline::parse_command_line(parameters));
+    }
+
+    configure_stdio(&mut command, config)?;
+
+    // AppEnvironment replaces the inherited environment; AppEnvironmentExtra
+    // is layered on top of whichever environment results.
+    if !config.app_environment.is_empty() {
+        command.env_clear();
+    }
+    for env_var in config
+        .app_environment
+        .iter()
+        .chain(&config.app_environment_extra)
+    {
+        if let Some((key, value)) = env_var.split_once('=') {
+            command.env(key, value);
+        }
+    }
+
+    Ok(command)
+}
+
+fn resolve_working_dir(config: &ServiceConfig) -> PathBuf {
+    config.app_directory.as_ref().cloned().unwrap_or_else(|| {
+        config
+            .application
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf()
+    })
+}
+
+/// Without redirection the application inherits the supervisor's output:
+/// a hidden console under the SCM, the terminal when run in the foreground.
+/// AppOutputTimeout and a `stdout:` AppReadyCheck need to see the output,
+/// so it is then piped to the runtime log instead.
+fn configure_stdio(command: &mut Command, config: &ServiceConfig) -> Result<(), LaunchError> {
+    if let Some(path) = &config.app_stdin {
+        let file = std::fs::File::open(path).map_err(|error| LaunchError::stdin(path, error))?;
+        command.stdin(Stdio::from(file));
+    }
+
+    let watched = config.app_output_timeout > 0
+        || matches!(config.app_ready_check, Some(ReadyCheck::Stdout(_)));
+    if config.app_stdout.is_some() || config.app_stderr.is_some() || watched {
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+    } else if config.app_no_console {
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+    }
+
+    Ok(())
+}
+
+/// Opens the AppStdout/AppStderr files, rotating them first if
+/// AppRotateFiles is set. Both streams share one writer when they point at
+/// the same file, so online rotation renames it exactly once.
+fn open_output_targets(config: &ServiceConfig) -> (OutputTarget, OutputTarget) {
+    let policy = RotationPolicy::from_config(config);
+    let open = |path: &Path, stream_name: &str| match OutputFile::open(path, policy) {
+        Ok(file) => OutputTarget::File(Arc::new(Mutex::new(file))),
+        Err(error) => {
+            error!(
+                "Failed to open {stream_name} redirection file {path:?}: {error}; output will be discarded"
+            );
+            OutputTarget::Discard
+        }
+    };
+
+    let stdout = match &config.app_stdout {
+        Some(path) => open(path, "stdout"),
+        None => OutputTarget::Log,
+    };
+    let stderr = match (&config.app_stderr, &config.app_stdout, &stdout) {
+        (Some(stderr_path), Some(stdout_path), OutputTarget::File(file))
+            if same_file_path(stderr_path, stdout_path) =>
+        {
+            OutputTarget::File(file.clone())
+        }
+        (Some(path), _, _) => open(path, "stderr"),
+        (None, _, _) => OutputTarget::Log,
+    };
+    (stdout, stderr)
+}
+
+fn spawn_output_thread<T>(
+    stream: Option<T>,
+    target: OutputTarget,
+    is_stderr: bool,
+    line_prefix: Option<LinePrefix>,
+    activity: OutputActivity,
+    matcher: Option<LineMatcher>,
+) -> Option<thread::JoinHandle<()>>
+where
+    T: std::io::Read + Send + 'static,
+{
+    stream.map(|stream| {
+        thread::spawn(move || {
+            pump_output(stream, target, is_stderr, line_prefix, activity, matcher)
+        })
+    })
+}
+
+fn monitor_child(
+    reporter: &dyn StatusReporter,
+    events: &Events,
+    config: &ServiceConfig,
+    running: &mut RunningChild,
+    consecutive_failures: &mut u32,
+) -> LoopControl {
+    let start_time = Instant::now();
+    let output_timeout = Duration::from_millis(u64::from(config.app_output_timeout));
+
+    let exit = loop {
+        let silence_deadline =
+            (!output_timeout.is_zero()).then(|| running.activity.latest() + output_timeout);
+        let watchdog_deadline = running
+            .notify
+            .as_ref()
+            .and_then(|channel| channel.state.watchdog_deadline());
+        let deadline = silence_deadline.into_iter().chain(watchdog_deadline).min();
+        match events.next(deadline) {
+            Some(Event::ChildExited { generation, result }) if generation == running.generation => {
+                match (check_process_status(running), result) {
+                    (ProcessStatus::Exited(exit), _) => break Ok(exit),
+                    (ProcessStatus::Unknown(error), _) | (ProcessStatus::Running, Err(error)) => {
+                        break Err(error);
+                    }
+                    (ProcessStatus::Running, Ok(())) => continue,
+                }
+            }
+            Some(Event::Unhealthy { generation, reason }) if generation == running.generation => {
+                error!("Application is unhealthy ({reason}); restarting it");
+                return restart_hung_child(
+                    events,
+                    running,
+                    config,
+                    start_time,
+                    consecutive_failures,
+                );
+            }
+            Some(Event::Notify {
+                generation,
+                notification,
+            }) if generation == running.generation => {
+                handle_notification(running, notification);
+            }
+            Some(
+                Event::ChildExited { .. }
+                | Event::Ready { .. }
+                | Event::Unhealthy { .. }
+                | Event::Notify { .. },
+            ) => continue,
+            None if deadline.is_some() => {
+                // Output or a ping may have arrived just before the
+                // deadline.
+                let silent_for = running.activity.latest().elapsed();
+                if silence_deadline.is_some() && silent_for >= output_timeout {
+                    error!(
+                        "Application has written no output for {silent_for:?} \
+                         (AppOutputTimeout); restarting it"
+                    );
+                } else if watchdog_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    error!(
+                        "Application sent no WATCHDOG=1 for {}ms (AppWatchdogTimeout); \
+                         restarting it",
+                        config.app_watchdog_timeout
+                    );
+                } else {
+                    continue;
+                }
+                return restart_hung_child(
+                    events,
+                    running,
+                    config,
+                    start_time,
+                    consecutive_failures,
+                );
+            }
+
+            Some(Event::Stop) | None => {
+                info!("Shutting down service");
+                report_stop_progress(reporter, &mut 0, stop_wait_hint(config));
+                stop_child_process(Some(reporter), events, running, config);
+                return LoopControl::Exit(0);
+            }
+        }
+    };
+
+    match exit {
+        Ok(exit) => {
+            let runtime = start_time.elapsed();
+            info!("Application {exit} after {runtime:?}");
+            let exit_code = exit.code();
+
+            match config.exit_action(exit_code as u32) {
+                ExitAction::Restart => LoopControl::Restart(calculate_restart_delay(
+                    config,
+                    runtime,
+                    consecutive_failures,
+                )),
+                ExitAction::Ignore => LoopControl::Idle(exit_code as u32),
+                ExitAction::Exit => LoopControl::Exit(exit_code as u32),
+                ExitAction::Suicide => LoopControl::Suicide(exit_code),
+            }
+        }
+        Err(error) => {
+            error!("Failed to query child process status: {error}");
+            report_stop_progress(reporter, &mut 0, stop_wait_hint(config));
+            stop_child_process(Some(reporter), events, running, config);
+            LoopControl::Exit(1)
+        }
+    }
+}
+
+/// Applies a notification from the current launch, logging what changed.
+fn handle_notification(running: &mut RunningChild, notification: Notification) -> Update {
+    let Some(channel) = running.notify.as_mut() else {
+        return Update::Unchanged;
+    };
+    let update = channel.apply(notification);
+    match &update {
+        Update::Status(status) => info!("Application status: {status}"),
+        Update::Stopping => info!("Application reports that it is stopping"),
+        Update::Ready | Update::Unchanged => {}
+    }
+    update
+}
+
+/// Stops an application that is running but not working and restarts it
+/// like one that failed. The service itself keeps running, so the stop
+/// sequence is not reported as STOP_PENDING. An application that survives
+/// it (e.g. with the forced kill skipped) is killed with its process tree
+/// by `finalize_child_threads` before anything waits on its output.
+fn restart_hung_child(
+    events: &Events,
+    running: &mut RunningChild,
+    config: &ServiceConfig,
+    start_time: Instant,
+    consecutive_failures: &mut u32,
+) -> LoopControl {
+    stop_child_process(None, events, running, config);
+    if process_running(running) {
+        warn!("Killing the hung application before restarting it");
+    }
+    LoopControl::Restart(calculate_restart_delay(
+        config,
+        start_time.elapsed(),
+        consecutive_failures,
+    ))
+}
+
+fn calculate_restart_delay(
+    config: &ServiceConfig,
+    runtime: Duration,
+    consecutive_failures: &mut u32,
+) -> Option<Instant> {
+    let delay = restart::restart_delay_duration(
+        config,
+        runtime,
+        consecutive_failures,
+        restart::random_fraction(),
+    );
+    (delay.as_millis() > 0).then(|| Instant::now() + delay)
+}
+
+/// Kills what is left of the application's process tree, then waits for
+/// the output pumps. The tree has to go first: a lingering grandchild
+/// (e.g. `sh -c "helper & exec app"`) or an application that survived the
+/// stop sequence still holds the pipes, so the pumps would never finish.
+fn finalize_child_threads(running_child: RunningChild) {
+    let RunningChild {
+        mut child,
+        guard,
+        stdout_thread,
+        stderr_thread,
+        ..
+    } = running_child;
+    drop(guard);
+    // Reap an application that was only just killed.
+    let _ = child.wait();
+    if let Some(thread) = stdout_thread {
+        let _ = thread.join();
+    }
+    if let Some(thread) = stderr_thread {
+        let _ = thread.join();
+    }
+}
+
+/// Reaps an application that has exited, through its guard so that the
+/// rest of its process tree is killed first.
+fn check_process_status(running: &mut RunningChild) -> ProcessStatus {
+    match running.guard.try_wait(&mut running.child) {
+        Ok(None) => ProcessStatus::Running,
+        Ok(Some(status)) => ProcessStatus::Exited(process::platform().exit_of(status)),
+        Err(error) => ProcessStatus::Unknown(error),
+    }
+}
+
+/// Total stop budget reported to the SCM, covering every enabled stop
+/// method.
+fn stop_wait_hint(config: &ServiceConfig) -> Duration {
+    let control = process::platform();
+    StopMethod::ALL
+        .into_iter()
+        .filter(|&method| !method.skipped(config) && control.supports(method, config))
+        .map(|method| method.timeout(config))
+        .sum::<Duration>()
+        + Duration::from_secs(2)
+}
+
+fn report_start_progress(reporter: &dyn StatusReporter, checkpoint: &mut u32, wait_hint: Duration) {
+    *checkpoint += 1;
+    if let Err(error) = reporter.start_pending(*checkpoint, wait_hint, true) {
+        warn!("Failed to report START_PENDING: {error}");
+    }
+}
+
+fn report_stop_progress(reporter: &dyn StatusReporter, checkpoint: &mut u32, wait_hint: Duration) {
+    if let Err(error) = reporter.stop_pending(*checkpoint, wait_hint) {
+        warn!("Failed to report stop progress: {error}");
+    }
+    *checkpoint += 1;
+}
+
+fn process_running(running: &mut RunningChild) -> bool {
+    matches!(check_process_status(running), ProcessStatus::Running)
+}
+
+/// Escalating stop sequence: Ctrl-C, WM_CLOSE, WM_QUIT, TerminateProcess
+/// on Windows; SIGINT, SIGTERM, SIGHUP, SIGKILL to the process group
+/// elsewhere.
+///
+/// Each step is skipped if the corresponding AppStopMethodSkip bit is set,
+/// the process has already exited or the platform cannot deliver it.
+/// Progress is reported to `reporter`, if given, as STOP_PENDING. All
+/// signalling is done while the child handle is held, so the PID cannot be
+/// recycled by another process mid-sequence.
+fn stop_child_process(
+    reporter: Option<&dyn StatusReporter>,
+    events: &Events,
+    running: &mut RunningChild,
+    config: &ServiceConfig,
+) {
+    let control = process::platform();
+    let child_id = running.child.id();
+    info!("Stopping child process with PID: {child_id}");
+    let wait_hint = stop_wait_hint(config);
+    let mut checkpoint = 1u32;
+
+    for method in StopMethod::ALL {
+        if !process_running(running) {
+            info!("Child process already exited");
+            return;
+        }
+        if method.skipped(config) || !control.supports(method, config) {
+            continue;
+        }
+
+        if let Some(reporter) = reporter {
+            report_stop_progress(reporter, &mut checkpoint, wait_hint);
+        }
+        let name = control.describe(method);
+        info!("Sending {name} to child process");
+        if control.send_stop(&mut running.child, method)
+            && wait_for_process_exit(events, running, method.timeout(config))
+        {
+            info!("Child process stopped after {name}");
+            return;
+        }
+    }
+
+    if process_running(running) {
+        warn!(
+            "Child process {child_id} is still running after the stop sequence; \
+             it will be killed along with its process tree"
+        );
+    }
+}
+
+/// Waits up to `timeout` for the application to exit. A stop requested
+/// meanwhile is kept for after the stop sequence, which then either ends
+/// the service anyway or was restarting the application.
+fn wait_for_process_exit(events: &Events, running: &mut RunningChild, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !process_running(running) {
+            return true;
+        }
+        match events.receive(Some(deadline)) {
+            Some(Event::Stop) => events.deferred_stop.set(true),
+            Some(_) => {}
+            None => return !process_running(running),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StartCondition;
+    use std::ffi::OsStr;
+
+    fn test_config() -> ServiceConfig {
+        ServiceConfig {
+            app_throttle: 1500,
+            app_restart_delay: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn environment_extra_is_layered_over_replaced_environment() {
+        let config = ServiceConfig {
+            application: PathBuf::from(r"C:\app.exe"),
+            app_environment: vec!["PATH=C:\\bin".to_string(), "MODE=base".to_string()],
+            app_environment_extra: vec!["MODE=extra".to_string()],
+            ..Default::default()
+        };
+        let command = build_command(&config).unwrap();
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("PATH"), Some(OsStr::new("C:\\bin")))));
+        assert!(envs.contains(&(OsStr::new("MODE"), Some(OsStr::new("extra")))));
+    }
+
+    #[test]
+    fn zero_restart_delay_means_immediate_restart() {
+        let config = test_config();
+        let mut failures = 0;
+        assert!(calculate_restart_delay(&config, Duration::from_secs(60), &mut failures).is_none());
+    }
+
+    #[derive(Default)]
+    struct RecordingReporter {
+        events: Mutex<Vec<&'static str>>,
+    }
+
+    impl StatusReporter for RecordingReporter {
+        fn start_pending(&self, _: u32, _: Duration, _: bool) -> AppResult<()> {
+            self.events.lock().unwrap().push("start_pending");
+            Ok(())
+        }
+
+        fn running(&self) -> AppResult<()> {
+            self.events.lock().unwrap().push("running");
+            Ok(())
+        }
+
+        fn stop_pending(&self, _: u32, _: Duration) -> AppResult<()> {
+            self.events.lock().unwrap().push("stop_pending");
+            Ok(())
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn application_exit_ends_the_loop_with_its_exit_code() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"exit 3\"".to_string()),
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, 3);
+        assert_eq!(*reporter.events.lock().unwrap(), vec!["running"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lingering_grandchildren_do_not_hold_up_the_exit() {
+        let dir = crate::config_store::tests::TempDir::new("grandchild");
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            // The backgrounded sleep keeps the redirected stdout open.
+            app_parameters: Some("-c \"sleep 30 & exit 3\"".to_string()),
+            app_stdout: Some(dir.0.join("out.log")),
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_stops_the_application() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sleep"),
+            app_parameters: Some("30".to_string()),
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+        events.sender().send(Event::Stop).unwrap();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, 0);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let events = reporter.events.lock().unwrap();
+        assert_eq!(events.first(), Some(&"running"));
+        assert!(events.contains(&"stop_pending"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exits_are_handled_as_they_happen() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"sleep 0.1; exit 3\"".to_string()),
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+        // Left over from an earlier launch; must not be taken for this one.
+        events
+            .sender()
+            .send(Event::ChildExited {
+                generation: 0,
+                result: Ok(()),
+            })
+            .unwrap();
+
+        let started = Instant::now();
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+        assert!(started.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn failed_launches_report_start_pending_then_give_up() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/nonexistent/nssm-rs-test-app"),
+            app_launch_attempts: 2,
+            app_restart_backoff_base: 10,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::LAUNCH_FAILED_EXIT_CODE);
+        assert_eq!(*reporter.events.lock().unwrap(), vec!["start_pending"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignored_sigint_escalates_to_sigterm() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"trap '' INT; exec sleep 30\"".to_string()),
+            app_stop_method_console: 200,
+            ..test_config()
+        };
+        let events = Events::new();
+        let mut running = launch_child(&config, &events, 1, Path::new("test.status")).unwrap();
+        // Give the shell time to install the trap before the stop sequence.
+        thread::sleep(Duration::from_millis(200));
+
+        stop_child_process(
+            Some(&RecordingReporter::default()),
+            &events,
+            &mut running,
+            &config,
+        );
+        let status = running.child.try_wait().unwrap().expect("child stopped");
+        assert_eq!(
+            process::platform().exit_of(status),
+            ChildExit::Signal(libc::SIGTERM)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unhealthy_applications_are_stopped_and_restarted() {
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sleep"),
+            app_parameters: Some("30".to_string()),
+            app_health_check: crate::config::HealthCheck::parse(&format!(
+                "tcp://127.0.0.1:{closed_port}"
+            ))
+            .unwrap(),
+            app_health_check_interval: 20,
+            app_health_check_threshold: 2,
+            app_restart_limit: 1,
+            app_restart_backoff_base: 10,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::RESTART_LIMIT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        // Restarting the application is not a service stop.
+        assert_eq!(*reporter.events.lock().unwrap(), vec!["running"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn silent_applications_are_restarted_after_the_output_timeout() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"echo starting; exec sleep 30\"".to_string()),
+            app_output_timeout: 200,
+            app_restart_limit: 1,
+            app_restart_backoff_base: 10,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::RESTART_LIMIT_EXIT_CODE);
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(*reporter.events.lock().unwrap(), vec!["running"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_applications_that_survive_the_stop_sequence_are_killed() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some(
+                "-c \"trap '' INT TERM HUP; echo starting; while :; do sleep 1; done\"".to_string(),
+            ),
+            app_output_timeout: 200,
+            // Skip the forced kill, so nothing in the stop sequence ends it.
+            app_stop_method_skip: 8,
+            app_stop_method_console: 100,
+            app_stop_method_window: 100,
+            app_stop_method_threads: 100,
+            app_restart_limit: 1,
+            app_restart_backoff_base: 10,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::RESTART_LIMIT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn steady_output_keeps_the_watchdog_quiet() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some(
+                "-c \"for i in 1 2 3 4 5 6; do echo tick; sleep 0.1; done; exit 3\"".to_string(),
+            ),
+            app_output_timeout: 300,
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn running_is_reported_once_the_ready_line_is_written() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some(
+                "-c \"echo starting; sleep 0.6; echo Listening on 8080; sleep 0.2; exit 3\""
+                    .to_string(),
+            ),
+            app_ready_check: ReadyCheck::parse("stdout:^Listening on \\d+$").unwrap(),
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+        let events = reporter.events.lock().unwrap();
+        // START_PENDING progress is reported while the application warms up.
+        assert_eq!(events.first(), Some(&"start_pending"));
+        assert_eq!(events.last(), Some(&"running"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn start_fails_when_the_application_is_not_ready_in_time() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sleep"),
+            app_parameters: Some("30".to_string()),
+            app_ready_check: ReadyCheck::parse("file:/nonexistent/nssm-rs-ready").unwrap(),
+            app_ready_timeout: 300,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::READY_TIMEOUT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let events = reporter.events.lock().unwrap();
+        assert!(!events.contains(&"running"));
+        assert!(events.contains(&"stop_pending"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn first_launch_waits_for_start_conditions() {
+        let dir = crate::config_store::tests::TempDir::new("start-condition");
+        let directory = dir.0.join("share");
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"exit 3\"".to_string()),
+            app_start_condition: vec![StartCondition::Path(directory.clone())],
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let share = {
+            let directory = directory.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                std::fs::create_dir_all(&directory).unwrap();
+            })
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        share.join().unwrap();
+        assert_eq!(
+            *reporter.events.lock().unwrap(),
+            vec!["start_pending", "running"]
+        );
+    }
+
+    #[test]
+    fn start_fails_when_start_conditions_are_not_met_in_time() {
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = ServiceConfig {
+            // Never launched.
+            application: PathBuf::from("/nonexistent/nssm-rs-test-app"),
+            app_start_condition: vec![StartCondition::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: closed_port,
+            }],
+            app_start_timeout: 300,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::START_CONDITION_EXIT_CODE);
+        assert!(
+            reporter
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|event| *event == "start_pending")
+        );
+
+        // A stop while waiting ends the service without launching.
+        let config = ServiceConfig {
+            app_start_timeout: 0,
+            ..config
+        };
+        events.sender().send(Event::Stop).unwrap();
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 0);
+    }
+
+    /// Stands in for an sd_notify client: waits for the application to
+    /// write its NOTIFY_SOCKET to `address_file`, then sends `message`.
+    #[cfg(unix)]
+    fn notify_from_test(address_file: PathBuf, message: &'static str) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let address = loop {
+                match std::fs::read_to_string(&address_file) {
+                    Ok(address) if address.ends_with('\n') => break address,
+                    _ if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+                    _ => panic!("the application did not publish NOTIFY_SOCKET"),
+                }
+            };
+            std::os::unix::net::UnixDatagram::unbound()
+                .unwrap()
+                .send_to(message.as_bytes(), address.trim_end())
+                .unwrap();
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notify_ready_reports_running_and_status_is_published() {
+        let dir = crate::config_store::tests::TempDir::new("supervisor-notify");
+        let directory = dir.0.clone();
+        let address_file = directory.join("address");
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some(format!(
+                "-c \"echo $NOTIFY_SOCKET > {}; sleep 0.5; exit 3\"",
+                address_file.display()
+            )),
+            app_ready_check: Some(ReadyCheck::Notify),
+            app_log_directory: Some(directory.clone()),
+            app_exit_default: ExitAction::Exit,
+            ..test_config()
+        };
+        let status_path = notify::status_path("test", &config);
+        let client = notify_from_test(address_file, "STATUS=Serving requests\nREADY=1");
+        let watcher = {
+            let status_path = status_path.clone();
+            thread::spawn(move || {
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while Instant::now() < deadline {
+                    if let Some(status) = notify::read_status(&status_path) {
+                        return Some(status);
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                None
+            })
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 3);
+        client.join().unwrap();
+        assert_eq!(watcher.join().unwrap().as_deref(), Some("Serving requests"));
+        // The status goes with the application.
+        assert!(notify::read_status(&status_path).is_none());
+        assert_eq!(reporter.events.lock().unwrap().last(), Some(&"running"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missed_watchdog_pings_restart_the_application() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            // Exits (ending the run with 1) if WATCHDOG_USEC is not exported.
+            app_parameters: Some(
+                "-c \"test -n \\\"$WATCHDOG_USEC\\\" && exec sleep 30\"".to_string(),
+            ),
+            app_watchdog_timeout: 200,
+            app_exit_default: ExitAction::Exit,
+            app_restart_limit: 1,
+            app_restart_backoff_base: 10,
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        let started = Instant::now();
+        let exit_code = run("test", &config, &reporter, &events).unwrap();
+        assert_eq!(exit_code, restart::RESTART_LIMIT_EXIT_CODE);
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(*reporter.events.lock().unwrap(), vec!["running"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_exits_select_exit_actions_by_128_plus_signal() {
+        let config = ServiceConfig {
+            application: PathBuf::from("/bin/sh"),
+            app_parameters: Some("-c \"kill -TERM $$\"".to_string()),
+            app_exit: [(143, ExitAction::Exit)].into(),
+            ..test_config()
+        };
+        let reporter = RecordingReporter::default();
+        let events = Events::new();
+
+        assert_eq!(run("test", &config, &reporter, &events).unwrap(), 143);
+    }
+}